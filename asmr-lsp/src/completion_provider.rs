@@ -6,27 +6,29 @@ use strum::IntoEnumIterator;
 
 use assembl_really as asmr;
 use asmr::core::register::RegisterName;
-use asmr::parser::{line::Line, instruction::Instruction};
+use asmr::parser::{line::Line, instruction::Instruction, prefix::Prefix};
 
 /// Parses an asmr file into an array of completion items for intellisense.
 /// Returns a JSON encoded `CompletionItem[]`.
 #[wasm_bindgen(js_name = getCompletionItems)]
 pub fn get_completion_items(file_contents: &str) -> String {
-    let lines = asmr::parse_lines(file_contents.split('\n')).unwrap_throw();
+    let lines = asmr::parse_lines_recovering(file_contents.split('\n')).0;
     let mut completion_items: Vec<CompletionItem> = Vec::new();
 
     for i in 0..lines.len() {
         match lines.get(i) {
             Some(Line::Label(label)) => {
                 completion_items.push(CompletionItem {
-                    token_name: label.clone(),
+                    token_name: label.node.clone(),
                     token_type: CompletionType::Label,
+                    documentation: None,
                 });
             },
-            Some(Line::Variable { identifier, .. }) => {
+            Some(Line::Variable { identifier, .. } | Line::Equ { identifier, .. }) => {
                 completion_items.push(CompletionItem {
-                    token_name: identifier.clone(),
+                    token_name: identifier.node.clone(),
                     token_type: CompletionType::Variable,
+                    documentation: None,
                 });
             },
             _ => {}, // No completion information
@@ -37,10 +39,12 @@ pub fn get_completion_items(file_contents: &str) -> String {
     completion_items.push(CompletionItem {
         token_name: "asmr::io::print".to_string(),
         token_type: CompletionType::Function,
+        documentation: None,
     });
     completion_items.push(CompletionItem {
         token_name: "asmr::io::readln".to_string(),
         token_type: CompletionType::Function,
+        documentation: None,
     });
 
     // Register registers
@@ -48,14 +52,25 @@ pub fn get_completion_items(file_contents: &str) -> String {
         completion_items.push(CompletionItem {
             token_name: r.to_string(),
             token_type: CompletionType::Register,
+            documentation: None,
         });
     });
 
-    // Register instructions
+    // Register instructions, documented with their operand forms
     Instruction::iter().for_each(|r| {
         completion_items.push(CompletionItem {
             token_name: r.to_string(),
             token_type: CompletionType::Instruction,
+            documentation: Some(r.describe_signatures()),
+        });
+    });
+
+    // Register prefixes
+    Prefix::iter().for_each(|p| {
+        completion_items.push(CompletionItem {
+            token_name: p.to_string(),
+            token_type: CompletionType::Instruction,
+            documentation: None,
         });
     });
 
@@ -70,6 +85,8 @@ pub struct CompletionItem {
     #[wasm_bindgen(skip)]
     pub token_name: String,
     pub token_type: CompletionType,
+    #[wasm_bindgen(skip)]
+    pub documentation: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -78,6 +95,11 @@ impl CompletionItem {
     pub fn token_name(&self) -> String {
         self.token_name.clone()
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn documentation(&self) -> Option<String> {
+        self.documentation.clone()
+    }
 }
 
 /// The types of asmr completion items
@@ -0,0 +1,53 @@
+use wasm_bindgen::prelude::*;
+use serde_json as json;
+use serde::Serialize;
+
+use assembl_really as asmr;
+
+use crate::document_symbol_provider::Range;
+
+/// Preprocesses and parses an asmr file into an array of diagnostics, one for every error in the file.
+/// Returns a JSON encoded `Diagnostic[]`.
+#[wasm_bindgen(js_name = getDiagnostics)]
+pub fn get_diagnostics(file_contents: &str) -> String {
+    // Directives that fail to expand are skipped, so the rest of the lines are still parsed
+    let (preprocessed, mut errors) = asmr::preprocess_recovering(file_contents.split('\n'));
+    errors.extend(asmr::parse_lines_recovering(preprocessed.lines.iter()).1.into_iter().map(|e| preprocessed.map_parser_error(e)));
+
+    // Errors located in included files have no range in this document
+    let diagnostics: Vec<Diagnostic> = errors.into_iter()
+        .filter(|e| e.file.is_none())
+        .map(|e| {
+            let line = e.line_number as usize - 1;
+            Diagnostic {
+                message: e.cause,
+                range: Range {
+                    line_start: line,
+                    char_start: e.span.column,
+                    line_end: line,
+                    char_end: e.span.column_end,
+                },
+            }
+        })
+        .collect();
+
+    json::to_string(&diagnostics).unwrap_throw()
+}
+
+/// Contains an error message and the source range it applies to
+#[wasm_bindgen]
+#[derive(Serialize)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    #[wasm_bindgen(skip)]
+    pub message: String,
+    pub range: Range,
+}
+
+#[wasm_bindgen]
+impl Diagnostic {
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
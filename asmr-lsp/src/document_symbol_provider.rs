@@ -10,7 +10,7 @@ use asmr::parser::line::Line;
 #[wasm_bindgen(js_name = getDocumentSymbols)]
 pub fn get_document_symbols(file_contents: &str) -> String {
     let file_lines = file_contents.split('\n').collect::<Vec<_>>();
-    let lines = asmr::parse_lines(file_lines.iter()).unwrap_throw();
+    let lines = asmr::parse_lines_recovering(file_lines.iter()).0;
     let mut symbols: Vec<DocumentSymbol> = Vec::new();
     
     // Tracks the current label that symbols are under
@@ -28,17 +28,17 @@ pub fn get_document_symbols(file_contents: &str) -> String {
     });
 
     for i in 0..lines.len() {
-        if let Some(Line::Label(label)) = lines.get(i) {
+        if let Some(Line::Label(label) | Line::Proc { name: label, .. }) = lines.get(i) {
             // Push existing label if it exists
             if let Some(cur) = current_label {
                 push_symbol(cur.0, SymbolType::Label, cur.1, i - 1);
             }
 
             // Set current label
-            current_label = Some((label.clone(), i));
+            current_label = Some((label.node.clone(), i));
         }
-        else if let Some(Line::Variable { identifier, .. }) = lines.get(i) {
-            push_symbol(identifier.clone(), SymbolType::Variable, i, i);
+        else if let Some(Line::Variable { identifier, .. } | Line::Equ { identifier, .. }) = lines.get(i) {
+            push_symbol(identifier.node.clone(), SymbolType::Variable, i, i);
         }
     }
 
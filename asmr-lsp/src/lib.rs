@@ -1,3 +1,4 @@
 mod completion_provider;
 mod semantic_highlighter;
 mod document_symbol_provider;
+mod diagnostics_provider;
@@ -3,14 +3,13 @@ use serde_json as json;
 use serde::Serialize;
 
 use assembl_really as asmr;
-use asmr::parser::{line::Line, token::Token};
+use asmr::parser::{line::Line, span::Spanned, token::{Token, EffectiveAddress}};
 
 /// Parses an asmr file into an array of semantic tokens for semantic highlighting.
 /// Returns a JSON encoded `SemanticToken[]`.
 #[wasm_bindgen(js_name = parseFileTokens)]
 pub fn parse_file_tokens(file_contents: &str) -> String {
-    let file_lines = file_contents.split('\n').collect::<Vec<_>>();
-    let lines = asmr::parse_lines(file_lines.iter()).unwrap_throw();
+    let lines = asmr::parse_lines_recovering(file_contents.split('\n')).0;
     let mut tokens: Vec<SemanticToken> = Vec::new();
     
     // Track all labels for highlighting
@@ -22,33 +21,27 @@ pub fn parse_file_tokens(file_contents: &str) -> String {
     }
     
     for i in 0..lines.len() {
-        let line = file_lines.get(i).unwrap_throw();
-
         match lines.get(i) {
             Some(Line::Instruction { params, .. }) => {
-                tokens.append(&mut parse_params(params, line, i, &identifiers));
+                tokens.append(&mut parse_params(params, i, &identifiers));
             },
             Some(Line::Label(label)) => {
-                tokens.push(SemanticToken {
-                    token_name: label.clone(),
-                    delta_line: i as u32,
-                    delta_start: line.match_indices(label).collect::<Vec<_>>().first().unwrap().0 as u32,
-                    length: label.len() as u32,
-                    token_type: SemanticTokenType::Variable as u32,
-                });
+                tokens.push(SemanticToken::from(label, i, SemanticTokenType::Variable));
                 identifiers.push(label);
             },
             Some(Line::Variable { identifier, params, .. }) => {
-                tokens.push(SemanticToken {
-                    token_name: identifier.clone(),
-                    delta_line: i as u32,
-                    delta_start: line.match_indices(identifier).collect::<Vec<_>>().first().unwrap().0 as u32,
-                    length: identifier.len() as u32,
-                    token_type: SemanticTokenType::Variable as u32,
-                });
+                tokens.push(SemanticToken::from(identifier, i, SemanticTokenType::Variable));
+                identifiers.push(identifier);
+
+                tokens.append(&mut parse_params(params, i, &identifiers));
+            },
+            Some(Line::Equ { identifier, value }) => {
+                tokens.push(SemanticToken::from(identifier, i, SemanticTokenType::Variable));
                 identifiers.push(identifier);
 
-                tokens.append(&mut parse_params(params, line, i, &identifiers));
+                tokens.extend(value.symbols().into_iter()
+                    .filter(|symbol| identifiers.contains(&&symbol.node))
+                    .map(|symbol| SemanticToken::from(symbol, i, SemanticTokenType::Variable)));
             },
             _ => {}, // No semantic information
         };
@@ -58,33 +51,30 @@ pub fn parse_file_tokens(file_contents: &str) -> String {
 }
 
 /// Parses the parameters for an instruction into semantic tokens.
-fn parse_params(params: &Vec<Token>, line: &str, line_idx: usize, identifiers: &Vec<&String>) -> Vec<SemanticToken> {
+fn parse_params(params: &[Spanned<Token>], line_idx: usize, identifiers: &[&String]) -> Vec<SemanticToken> {
     let mut tokens: Vec<SemanticToken> = Vec::new();
 
     for token in params {
-        match token {
+        match &token.node {
             Token::Identifier(identifier) => {
+                let identifier = Spanned::new(identifier.clone(), token.span);
                 if ["asmr::io::print", "asmr::io::readln"].contains(&identifier.as_str()) {
-                    tokens.push(SemanticToken {
-                        token_name: identifier.clone(),
-                        delta_line: line_idx as u32,
-                        delta_start: line.match_indices(identifier).collect::<Vec<_>>().first().unwrap().0 as u32,
-                        length: identifier.len() as u32,
-                        token_type: SemanticTokenType::Function as u32,
-                    });
+                    tokens.push(SemanticToken::from(&identifier, line_idx, SemanticTokenType::Function));
                     continue;
                 }
 
-                if identifiers.contains(&identifier) {
-                    tokens.push(SemanticToken {
-                        token_name: identifier.clone(),
-                        delta_line: line_idx as u32,
-                        delta_start: line.match_indices(identifier).collect::<Vec<_>>().first().unwrap().0 as u32,
-                        length: identifier.len() as u32,
-                        token_type: SemanticTokenType::Variable as u32,
-                    });
+                if identifiers.contains(&&identifier.node) {
+                    tokens.push(SemanticToken::from(&identifier, line_idx, SemanticTokenType::Variable));
                 }
             },
+            Token::Memory(EffectiveAddress { symbol: Some(identifier), .. }) if identifiers.contains(&&identifier.node) => {
+                tokens.push(SemanticToken::from(identifier, line_idx, SemanticTokenType::Variable));
+            },
+            Token::Expression(expression) => {
+                tokens.extend(expression.symbols().into_iter()
+                    .filter(|symbol| identifiers.contains(&&symbol.node))
+                    .map(|symbol| SemanticToken::from(symbol, line_idx, SemanticTokenType::Variable)));
+            },
             _ => {}, // No semantic information
         };
     }
@@ -105,6 +95,19 @@ pub struct SemanticToken {
     pub token_type: u32,
 }
 
+impl SemanticToken {
+    /// Creates the semantic token for an identifier, positioned by the span it was parsed from.
+    fn from(identifier: &Spanned<String>, line_idx: usize, token_type: SemanticTokenType) -> Self {
+        SemanticToken {
+            token_name: identifier.node.clone(),
+            delta_line: line_idx as u32,
+            delta_start: identifier.span.column as u32,
+            length: (identifier.span.column_end - identifier.span.column) as u32,
+            token_type: token_type as u32,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl SemanticToken {
     #[wasm_bindgen(getter)]
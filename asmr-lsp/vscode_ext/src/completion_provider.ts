@@ -8,6 +8,9 @@ export class AsmrCompletionItemProvider implements vscode.CompletionItemProvider
 
         tokens.forEach(token => {
             let completionItem = new vscode.CompletionItem(token.token_name);
+            if (token.documentation) {
+                completionItem.detail = token.documentation;
+            }
 
             switch (token.token_type) {
                 case asmr.CompletionType.Variable:
@@ -0,0 +1,27 @@
+import * as vscode from 'vscode';
+import * as asmr from './asmr/asmr_lsp';
+
+// Reports every parser error in an asmr document
+export class AsmrDiagnosticsProvider {
+    private readonly diagnostics = vscode.languages.createDiagnosticCollection('asmr');
+
+    register(context: vscode.ExtensionContext) {
+        context.subscriptions.push(this.diagnostics);
+        context.subscriptions.push(vscode.workspace.onDidOpenTextDocument(document => this.update(document)));
+        context.subscriptions.push(vscode.workspace.onDidChangeTextDocument(event => this.update(event.document)));
+        context.subscriptions.push(vscode.workspace.onDidCloseTextDocument(document => this.diagnostics.delete(document.uri)));
+        vscode.workspace.textDocuments.forEach(document => this.update(document));
+    }
+
+    update(document: vscode.TextDocument) {
+        if (document.languageId !== 'asmr') {
+            return;
+        }
+
+        const diagnostics: asmr.Diagnostic[] = JSON.parse(asmr.getDiagnostics(document.getText()));
+        this.diagnostics.set(document.uri, diagnostics.map(diagnostic => {
+            const range = new vscode.Range(diagnostic.range.line_start, diagnostic.range.char_start, diagnostic.range.line_end, diagnostic.range.char_end);
+            return new vscode.Diagnostic(range, diagnostic.message, vscode.DiagnosticSeverity.Error);
+        }));
+    }
+}
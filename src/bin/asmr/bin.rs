@@ -1,50 +1,72 @@
-use std::{io::{self, BufRead, Lines, BufReader}, path::Path, fs::File, env, process::ExitCode};
+use std::{io::{self, IsTerminal}, path::PathBuf, env, process::ExitCode};
 
 use assembl_really as asmr;
+use asmr::diagnostics::Diagnostic;
 
 fn main() -> ExitCode {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Error: An input file must be specified.");
-        return ExitCode::FAILURE;
-    }
+    // Get the input asmr file and the directories to search for included files
+    let (file_path, include_paths) = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!("Usage: asmr [-I <directory>]... <file>");
+            return ExitCode::FAILURE;
+        },
+    };
 
-    // Get the input asmr file
-    let file_path = &args[1];
+    // Read and expand preprocessor directives such as includes and macros, keeping the source of every file for diagnostics
+    let (preprocessed, errors) = match asmr::preprocess_file(&file_path, &include_paths) {
+        Ok(preprocessed) => preprocessed,
+        Err(_) => {
+            eprintln!("There was an error opening the file.");
+            return ExitCode::FAILURE;
+        },
+    };
 
-    // Read the file by line
-    let lines = read_lines(file_path.trim());
-    if let Err(e) = lines {
-        eprintln!("{}", e);
-        return ExitCode::FAILURE;
-    }
+    // Diagnostics are written to stderr, so only colour them when it is a terminal
+    let files: Vec<_> = preprocessed.files.iter().map(|f| (f.name().unwrap_or_default(), f.lines.as_slice())).collect();
+    let files: Vec<_> = files.iter().map(|(name, lines)| (name.as_str(), *lines)).collect();
+    let report = |diagnostic: Diagnostic| eprintln!("{}", diagnostic.render_files(&files, io::stderr().is_terminal()));
 
-    // Parse the file into an AST
-    let ast = asmr::parse_lines(lines.unwrap().map(|r| r.unwrap()));
-    if let Err(ref e) = ast {
-        eprintln!("{}", e);
+    // Parse the expanded lines even if preprocessing failed, reporting every error at once in source order
+    let (ast, parse_errors) = asmr::parse_lines_recovering(preprocessed.lines.iter());
+    let mut errors: Vec<_> = errors.into_iter().chain(parse_errors.into_iter().map(|e| preprocessed.map_parser_error(e))).collect();
+    if !errors.is_empty() {
+        let main_file = preprocessed.files[0].name();
+        errors.sort_by_key(|e| (e.file != main_file, e.file.clone(), e.line_number));
+        errors.iter().for_each(|e| report(Diagnostic::from(e)));
         return ExitCode::FAILURE;
     }
 
     // Execute the parsed AST
-    let exit_code = asmr::execute(ast.unwrap());
-    if let Err(ref e) = exit_code {
-        eprintln!("{}", e);
+    let exit_code = asmr::execute(ast);
+    if let Err(e) = exit_code {
+        report(Diagnostic::from(&preprocessed.map_runtime_error(e)));
         return ExitCode::FAILURE;
     }
 
     exit_code.unwrap()
 }
 
-/// Reads the specified file by line with a BufReader.
-fn read_lines<'a, P>(file_path: P) -> Result<Lines<BufReader<File>>, &'a str>
-where
-    P: AsRef<Path>
-{
-    let file = File::open(file_path);
-    if let Err(_) = file {
-        return Err("There was an error opening the file.");
+/// Parses the command line arguments into the input file and the include paths given with `-I`.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let mut file_path = None;
+    let mut include_paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            include_paths.push(args.next().ok_or("`-I` must be followed by a directory.")?.into());
+        }
+        else if let Some(path) = arg.strip_prefix("-I") {
+            include_paths.push(path.into());
+        }
+        else if file_path.is_none() {
+            file_path = Some(PathBuf::from(arg.trim()));
+        }
+        else {
+            return Err(format!("Unexpected argument `{arg}`. Only one input file can be specified."));
+        }
     }
 
-    Ok(io::BufReader::new(file.unwrap()).lines())
+    Ok((file_path.ok_or("An input file must be specified.")?, include_paths))
 }
@@ -1,4 +1,6 @@
 pub mod flags;
 pub mod register;
 pub mod executing_context;
+pub mod memory;
 pub mod builtins;
+pub mod alu;
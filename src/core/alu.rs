@@ -0,0 +1,660 @@
+//! Arithmetic and logic operations with IA-32 flag semantics.
+//!
+//! Every operation works on the low `size` bits of its operands, returns the result truncated to `size`,
+//! and updates the flags as described in the Intel SDM. Flags the SDM leaves undefined are computed from
+//! the result for SF, ZF and PF, and cleared for AF, so that programs behave deterministically.
+
+use super::{flags::{Flag, Flags}, memory::DataSize};
+
+/// Gets a mask of the bits in an operand of width `size`.
+pub fn mask(size: DataSize) -> u32 {
+    match size {
+        DataSize::Byte => 0xFF,
+        DataSize::Word => 0xFFFF,
+        DataSize::Dword => 0xFFFF_FFFF,
+    }
+}
+
+/// Gets the sign bit of an operand of width `size`.
+pub fn sign_bit(size: DataSize) -> u32 {
+    1 << (size.bytes() * 8 - 1)
+}
+
+/// Sets ZF, SF and PF from the result of an operation.
+fn set_result_flags(result: u32, size: DataSize, flags: &mut Flags) {
+    flags.assign(Flag::ZF, result & mask(size) == 0);
+    flags.assign(Flag::SF, result & sign_bit(size) != 0);
+    flags.assign(Flag::PF, (result as u8).count_ones() & 1 == 0);
+}
+
+pub fn add(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let (lhs, rhs) = (lhs & mask(size), rhs & mask(size));
+    let result = lhs.wrapping_add(rhs) & mask(size);
+
+    flags.assign(Flag::CF, lhs as u64 + rhs as u64 > mask(size) as u64);
+    flags.assign(Flag::OF, (lhs ^ result) & (rhs ^ result) & sign_bit(size) != 0);
+    flags.assign(Flag::AF, (lhs ^ rhs ^ result) & 0x10 != 0);
+    set_result_flags(result, size, flags);
+    result
+}
+
+pub fn sub(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let (lhs, rhs) = (lhs & mask(size), rhs & mask(size));
+    let result = lhs.wrapping_sub(rhs) & mask(size);
+
+    flags.assign(Flag::CF, lhs < rhs);
+    flags.assign(Flag::OF, (lhs ^ rhs) & (lhs ^ result) & sign_bit(size) != 0);
+    flags.assign(Flag::AF, (lhs ^ rhs ^ result) & 0x10 != 0);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Adds with carry, adding CF to the sum so that multi-word additions can be chained.
+pub fn adc(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let (lhs, rhs) = (lhs & mask(size), rhs & mask(size));
+    let sum = lhs as u64 + rhs as u64 + flags.get(Flag::CF) as u64;
+    let result = sum as u32 & mask(size);
+
+    flags.assign(Flag::CF, sum > mask(size) as u64);
+    flags.assign(Flag::OF, (lhs ^ result) & (rhs ^ result) & sign_bit(size) != 0);
+    flags.assign(Flag::AF, (lhs ^ rhs ^ result) & 0x10 != 0);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Subtracts with borrow, subtracting CF from the difference so that multi-word subtractions can be chained.
+pub fn sbb(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let (lhs, rhs) = (lhs & mask(size), rhs & mask(size));
+    let subtrahend = rhs as u64 + flags.get(Flag::CF) as u64;
+    let result = (lhs as u64).wrapping_sub(subtrahend) as u32 & mask(size);
+
+    flags.assign(Flag::CF, (lhs as u64) < subtrahend);
+    flags.assign(Flag::OF, (lhs ^ rhs) & (lhs ^ result) & sign_bit(size) != 0);
+    flags.assign(Flag::AF, (lhs ^ rhs ^ result) & 0x10 != 0);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Adds 1 without affecting CF.
+pub fn inc(value: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let cf = flags.get(Flag::CF);
+    let result = add(value, 1, size, flags);
+    flags.assign(Flag::CF, cf);
+    result
+}
+
+/// Subtracts 1 without affecting CF.
+pub fn dec(value: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let cf = flags.get(Flag::CF);
+    let result = sub(value, 1, size, flags);
+    flags.assign(Flag::CF, cf);
+    result
+}
+
+/// Two's complement negation. CF is set unless the operand is 0.
+pub fn neg(value: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    sub(0, value, size, flags)
+}
+
+/// Unsigned widening multiplication, returning the low and high halves of the product.
+/// CF and OF are set when the high half is non-zero.
+pub fn mul(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> (u32, u32) {
+    let product = (lhs & mask(size)) as u64 * (rhs & mask(size)) as u64;
+    let (low, high) = split(product, size);
+
+    flags.assign(Flag::CF, high != 0);
+    flags.assign(Flag::OF, high != 0);
+    flags.unset(Flag::AF);
+    set_result_flags(low, size, flags);
+    (low, high)
+}
+
+/// Signed widening multiplication, returning the low and high halves of the product.
+/// CF and OF are set when the low half alone does not hold the signed product.
+pub fn imul(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> (u32, u32) {
+    let product = sign_extend(lhs, size) as i32 as i64 * sign_extend(rhs, size) as i32 as i64;
+    let (low, high) = split(product as u64, size);
+    let truncated = sign_extend(low, size) as i32 as i64 != product;
+
+    flags.assign(Flag::CF, truncated);
+    flags.assign(Flag::OF, truncated);
+    flags.unset(Flag::AF);
+    set_result_flags(low, size, flags);
+    (low, high)
+}
+
+/// Unsigned division of the double-width dividend `high:low`, returning the quotient and remainder.
+/// Returns `None` when dividing by 0 or when the quotient does not fit in `size`,
+/// both of which raise a divide error. Flags are not affected.
+pub fn div(high: u32, low: u32, divisor: u32, size: DataSize) -> Option<(u32, u32)> {
+    let bits = size.bytes() * 8;
+    let dividend = ((high & mask(size)) as u64) << bits | (low & mask(size)) as u64;
+    let divisor = (divisor & mask(size)) as u64;
+    if divisor == 0 { return None }
+
+    let quotient = dividend / divisor;
+    if quotient > mask(size) as u64 { return None }
+    Some((quotient as u32, (dividend % divisor) as u32))
+}
+
+/// Signed division of the double-width dividend `high:low`, returning the quotient and remainder.
+/// The quotient is truncated towards 0 and the remainder has the sign of the dividend.
+/// Returns `None` when dividing by 0 or when the quotient does not fit in `size`,
+/// both of which raise a divide error. Flags are not affected.
+pub fn idiv(high: u32, low: u32, divisor: u32, size: DataSize) -> Option<(u32, u32)> {
+    let bits = size.bytes() * 8;
+    let dividend = (sign_extend(high, size) as i32 as i64) << bits | (low & mask(size)) as i64;
+    let divisor = sign_extend(divisor, size) as i32 as i64;
+    if divisor == 0 { return None }
+
+    // i64::MIN / -1 overflows, and its quotient would not fit in `size` anyway
+    let quotient = dividend.checked_div(divisor)?;
+    let remainder = dividend.checked_rem(divisor)?;
+    if quotient != sign_extend(quotient as u32, size) as i32 as i64 { return None }
+    Some((quotient as u32 & mask(size), remainder as u32 & mask(size)))
+}
+
+/// Sign-extends the low `size` bits of `value` to 32 bits.
+pub fn sign_extend(value: u32, size: DataSize) -> u32 {
+    let shift = 32 - size.bytes() * 8;
+    (((value << shift) as i32) >> shift) as u32
+}
+
+/// Splits a double-width product into its low and high halves of width `size`.
+fn split(product: u64, size: DataSize) -> (u32, u32) {
+    let bits = size.bytes() * 8;
+    (product as u32 & mask(size), (product >> bits) as u32 & mask(size))
+}
+
+/// Sets the flags for a bitwise operation: CF and OF are cleared.
+fn logic(result: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let result = result & mask(size);
+
+    flags.unset(Flag::CF);
+    flags.unset(Flag::OF);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+pub fn and(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    logic(lhs & rhs, size, flags)
+}
+
+pub fn or(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    logic(lhs | rhs, size, flags)
+}
+
+pub fn xor(lhs: u32, rhs: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    logic(lhs ^ rhs, size, flags)
+}
+
+/// Logical shift left. The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the last bit shifted out and OF is set when the sign bit changes.
+pub fn shl(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let shifted = (lhs as u64) << count;
+    let result = shifted as u32 & mask(size);
+    let cf = (shifted >> (size.bytes() * 8)) & 1 != 0;
+
+    flags.assign(Flag::CF, cf);
+    flags.assign(Flag::OF, (result & sign_bit(size) != 0) != cf);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Logical shift right, filling with zeros. The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the last bit shifted out and OF is set to the sign bit of the original operand.
+pub fn shr(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let result = lhs >> count;
+
+    flags.assign(Flag::CF, (lhs >> (count - 1)) & 1 != 0);
+    flags.assign(Flag::OF, lhs & sign_bit(size) != 0);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Arithmetic shift right, filling with copies of the sign bit. The count is masked to 5 bits and a count of 0
+/// leaves the flags unchanged. CF receives the last bit shifted out and OF is cleared.
+pub fn sar(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let extended = sign_extend(lhs, size) as i32;
+    let result = (extended >> count) as u32 & mask(size);
+
+    flags.assign(Flag::CF, (extended >> (count - 1)) & 1 != 0);
+    flags.unset(Flag::OF);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Rotates left. The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the bit rotated into the lowest bit and OF is set to the new sign bit XOR CF.
+/// SF, ZF, PF and AF are not affected.
+pub fn rol(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let count = count % bits;
+    let result = (lhs << count | lhs.checked_shr(bits - count).unwrap_or(0)) & mask(size);
+    let cf = result & 1 != 0;
+
+    flags.assign(Flag::CF, cf);
+    flags.assign(Flag::OF, (result & sign_bit(size) != 0) != cf);
+    result
+}
+
+/// Rotates right. The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the bit rotated into the sign bit and OF is set to the XOR of the two highest bits.
+/// SF, ZF, PF and AF are not affected.
+pub fn ror(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let count = count % bits;
+    let result = (lhs >> count | lhs.checked_shl(bits - count).unwrap_or(0)) & mask(size);
+    let msb = result & sign_bit(size) != 0;
+
+    flags.assign(Flag::CF, msb);
+    flags.assign(Flag::OF, msb != (result & (sign_bit(size) >> 1) != 0));
+    result
+}
+
+/// Rotates left through CF, treating CF as an extra bit above the operand.
+/// The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// OF is set to the new sign bit XOR CF. SF, ZF, PF and AF are not affected.
+pub fn rcl(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let wide = (flags.get(Flag::CF) as u64) << bits | lhs as u64;
+    let rotated = rotate_through_carry(wide, count % (bits + 1), bits + 1);
+    let result = rotated as u32 & mask(size);
+    let cf = (rotated >> bits) & 1 != 0;
+
+    flags.assign(Flag::CF, cf);
+    flags.assign(Flag::OF, (result & sign_bit(size) != 0) != cf);
+    result
+}
+
+/// Rotates right through CF, treating CF as an extra bit above the operand.
+/// The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// OF is set to the original sign bit XOR the original CF. SF, ZF, PF and AF are not affected.
+pub fn rcr(lhs: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let cf = flags.get(Flag::CF);
+    let wide = (cf as u64) << bits | lhs as u64;
+    let rotated = rotate_through_carry(wide, (bits + 1) - count % (bits + 1), bits + 1);
+
+    flags.assign(Flag::OF, (lhs & sign_bit(size) != 0) != cf);
+    flags.assign(Flag::CF, (rotated >> bits) & 1 != 0);
+    rotated as u32 & mask(size)
+}
+
+/// Rotates the low `width` bits of `value` left by `count`.
+fn rotate_through_carry(value: u64, count: u32, width: u32) -> u64 {
+    let width_mask = (1u64 << width) - 1;
+    (value << count | value >> (width - count)) & width_mask
+}
+
+/// Double precision shift left, shifting bits of `src` into `lhs` from the right.
+/// The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the last bit shifted out and OF is set when the sign bit changes.
+pub fn shld(lhs: u32, src: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let shifted = ((lhs as u128) << bits | (src & mask(size)) as u128) << count;
+    let result = (shifted >> bits) as u32 & mask(size);
+
+    flags.assign(Flag::CF, (shifted >> (2 * bits)) & 1 != 0);
+    flags.assign(Flag::OF, (result ^ lhs) & sign_bit(size) != 0);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+/// Double precision shift right, shifting bits of `src` into `lhs` from the left.
+/// The count is masked to 5 bits and a count of 0 leaves the flags unchanged.
+/// CF receives the last bit shifted out and OF is set when the sign bit changes.
+pub fn shrd(lhs: u32, src: u32, count: u32, size: DataSize, flags: &mut Flags) -> u32 {
+    let lhs = lhs & mask(size);
+    let count = count & 0x1F;
+    if count == 0 { return lhs }
+
+    let bits = size.bytes() * 8;
+    let wide = ((src & mask(size)) as u64) << bits | lhs as u64;
+    let result = (wide >> count) as u32 & mask(size);
+
+    flags.assign(Flag::CF, (wide >> (count - 1)) & 1 != 0);
+    flags.assign(Flag::OF, (result ^ lhs) & sign_bit(size) != 0);
+    flags.unset(Flag::AF);
+    set_result_flags(result, size, flags);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CF: u32 = Flag::CF as u32;
+    const PF: u32 = Flag::PF as u32;
+    const AF: u32 = Flag::AF as u32;
+    const ZF: u32 = Flag::ZF as u32;
+    const SF: u32 = Flag::SF as u32;
+    const OF: u32 = Flag::OF as u32;
+
+    type BinaryOp = fn(u32, u32, DataSize, &mut Flags) -> u32;
+    type UnaryOp = fn(u32, DataSize, &mut Flags) -> u32;
+
+    /// Runs each `(lhs, rhs, size, result, flags)` case, asserting the result and the exact flag bits.
+    fn check_binary(op: BinaryOp, cases: &[(u32, u32, DataSize, u32, u32)]) {
+        for &(lhs, rhs, size, expected, expected_flags) in cases {
+            let mut flags = Flags::new();
+            let result = op(lhs, rhs, size, &mut flags);
+
+            assert_eq!(result, expected, "result of ({lhs:#x}, {rhs:#x}, {size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of ({lhs:#x}, {rhs:#x}, {size})");
+        }
+    }
+
+    /// Runs each `(operand, initial flags, size, result, flags)` case, asserting the result and the exact flag bits.
+    fn check_unary(op: UnaryOp, cases: &[(u32, u32, DataSize, u32, u32)]) {
+        for &(value, initial, size, expected, expected_flags) in cases {
+            let mut flags = Flags::from_bits(initial);
+            let result = op(value, size, &mut flags);
+
+            assert_eq!(result, expected, "result of ({value:#x}, {size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of ({value:#x}, {size})");
+        }
+    }
+
+    #[test]
+    fn add_sets_flags() {
+        check_binary(add, &[
+            (1, 2, DataSize::Dword, 3, PF),
+            (0, 0, DataSize::Dword, 0, ZF | PF),
+            (0xFFFF_FFFF, 1, DataSize::Dword, 0, CF | ZF | PF | AF),
+            (0x7FFF_FFFF, 1, DataSize::Dword, 0x8000_0000, SF | OF | PF | AF),
+            (0x8000_0000, 0x8000_0000, DataSize::Dword, 0, CF | OF | ZF | PF),
+            (0x0F, 0x01, DataSize::Dword, 0x10, AF),
+            (0xFF, 1, DataSize::Byte, 0, CF | ZF | PF | AF),
+            (0x7F, 1, DataSize::Byte, 0x80, SF | OF | AF),
+            (0xFFFF, 0xFFFF, DataSize::Word, 0xFFFE, CF | SF | AF),
+        ]);
+    }
+
+    #[test]
+    fn sub_sets_flags() {
+        check_binary(sub, &[
+            (5, 3, DataSize::Dword, 2, 0),
+            (3, 3, DataSize::Dword, 0, ZF | PF),
+            (3, 5, DataSize::Dword, 0xFFFF_FFFE, CF | SF | AF),
+            (0x8000_0000, 1, DataSize::Dword, 0x7FFF_FFFF, OF | PF | AF),
+            (0x7FFF_FFFF, 0xFFFF_FFFF, DataSize::Dword, 0x8000_0000, CF | SF | OF | PF),
+            (0x10, 1, DataSize::Dword, 0x0F, PF | AF),
+            (0, 1, DataSize::Byte, 0xFF, CF | SF | PF | AF),
+            (0x80, 1, DataSize::Byte, 0x7F, OF | AF),
+        ]);
+    }
+
+    #[test]
+    fn cmp_of_negative_difference_sets_sign_and_overflow() {
+        // -5 < 3 must satisfy jl (SF != OF) and not jg
+        let mut flags = Flags::new();
+        sub(-5i32 as u32, 3, DataSize::Dword, &mut flags);
+        assert!(flags.get(Flag::SF) != flags.get(Flag::OF));
+
+        // i32::MIN < 1 overflows but must still satisfy jl
+        let mut flags = Flags::new();
+        sub(i32::MIN as u32, 1, DataSize::Dword, &mut flags);
+        assert!(flags.get(Flag::SF) != flags.get(Flag::OF));
+
+        // 3 > -5 must satisfy jg (ZF == 0 and SF == OF)
+        let mut flags = Flags::new();
+        sub(3, -5i32 as u32, DataSize::Dword, &mut flags);
+        assert!(!flags.get(Flag::ZF) && flags.get(Flag::SF) == flags.get(Flag::OF));
+    }
+
+    #[test]
+    fn adc_and_sbb_include_carry() {
+        let cases: [(BinaryOp, u32, u32, u32, DataSize, u32, u32); 7] = [
+            (adc, 1, 2, CF, DataSize::Dword, 4, 0),
+            (adc, 0xFFFF_FFFF, 0, CF, DataSize::Dword, 0, CF | ZF | PF | AF),
+            (adc, 0x7F, 0, CF, DataSize::Byte, 0x80, SF | OF | AF),
+            (adc, 0xFF, 0xFF, CF, DataSize::Byte, 0xFF, CF | SF | PF | AF),
+            (sbb, 5, 2, CF, DataSize::Dword, 2, 0),
+            (sbb, 0, 0, CF, DataSize::Dword, 0xFFFF_FFFF, CF | SF | PF | AF),
+            (sbb, 0, 0xFFFF_FFFF, CF, DataSize::Dword, 0, CF | ZF | PF | AF),
+        ];
+
+        for (op, lhs, rhs, initial, size, expected, expected_flags) in cases {
+            let mut flags = Flags::from_bits(initial);
+            assert_eq!(op(lhs, rhs, size, &mut flags), expected, "result of ({lhs:#x}, {rhs:#x}, {size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of ({lhs:#x}, {rhs:#x}, {size})");
+        }
+    }
+
+    #[test]
+    fn inc_sets_flags_and_preserves_carry() {
+        check_unary(inc, &[
+            (1, 0, DataSize::Dword, 2, 0),
+            (1, CF, DataSize::Dword, 2, CF),
+            (0xFFFF_FFFF, 0, DataSize::Dword, 0, ZF | PF | AF),
+            (0x7FFF_FFFF, CF, DataSize::Dword, 0x8000_0000, CF | SF | OF | PF | AF),
+            (0xFF, 0, DataSize::Byte, 0, ZF | PF | AF),
+        ]);
+    }
+
+    #[test]
+    fn dec_sets_flags_and_preserves_carry() {
+        check_unary(dec, &[
+            (2, 0, DataSize::Dword, 1, 0),
+            (1, CF, DataSize::Dword, 0, CF | ZF | PF),
+            (0, 0, DataSize::Dword, 0xFFFF_FFFF, SF | PF | AF),
+            (0x8000_0000, 0, DataSize::Dword, 0x7FFF_FFFF, OF | PF | AF),
+        ]);
+    }
+
+    #[test]
+    fn neg_sets_flags() {
+        check_unary(neg, &[
+            (0, CF, DataSize::Dword, 0, ZF | PF),
+            (1, 0, DataSize::Dword, 0xFFFF_FFFF, CF | SF | PF | AF),
+            (0x8000_0000, 0, DataSize::Dword, 0x8000_0000, CF | SF | OF | PF),
+            (0x80, 0, DataSize::Byte, 0x80, CF | SF | OF),
+        ]);
+    }
+
+    #[test]
+    fn mul_sets_carry_and_overflow_on_high_half() {
+        let cases = [
+            (6, 7, DataSize::Dword, (42, 0), 0),
+            (0, 7, DataSize::Dword, (0, 0), ZF | PF),
+            (0x1_0000, 0x1_0000, DataSize::Dword, (0, 1), CF | OF | ZF | PF),
+            (0xFFFF_FFFF, 2, DataSize::Dword, (0xFFFF_FFFE, 1), CF | OF | SF),
+            (16, 16, DataSize::Byte, (0, 1), CF | OF | ZF | PF),
+            (0xFFFF, 0xFFFF, DataSize::Word, (1, 0xFFFE), CF | OF),
+        ];
+
+        for (lhs, rhs, size, expected, expected_flags) in cases {
+            let mut flags = Flags::new();
+            assert_eq!(mul(lhs, rhs, size, &mut flags), expected, "mul {lhs:#x}, {rhs:#x} ({size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of mul {lhs:#x}, {rhs:#x} ({size})");
+        }
+    }
+
+    #[test]
+    fn imul_sets_carry_and_overflow_when_truncated() {
+        let cases = [
+            (6, -7i32 as u32, DataSize::Dword, (-42i32 as u32, 0xFFFF_FFFF), SF),
+            (0x1_0000, 0x1_0000, DataSize::Dword, (0, 1), CF | OF | ZF | PF),
+            (0x8000_0000, 0xFFFF_FFFF, DataSize::Dword, (0x8000_0000, 0), CF | OF | SF | PF),
+            (0xFF, 0xFF, DataSize::Byte, (1, 0), 0),
+            (0x40, 2, DataSize::Byte, (0x80, 0), CF | OF | SF),
+        ];
+
+        for (lhs, rhs, size, expected, expected_flags) in cases {
+            let mut flags = Flags::new();
+            assert_eq!(imul(lhs, rhs, size, &mut flags), expected, "imul {lhs:#x}, {rhs:#x} ({size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of imul {lhs:#x}, {rhs:#x} ({size})");
+        }
+    }
+
+    #[test]
+    fn div_returns_quotient_and_remainder() {
+        assert_eq!(div(0, 47, 10, DataSize::Dword), Some((4, 7)));
+        assert_eq!(div(1, 0, 2, DataSize::Dword), Some((0x8000_0000, 0)));
+        assert_eq!(div(0, 0xFFFF_FFFF, 0xFFFF_FFFF, DataSize::Dword), Some((1, 0)));
+        assert_eq!(div(0x01, 0x00, 0x10, DataSize::Byte), Some((0x10, 0)));
+        assert_eq!(div(0, 1, 0, DataSize::Dword), None);
+        assert_eq!(div(1, 0, 1, DataSize::Dword), None);
+    }
+
+    #[test]
+    fn idiv_truncates_towards_zero() {
+        assert_eq!(idiv(0xFFFF_FFFF, -47i32 as u32, 10, DataSize::Dword), Some((-4i32 as u32, -7i32 as u32)));
+        assert_eq!(idiv(0, 47, -10i32 as u32, DataSize::Dword), Some((-4i32 as u32, 7)));
+        assert_eq!(idiv(0xFF, 0xF9, 2, DataSize::Byte), Some((0xFD, 0xFF)));
+        assert_eq!(idiv(0xFFFF_FFFF, 0x8000_0000, 0xFFFF_FFFF, DataSize::Dword), None);
+        assert_eq!(idiv(0, 1, 0, DataSize::Dword), None);
+    }
+
+    #[test]
+    fn sign_extend_copies_the_sign_bit() {
+        assert_eq!(sign_extend(0x7F, DataSize::Byte), 0x7F);
+        assert_eq!(sign_extend(0x80, DataSize::Byte), 0xFFFF_FF80);
+        assert_eq!(sign_extend(0x1234_8000, DataSize::Word), 0xFFFF_8000);
+        assert_eq!(sign_extend(0x8000_0000, DataSize::Dword), 0x8000_0000);
+    }
+
+    #[test]
+    fn logic_ops_clear_carry_and_overflow() {
+        check_binary(and, &[
+            (0b1100, 0b1010, DataSize::Dword, 0b1000, 0),
+            (0b1100, 0b0011, DataSize::Dword, 0, ZF | PF),
+            (0x8000_0001, 0x8000_0003, DataSize::Dword, 0x8000_0001, SF),
+        ]);
+        check_binary(or, &[
+            (0b1100, 0b1010, DataSize::Dword, 0b1110, 0),
+            (0, 0, DataSize::Dword, 0, ZF | PF),
+            (0x80, 0x01, DataSize::Byte, 0x81, SF | PF),
+        ]);
+        check_binary(xor, &[
+            (0b1100, 0b1010, DataSize::Dword, 0b0110, PF),
+            (0xDEAD, 0xDEAD, DataSize::Dword, 0, ZF | PF),
+            (0xFFFF_FFFF, 0, DataSize::Dword, 0xFFFF_FFFF, SF | PF),
+        ]);
+    }
+
+    #[test]
+    fn shl_sets_flags() {
+        check_binary(shl, &[
+            (1, 1, DataSize::Dword, 2, 0),
+            (0x8000_0000, 1, DataSize::Dword, 0, CF | OF | ZF | PF),
+            (0x4000_0000, 1, DataSize::Dword, 0x8000_0000, SF | OF | PF),
+            (0xC000_0000, 1, DataSize::Dword, 0x8000_0000, CF | SF | PF),
+            (0x81, 1, DataSize::Byte, 0x02, CF | OF),
+            (3, 0, DataSize::Dword, 3, 0),
+            (1, 33, DataSize::Dword, 2, 0),
+        ]);
+    }
+
+    #[test]
+    fn shr_is_logical_and_sets_flags() {
+        check_binary(shr, &[
+            (4, 1, DataSize::Dword, 2, 0),
+            (3, 1, DataSize::Dword, 1, CF),
+            (0x8000_0000, 1, DataSize::Dword, 0x4000_0000, OF | PF),
+            (0xFFFF_FFFF, 31, DataSize::Dword, 1, CF | OF),
+            (1, 1, DataSize::Dword, 0, CF | ZF | PF),
+            (0x80, 7, DataSize::Byte, 1, OF),
+            (3, 0, DataSize::Dword, 3, 0),
+        ]);
+    }
+
+    #[test]
+    fn sar_fills_with_the_sign_bit() {
+        check_binary(sar, &[
+            (0x8000_0000, 4, DataSize::Dword, 0xF800_0000, SF | PF),
+            (0x7FFF_FFF0, 4, DataSize::Dword, 0x07FF_FFFF, PF),
+            (0xFFFF_FFFF, 1, DataSize::Dword, 0xFFFF_FFFF, CF | SF | PF),
+            (0x81, 1, DataSize::Byte, 0xC0, CF | SF | PF),
+            (3, 0, DataSize::Dword, 3, 0),
+        ]);
+    }
+
+    #[test]
+    fn rotates_only_set_carry_and_overflow() {
+        check_binary(rol, &[
+            (0x8000_0001, 1, DataSize::Dword, 0x0000_0003, CF | OF),
+            (0x1234_5678, 8, DataSize::Dword, 0x3456_7812, 0),
+            (0x81, 1, DataSize::Byte, 0x03, CF | OF),
+            (0x81, 9, DataSize::Byte, 0x03, CF | OF),
+            (0x81, 8, DataSize::Byte, 0x81, CF),
+        ]);
+        check_binary(ror, &[
+            (0x0000_0001, 1, DataSize::Dword, 0x8000_0000, CF | OF),
+            (0x1234_5678, 8, DataSize::Dword, 0x7812_3456, OF),
+            (0x03, 1, DataSize::Byte, 0x81, CF | OF),
+            (0x80, 1, DataSize::Byte, 0x40, OF),
+        ]);
+    }
+
+    #[test]
+    fn rotates_through_carry_include_carry_flag() {
+        let cases: [(BinaryOp, u32, u32, u32, DataSize, u32, u32); 6] = [
+            (rcl, 0x8000_0000, 0, 1, DataSize::Dword, 0, CF | OF),
+            (rcl, 0x0000_0000, CF, 1, DataSize::Dword, 1, 0),
+            (rcl, 0x80, CF, 9, DataSize::Byte, 0x80, CF),
+            (rcr, 0x0000_0001, 0, 1, DataSize::Dword, 0, CF),
+            (rcr, 0x0000_0000, CF, 1, DataSize::Dword, 0x8000_0000, OF),
+            (rcr, 0x01, CF, 2, DataSize::Byte, 0xC0, OF),
+        ];
+
+        for (op, lhs, initial, count, size, expected, expected_flags) in cases {
+            let mut flags = Flags::from_bits(initial);
+            assert_eq!(op(lhs, count, size, &mut flags), expected, "{lhs:#x} by {count} ({size})");
+            assert_eq!(flags.bits(), expected_flags, "flags of {lhs:#x} by {count} ({size})");
+        }
+    }
+
+    #[test]
+    fn double_precision_shifts_take_bits_from_source() {
+        let mut flags = Flags::new();
+        assert_eq!(shld(0x1234_5678, 0x9ABC_DEF0, 8, DataSize::Dword, &mut flags), 0x3456_789A);
+        assert!(!flags.get(Flag::CF));
+        assert_eq!(shld(0x8000_0000, 0, 1, DataSize::Dword, &mut flags), 0);
+        assert!(flags.get(Flag::CF) && flags.get(Flag::OF) && flags.get(Flag::ZF));
+
+        let mut flags = Flags::new();
+        assert_eq!(shrd(0x1234_5678, 0x9ABC_DEF0, 8, DataSize::Dword, &mut flags), 0xF012_3456);
+        assert!(!flags.get(Flag::CF) && flags.get(Flag::OF) && flags.get(Flag::SF));
+        assert_eq!(shrd(0x0001, 0x0000, 1, DataSize::Word, &mut flags), 0);
+        assert!(flags.get(Flag::CF) && !flags.get(Flag::OF));
+    }
+}
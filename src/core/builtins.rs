@@ -1,7 +1,7 @@
 use std::io::{self, Write};
 
 use crate::interpreter::error::AsmrRuntimeError;
-use super::{executing_context::ExecutingContext, register::{RegisterData, RegisterName}};
+use super::{executing_context::ExecutingContext, register::{RegisterData, RegisterName}, memory::DataSize};
 
 /// Calls the appropriate handlers for the builtin asmr functions.
 pub fn call_builtin_fn(func_name: &String, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
@@ -30,48 +30,60 @@ pub fn call_builtin_fn(func_name: &String, ctx: &mut ExecutingContext) -> Result
 /// get_param(1, ctx) // a
 /// get_param(2, ctx) // b
 /// ```
-fn get_param<'a>(n: i32, ctx: &'a &mut ExecutingContext) -> &'a RegisterData {
-    ctx.stack.get((ctx.registers.get(&RegisterName::Ebp).unwrap().borrow().get_raw() - 1 - n) as usize).unwrap()
+fn get_param(n: i32, ctx: &ExecutingContext) -> Result<RegisterData, AsmrRuntimeError> {
+    // Skip the saved ebp and eip making up the stack frame
+    let ebp = ctx.registers.get(&RegisterName::Ebp).unwrap().borrow().get_raw();
+    ctx.memory.read(ebp.wrapping_add(4 + 4 * n) as u32, DataSize::Dword)
+        .map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))
 }
 
 /// Clears `n` parameters from the stack.
 /// Because calling a function creates a stack frame, the stack after a call resembles
 /// ```nasm
-/// param3
-/// param2
-/// param1
-/// eip
-/// ebp
+/// param3  ; ebp + 16
+/// param2  ; ebp + 12
+/// param1  ; ebp + 8
+/// eip     ; ebp + 4
+/// ebp     ; ebp
 /// ```
 /// To preserve the stack frame, [clear_params] removes the parameters and updates the current ebp and esp.
-/// The pushed `eip` and `ebp` making up the stack frame are preserved so `ret` functions as expected.
-fn clear_params(n: i32, ctx: &mut ExecutingContext) {
-    // Clear n parameters from the stack (leaves stack frame pushed for when 'ret' is called)
-    let idx_stack_frame_start = ctx.registers.get(&RegisterName::Ebp).unwrap().borrow().get_raw() as usize - 1;
-    ctx.stack.drain((idx_stack_frame_start - n as usize)..idx_stack_frame_start);
+/// The pushed `eip` and `ebp` making up the stack frame are moved up so `ret` functions as expected.
+fn clear_params(n: i32, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    let ebp = ctx.registers.get(&RegisterName::Ebp).unwrap().borrow().get_raw() as u32;
+    let saved_ebp = ctx.memory.read(ebp, DataSize::Dword).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+    let saved_eip = ctx.memory.read(ebp.wrapping_add(4), DataSize::Dword).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+
+    // Move the stack frame over the top of the parameters
+    let offset = 4 * n as u32;
+    ctx.memory.write(ebp.wrapping_add(offset), DataSize::Dword, saved_ebp).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+    ctx.memory.write(ebp.wrapping_add(offset + 4), DataSize::Dword, saved_eip).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
 
     // Update the position of ebp and esp
-    *ctx.registers.get(&RegisterName::Ebp).unwrap().borrow_mut() -= n;
-    *ctx.registers.get(&RegisterName::Esp).unwrap().borrow_mut() -= n;
+    *ctx.registers.get(&RegisterName::Ebp).unwrap().borrow_mut() += offset as i32;
+    *ctx.registers.get(&RegisterName::Esp).unwrap().borrow_mut() += offset as i32;
+    Ok(())
 }
 
 fn io_print(ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
-    let msg = match get_param(1, &ctx) {
-        RegisterData::Value(i) => i.to_string(),
-        RegisterData::Pointer(p) => ctx.heap.get(*p as usize).unwrap().iter()
-                                        .map(|b| char::from(*b)).collect::<String>(),
+    // Strings are written byte for byte, so UTF-8 text prints as written
+    let msg = match get_param(1, ctx)? {
+        RegisterData::Value(i) => i.to_string().into_bytes(),
+        RegisterData::Pointer(p) => ctx.memory.read_str(p as u32)
+                                        .map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?
+                                        .to_vec(),
     };
-    print!("{}", msg);
-    io::stdout()
+    let mut stdout = io::stdout();
+    stdout.write_all(&msg)
+        .map_err(|e| AsmrRuntimeError::from(ctx.ptr, format!("failed to write to stdout: {}", e)))?;
+    stdout
         .flush()
         .map_err(|e| AsmrRuntimeError::from(ctx.ptr, format!("failed to flush stdout: {}", e)))?;
 
-    clear_params(1, ctx);
-    Ok(())
+    clear_params(1, ctx)
 }
 
 fn io_readln(ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
-    let p = if let RegisterData::Pointer(p) = get_param(1, &ctx) { *p }
+    let p = if let RegisterData::Pointer(p) = get_param(1, ctx)? { p as u32 }
             else { return Err(AsmrRuntimeError::from(ctx.ptr, "expected pointer but found value")) };
 
     let mut str = String::new();
@@ -79,9 +91,13 @@ fn io_readln(ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
         .read_line(&mut str)
         .map_err(|e| AsmrRuntimeError::from(ctx.ptr, format!("failed to read from stdin: {}", e)))?;
     
-    ctx.heap.get_mut(p as usize).unwrap()
-        .clone_from(&str.trim().as_bytes().to_vec());
+    // Store the line as a NUL terminated string, truncated to fit the buffer
+    let region = ctx.memory.region_of(p)
+        .ok_or_else(|| AsmrRuntimeError::from(ctx.ptr, format!("segmentation fault: address `{p:#x}` is not mapped")))?;
+    let mut bytes = str.trim().as_bytes().to_vec();
+    bytes.push(0);
+    bytes.truncate((region.end - p) as usize);
+    ctx.memory.write_bytes(p, &bytes).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
     
-    clear_params(1, ctx);
-    Ok(())
+    clear_params(1, ctx)
 }
@@ -2,7 +2,8 @@ use std::{collections::HashMap, cell::RefCell};
 
 use strum::IntoEnumIterator;
 
-use super::{register::{Register, RegisterName, RegisterData}, flags::Flags};
+use crate::interpreter::error::AsmrRuntimeError;
+use super::{register::{Register, RegisterName, RegisterData}, flags::Flags, memory::{Memory, DataSize, STACK_TOP}, alu};
 
 pub struct ExecutingContext {
     /// Tracks the state of the flags register
@@ -11,18 +12,18 @@ pub struct ExecutingContext {
     /// Maps the register names to their data
     pub registers: HashMap<RegisterName, RefCell<Register>>,
     
-    /// Stores the stack memory
-    pub stack: Vec<RegisterData>,
+    /// Stores the data segment and the stack
+    pub memory: Memory,
 
-    /// Stores the heap memory
-    pub heap: Vec<Vec<u8>>,
-
-    /// Maps identifiers to their pointers on the heap
+    /// Maps identifiers to their addresses in memory
     pub symtab: HashMap<String, i32>,
 
     /// Maps the label names to their pointers
     pub labels: HashMap<String, usize>,
 
+    /// Maps `equ` constants that could not be evaluated at parse time to their values
+    pub constants: HashMap<String, RegisterData>,
+
     /// Current instruction pointer
     pub ptr: usize,
 
@@ -33,13 +34,13 @@ pub struct ExecutingContext {
 impl ExecutingContext {
     pub fn new() -> Self {
         let registers: HashMap<RegisterName, RefCell<Register>> =
-            HashMap::from_iter(RegisterName::iter().map(|r| {
+            HashMap::from_iter(RegisterName::iter().filter(|r| r.alias().0 == *r).map(|r| {
                 let reg = match r {
-                    RegisterName::Eip |
-                    RegisterName::Esp => Register { data: RegisterData::Pointer(0) },
+                    RegisterName::Eip => Register { data: RegisterData::Pointer(0) },
 
-                    // Offset by 1 to account for final return address as first stack value
-                    RegisterName::Ebp => Register { data: RegisterData::Pointer(1) },
+                    // The stack is empty and grows downwards from the top of memory
+                    RegisterName::Esp |
+                    RegisterName::Ebp => Register { data: RegisterData::Pointer(STACK_TOP as i32) },
                     
                     _ => Register::new(),
                 };
@@ -49,14 +50,69 @@ impl ExecutingContext {
 
 
         Self {
-            stack: Vec::new(),
             flags: Flags::new(),
             registers,
-            heap: Vec::new(),
+            memory: Memory::new(),
             symtab: HashMap::new(),
             labels: HashMap::new(),
+            constants: HashMap::new(),
             ptr: 0,
             next: 1,
         }
     }
+
+    /// Reads a register, extracting the bits of a sub-register from its parent.
+    /// Sub-registers always hold values, only 32-bit registers can hold pointers.
+    pub fn get_register(&self, r: RegisterName) -> RegisterData {
+        let (parent, size, shift) = r.alias();
+        let data = self.registers.get(&parent).unwrap().borrow().data;
+
+        match size {
+            DataSize::Dword => data,
+            _ => RegisterData::Value(((data.get_raw() as u32 >> shift) & alu::mask(size)) as i32),
+        }
+    }
+
+    /// Writes a register, merging the low bits of `data` into the parent of a sub-register.
+    pub fn set_register(&mut self, r: RegisterName, data: RegisterData) {
+        let (parent, size, shift) = r.alias();
+        let mut reg = self.registers.get(&parent).unwrap().borrow_mut();
+
+        reg.data = match size {
+            DataSize::Dword => data,
+            _ => {
+                let mask = alu::mask(size) << shift;
+                let raw = (reg.get_raw() as u32 & !mask) | ((data.get_raw() as u32) << shift & mask);
+                reg.data.with_raw(raw as i32)
+            },
+        };
+    }
+
+    /// Decrements esp and writes `data` to the new top of the stack.
+    pub fn push(&mut self, data: RegisterData) -> Result<(), AsmrRuntimeError> {
+        let mut esp = self.registers.get(&RegisterName::Esp).unwrap().borrow_mut();
+        let addr = (esp.get_raw() as u32).wrapping_sub(4);
+
+        self.memory.write(addr, DataSize::Dword, data)
+            .map_err(|_| AsmrRuntimeError::from(self.ptr, "stack overflow"))?;
+        *esp -= 4;
+        Ok(())
+    }
+
+    /// Reads the top of the stack and increments esp.
+    pub fn pop(&mut self) -> Result<RegisterData, AsmrRuntimeError> {
+        let mut esp = self.registers.get(&RegisterName::Esp).unwrap().borrow_mut();
+        if esp.get_raw() as u32 >= STACK_TOP { return Err(AsmrRuntimeError::from(self.ptr, "cannot pop from an empty stack")) }
+
+        let data = self.memory.read(esp.get_raw() as u32, DataSize::Dword)
+            .map_err(|e| AsmrRuntimeError::from(self.ptr, e))?;
+        *esp += 4;
+        Ok(data)
+    }
+}
+
+impl Default for ExecutingContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
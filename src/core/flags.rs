@@ -1,10 +1,47 @@
+use std::fmt::{self, Display, Formatter};
+
+use strum::{EnumIter, IntoEnumIterator};
+
+/// The flags of the EFLAGS register, valued by their bit in the register.
+/// https://en.wikipedia.org/wiki/FLAGS_register
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(EnumIter, strum::Display)]
 pub enum Flag {
     CF = 0b1,            // Carry
+    PF = 0b100,          // Parity
+    AF = 0b10000,        // Auxiliary carry
     ZF = 0b1000000,      // Zero
     SF = 0b10000000,     // Sign
+    TF = 0b100000000,    // Trap
+    IF = 0b1000000000,   // Interrupt enable
+    DF = 0b10000000000,  // Direction
     OF = 0b100000000000, // Overflow
 }
 
+/// Bit 1 of EFLAGS is reserved and always reads as 1.
+const EFLAGS_RESERVED: u32 = 0b10;
+
+/// Conditions tested by the conditional instructions, e.g. `ja` or `jge`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    Overflow,       // OF
+    NotOverflow,    // !OF
+    Below,          // CF
+    AboveOrEqual,   // !CF
+    Equal,          // ZF
+    NotEqual,       // !ZF
+    BelowOrEqual,   // CF | ZF
+    Above,          // !CF & !ZF
+    Sign,           // SF
+    NotSign,        // !SF
+    Parity,         // PF
+    NotParity,      // !PF
+    Less,           // SF != OF
+    GreaterOrEqual, // SF == OF
+    LessOrEqual,    // ZF | SF != OF
+    Greater,        // !ZF & SF == OF
+}
+
 pub struct Flags {
     data: u32,
 }
@@ -14,6 +51,14 @@ impl Flags {
         Flags { data: 0 }
     }
 
+    pub fn from_bits(data: u32) -> Self {
+        Flags { data }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.data
+    }
+
     pub fn get(&self, flag: Flag) -> bool {
         self.data & flag as u32 != 0
     }
@@ -25,4 +70,85 @@ impl Flags {
     pub fn unset(&mut self, flag: Flag) {
         self.data &= !(flag as u32);
     }
+
+    /// Gets the value of the EFLAGS register, as pushed by `pushf`.
+    pub fn eflags(&self) -> u32 {
+        self.data | EFLAGS_RESERVED
+    }
+
+    /// Creates flags from a value of the EFLAGS register, as popped by `popf`.
+    /// Bits that are not a [Flag] are ignored.
+    pub fn from_eflags(eflags: u32) -> Self {
+        let data = Flag::iter().map(|f| f as u32).filter(|f| eflags & f != 0).fold(0, |data, f| data | f);
+        Flags { data }
+    }
+
+    /// Sets or unsets `flag` depending on `value`.
+    pub fn assign(&mut self, flag: Flag, value: bool) {
+        if value { self.set(flag) } else { self.unset(flag) }
+    }
+
+    /// Checks whether `condition` holds for the current flags.
+    pub fn check(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Overflow => self.get(Flag::OF),
+            Condition::NotOverflow => !self.get(Flag::OF),
+            Condition::Below => self.get(Flag::CF),
+            Condition::AboveOrEqual => !self.get(Flag::CF),
+            Condition::Equal => self.get(Flag::ZF),
+            Condition::NotEqual => !self.get(Flag::ZF),
+            Condition::BelowOrEqual => self.get(Flag::CF) || self.get(Flag::ZF),
+            Condition::Above => !self.get(Flag::CF) && !self.get(Flag::ZF),
+            Condition::Sign => self.get(Flag::SF),
+            Condition::NotSign => !self.get(Flag::SF),
+            Condition::Parity => self.get(Flag::PF),
+            Condition::NotParity => !self.get(Flag::PF),
+            Condition::Less => self.get(Flag::SF) != self.get(Flag::OF),
+            Condition::GreaterOrEqual => self.get(Flag::SF) == self.get(Flag::OF),
+            Condition::LessOrEqual => self.get(Flag::ZF) || self.get(Flag::SF) != self.get(Flag::OF),
+            Condition::Greater => !self.get(Flag::ZF) && self.get(Flag::SF) == self.get(Flag::OF),
+        }
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lists the set flags in the style of a debugger, e.g. `[ CF ZF ]`.
+impl Display for Flags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[ ")?;
+        for flag in Flag::iter().filter(|flag| self.get(*flag)) {
+            write!(f, "{flag} ")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eflags_sets_reserved_bit_and_ignores_unknown_bits() {
+        let flags = Flags::from_eflags(0xFFFF_FFFF);
+
+        assert_eq!(flags.bits(), 0b1111_1101_0101);
+        assert_eq!(flags.eflags(), 0b1111_1101_0111);
+        assert_eq!(Flags::new().eflags(), 0b10);
+    }
+
+    #[test]
+    fn display_lists_set_flags() {
+        let mut flags = Flags::new();
+        assert_eq!(flags.to_string(), "[ ]");
+
+        flags.set(Flag::ZF);
+        flags.set(Flag::CF);
+        flags.set(Flag::OF);
+        assert_eq!(flags.to_string(), "[ CF ZF OF ]");
+    }
 }
@@ -0,0 +1,262 @@
+use std::{collections::HashSet, ops::Range};
+
+use strum::{EnumString, Display};
+
+use super::register::RegisterData;
+
+/// Address of the first byte of the data segment.
+/// Everything below it is left unmapped so that null pointers fault.
+pub const DATA_START: u32 = 0x1000;
+
+/// Address one past the highest byte of the stack. The stack grows downwards from here.
+pub const STACK_TOP: u32 = 0x10_0000;
+
+/// Number of bytes reserved for the stack.
+pub const STACK_SIZE: u32 = 0x1_0000;
+
+/// The width of a memory access.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum DataSize {
+    Byte,
+    Word,
+    Dword,
+}
+
+impl DataSize {
+    /// The number of bytes accessed.
+    pub fn bytes(&self) -> u32 {
+        match self {
+            DataSize::Byte => 1,
+            DataSize::Word => 2,
+            DataSize::Dword => 4,
+        }
+    }
+}
+
+/// A flat, byte-addressable, little-endian address space made up of a data segment and a stack.
+///
+/// ```text
+/// 0x0000_0000  unmapped
+/// DATA_START   data segment (grows upwards with each allocation)
+///              unmapped
+/// STACK_TOP - STACK_SIZE
+///              stack (grows downwards)
+/// STACK_TOP
+/// ```
+pub struct Memory {
+    /// Bytes of the data segment, starting at [DATA_START]
+    data: Vec<u8>,
+
+    /// Bytes of the stack segment, ending at [STACK_TOP]
+    stack: Vec<u8>,
+
+    /// Address ranges of every allocation made in the data segment
+    allocations: Vec<Range<u32>>,
+
+    /// Addresses of the dwords that currently hold a [RegisterData::Pointer]
+    pointers: HashSet<u32>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory {
+            data: Vec::new(),
+            stack: vec![0; STACK_SIZE as usize],
+            allocations: Vec::new(),
+            pointers: HashSet::new(),
+        }
+    }
+
+    /// Appends `bytes` to the data segment and returns the address of the first byte.
+    pub fn alloc(&mut self, bytes: &[u8]) -> Result<u32, String> {
+        let start = self.reserve(bytes.len())?;
+        self.data.extend_from_slice(bytes);
+        Ok(start)
+    }
+
+    /// Appends `len` zeroed bytes to the data segment and returns the address of the first byte.
+    pub fn alloc_zeroed(&mut self, len: u32) -> Result<u32, String> {
+        let start = self.reserve(len as usize)?;
+        self.data.resize(self.data.len() + len as usize, 0);
+        Ok(start)
+    }
+
+    /// Gets the address just past the last allocation, where the next allocation will start.
+    pub fn data_end(&self) -> u32 {
+        DATA_START + self.data.len() as u32
+    }
+
+    /// Records an allocation of `len` bytes at the end of the data segment,
+    /// failing before anything is allocated if it would run into the stack.
+    fn reserve(&mut self, len: usize) -> Result<u32, String> {
+        let start = self.data_end();
+        let end = u32::try_from(len).ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|end| *end <= STACK_TOP - STACK_SIZE)
+            .ok_or_else(|| format!("out of memory: cannot allocate {len} bytes"))?;
+
+        self.allocations.push(start..end);
+        Ok(start)
+    }
+
+    /// Gets the address range of the allocation or segment containing `addr`.
+    pub fn region_of(&self, addr: u32) -> Option<Range<u32>> {
+        if let Some(region) = self.allocations.iter().find(|r| r.contains(&addr)) {
+            return Some(region.clone());
+        }
+
+        let stack = (STACK_TOP - STACK_SIZE)..STACK_TOP;
+        if stack.contains(&addr) { Some(stack) } else { None }
+    }
+
+    /// Reads `len` bytes starting at `addr`.
+    pub fn read_bytes(&self, addr: u32, len: u32) -> Result<&[u8], String> {
+        let (segment, offset) = self.translate(addr, len)?;
+        Ok(&segment[offset..offset + len as usize])
+    }
+
+    /// Writes `bytes` starting at `addr`.
+    pub fn write_bytes(&mut self, addr: u32, bytes: &[u8]) -> Result<(), String> {
+        let len = bytes.len() as u32;
+        let (_, offset) = self.translate(addr, len)?;
+        let segment = if addr >= STACK_TOP - STACK_SIZE { &mut self.stack } else { &mut self.data };
+        segment[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        // Any pointer partially or fully overwritten is no longer a pointer
+        for p in addr.saturating_sub(3)..addr + len {
+            self.pointers.remove(&p);
+        }
+        Ok(())
+    }
+
+    /// Reads a little-endian value of width `size` at `addr`, zero-extending it to 32 bits.
+    pub fn read(&self, addr: u32, size: DataSize) -> Result<RegisterData, String> {
+        let bytes = self.read_bytes(addr, size.bytes())?;
+        let mut buf = [0; 4];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        let raw = i32::from_le_bytes(buf);
+
+        if size == DataSize::Dword && self.pointers.contains(&addr) {
+            Ok(RegisterData::Pointer(raw))
+        }
+        else {
+            Ok(RegisterData::Value(raw))
+        }
+    }
+
+    /// Writes the low `size` bytes of `data` at `addr` in little-endian order.
+    pub fn write(&mut self, addr: u32, size: DataSize, data: RegisterData) -> Result<(), String> {
+        let bytes = match data {
+            RegisterData::Value(i) | RegisterData::Pointer(i) => i.to_le_bytes(),
+        };
+        self.write_bytes(addr, &bytes[..size.bytes() as usize])?;
+
+        if let (DataSize::Dword, RegisterData::Pointer(_)) = (size, data) {
+            self.pointers.insert(addr);
+        }
+        Ok(())
+    }
+
+    /// Reads the bytes starting at `addr` up to the first NUL byte or the end of its region.
+    pub fn read_str(&self, addr: u32) -> Result<&[u8], String> {
+        let region = self.region_of(addr).ok_or_else(|| segfault(addr))?;
+        let bytes = self.read_bytes(addr, region.end - addr)?;
+        let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Ok(&bytes[..len])
+    }
+
+    /// Maps an address range to its backing segment and the offset of `addr` within it.
+    fn translate(&self, addr: u32, len: u32) -> Result<(&[u8], usize), String> {
+        let end = addr.checked_add(len).ok_or_else(|| segfault(addr))?;
+
+        if addr >= STACK_TOP - STACK_SIZE && end <= STACK_TOP {
+            Ok((&self.stack, (addr - (STACK_TOP - STACK_SIZE)) as usize))
+        }
+        else if addr >= DATA_START && end <= DATA_START + self.data.len() as u32 {
+            Ok((&self.data, (addr - DATA_START) as usize))
+        }
+        else {
+            Err(segfault(addr))
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn segfault(addr: u32) -> String {
+    format!("segmentation fault: address `{addr:#x}` is not mapped")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_places_allocations_contiguously() -> Result<(), String> {
+        let mut mem = Memory::new();
+        let a = mem.alloc(b"abc")?;
+        let b = mem.alloc(b"de")?;
+
+        assert_eq!(a, DATA_START);
+        assert_eq!(b, DATA_START + 3);
+        assert_eq!(mem.read_bytes(a, 5)?, b"abcde");
+        assert_eq!(mem.region_of(a + 2), Some(a..b));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_and_write_are_little_endian() -> Result<(), String> {
+        let mut mem = Memory::new();
+        let addr = mem.alloc(&[0; 4])?;
+        mem.write(addr, DataSize::Dword, RegisterData::Value(0x1234_5678))?;
+
+        assert_eq!(mem.read_bytes(addr, 4)?, &[0x78, 0x56, 0x34, 0x12]);
+        assert!(matches!(mem.read(addr + 1, DataSize::Byte)?, RegisterData::Value(0x56)));
+        assert!(matches!(mem.read(addr, DataSize::Word)?, RegisterData::Value(0x5678)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_preserves_pointers_until_overwritten() -> Result<(), String> {
+        let mut mem = Memory::new();
+        let addr = mem.alloc(&[0; 8])?;
+        mem.write(addr, DataSize::Dword, RegisterData::Pointer(DATA_START as i32))?;
+        assert!(matches!(mem.read(addr, DataSize::Dword)?, RegisterData::Pointer(_)));
+
+        mem.write(addr + 3, DataSize::Byte, RegisterData::Value(0))?;
+        assert!(matches!(mem.read(addr, DataSize::Dword)?, RegisterData::Value(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_str_stops_at_nul_or_region_end() -> Result<(), String> {
+        let mut mem = Memory::new();
+        let a = mem.alloc(b"hi\0there")?;
+        let b = mem.alloc(b"bye")?;
+
+        assert_eq!(mem.read_str(a)?, b"hi");
+        assert_eq!(mem.read_str(a + 3)?, b"there");
+        assert_eq!(mem.read_str(b)?, b"bye");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unmapped_access_is_an_error() {
+        let mut mem = Memory::new();
+
+        assert!(mem.read(0, DataSize::Byte).is_err());
+        assert!(mem.read(DATA_START, DataSize::Byte).is_err());
+        assert!(mem.write(STACK_TOP - 2, DataSize::Dword, RegisterData::Value(0)).is_err());
+        assert!(mem.read(STACK_TOP - 4, DataSize::Dword).is_ok());
+    }
+}
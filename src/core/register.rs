@@ -1,20 +1,22 @@
-use std::ops::{AddAssign, SubAssign, MulAssign, DivAssign, ShlAssign, ShrAssign, BitAndAssign, BitOrAssign, BitXorAssign};
+use std::ops::{AddAssign, SubAssign};
 
 use strum::{EnumString, EnumIter, Display};
 
+use super::memory::DataSize;
+
 #[derive(Clone, Copy)]
 pub struct Register {
     pub data: RegisterData
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RegisterData {
     Value(i32),
     Pointer(i32),
 }
 
 /// https://www.tutorialspoint.com/assembly_programming/assembly_registers.htm
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 #[derive(EnumString, EnumIter, Display)]
 #[strum(serialize_all = "snake_case")]
 pub enum RegisterName {
@@ -24,10 +26,28 @@ pub enum RegisterName {
     Ecx,
     Edx,
 
+// Indices
+    Esi,
+    Edi,
+
 // Pointers
     Eip,
     Esp,
     Ebp,
+
+// Sub-registers
+    Ax,
+    Al,
+    Ah,
+    Bx,
+    Bl,
+    Bh,
+    Cx,
+    Cl,
+    Ch,
+    Dx,
+    Dl,
+    Dh,
 }
 
 impl Register {
@@ -36,142 +56,96 @@ impl Register {
     }
 
     pub fn get_raw(&self) -> i32 {
-        match self.data {
-            RegisterData::Value(i) => i,
-            RegisterData::Pointer(p) => p,
+        self.data.get_raw()
+    }
+}
+
+impl RegisterName {
+    /// Gets the 32-bit register this register is a view of, along with the
+    /// width of the view and the bit offset it starts at.
+    /// 32-bit registers are a view of themselves.
+    /// 
+    /// ```text
+    /// 31              15      7      0
+    /// |      eax      |  ah   |  al  |
+    ///                 |      ax      |
+    /// ```
+    pub fn alias(&self) -> (RegisterName, DataSize, u32) {
+        match self {
+            RegisterName::Ax => (RegisterName::Eax, DataSize::Word, 0),
+            RegisterName::Al => (RegisterName::Eax, DataSize::Byte, 0),
+            RegisterName::Ah => (RegisterName::Eax, DataSize::Byte, 8),
+            RegisterName::Bx => (RegisterName::Ebx, DataSize::Word, 0),
+            RegisterName::Bl => (RegisterName::Ebx, DataSize::Byte, 0),
+            RegisterName::Bh => (RegisterName::Ebx, DataSize::Byte, 8),
+            RegisterName::Cx => (RegisterName::Ecx, DataSize::Word, 0),
+            RegisterName::Cl => (RegisterName::Ecx, DataSize::Byte, 0),
+            RegisterName::Ch => (RegisterName::Ecx, DataSize::Byte, 8),
+            RegisterName::Dx => (RegisterName::Edx, DataSize::Word, 0),
+            RegisterName::Dl => (RegisterName::Edx, DataSize::Byte, 0),
+            RegisterName::Dh => (RegisterName::Edx, DataSize::Byte, 8),
+            _ => (*self, DataSize::Dword, 0),
         }
     }
-}
-
 
-impl AddAssign<i32> for Register {
-    fn add_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i + rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p + rhs),
-        }
+    /// The width of the register.
+    pub fn size(&self) -> DataSize {
+        self.alias().1
     }
 }
 
-impl AddAssign for Register {
-    fn add_assign(&mut self, rhs: Self) {
-        self.add_assign(rhs.get_raw())
-    }
-}
-
-impl SubAssign<i32> for Register {
-    fn sub_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i - rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p - rhs),
-        }
-    }
-}
-
-impl SubAssign for Register {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.sub_assign(rhs.get_raw())
-    }
-}
-
-impl MulAssign<i32> for Register {
-    fn mul_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i * rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p * rhs),
-        }
-    }
-}
-
-impl MulAssign for Register {
-    fn mul_assign(&mut self, rhs: Self) {
-        self.mul_assign(rhs.get_raw())
-    }
-}
-
-impl DivAssign<i32> for Register {
-    fn div_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i / rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p / rhs),
-        }
-    }
-}
-
-impl DivAssign for Register {
-    fn div_assign(&mut self, rhs: Self) {
-        self.div_assign(rhs.get_raw())
-    }
-}
-
-impl ShlAssign<i32> for Register {
-    fn shl_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i << rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p << rhs),
+impl RegisterData {
+    pub fn get_raw(&self) -> i32 {
+        match *self {
+            RegisterData::Value(i) => i,
+            RegisterData::Pointer(p) => p,
         }
     }
-}
 
-impl ShrAssign<i32> for Register {
-    fn shr_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i >> rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p >> rhs),
+    /// Replaces the raw data while keeping it a value or a pointer.
+    pub fn with_raw(&self, raw: i32) -> Self {
+        match self {
+            RegisterData::Value(_) => RegisterData::Value(raw),
+            RegisterData::Pointer(_) => RegisterData::Pointer(raw),
         }
     }
 }
 
-impl BitAndAssign<i32> for Register {
-    fn bitand_assign(&mut self, rhs: i32) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i & rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p & rhs),
-        }
+impl Default for Register {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl BitAndAssign for Register {
-    fn bitand_assign(&mut self, rhs: Self) {
-        self.bitand_assign(rhs.get_raw())
-    }
-}
+// Adjusting a register wraps around like a 32-bit hardware register, as when moving the stack pointer.
+// Flags are not affected, flag-setting arithmetic lives in `core::alu`.
 
-impl BitOrAssign<i32> for Register {
-    fn bitor_assign(&mut self, rhs: i32) {
+impl AddAssign<i32> for Register {
+    fn add_assign(&mut self, rhs: i32) {
         match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i | rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p | rhs),
+            RegisterData::Value(i) => self.data = RegisterData::Value(i.wrapping_add(rhs)),
+            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p.wrapping_add(rhs)),
         }
     }
 }
 
-impl BitOrAssign for Register {
-    fn bitor_assign(&mut self, rhs: Self) {
-        self.bitor_assign(rhs.get_raw())
-    }
-}
-
-impl Register {
-    pub fn bitnot_assign_self(&mut self) {
-        match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(!i),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(!p),
-        }
+impl AddAssign for Register {
+    fn add_assign(&mut self, rhs: Self) {
+        self.add_assign(rhs.get_raw())
     }
 }
 
-impl BitXorAssign<i32> for Register {
-    fn bitxor_assign(&mut self, rhs: i32) {
+impl SubAssign<i32> for Register {
+    fn sub_assign(&mut self, rhs: i32) {
         match self.data {
-            RegisterData::Value(i) => self.data = RegisterData::Value(i ^ rhs),
-            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p ^ rhs),
+            RegisterData::Value(i) => self.data = RegisterData::Value(i.wrapping_sub(rhs)),
+            RegisterData::Pointer(p) => self.data = RegisterData::Pointer(p.wrapping_sub(rhs)),
         }
     }
 }
 
-impl BitXorAssign for Register {
-    fn bitxor_assign(&mut self, rhs: Self) {
-        self.bitxor_assign(rhs.get_raw())
+impl SubAssign for Register {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.sub_assign(rhs.get_raw())
     }
 }
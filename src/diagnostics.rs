@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+use crate::{parser::{error::ParserError, span::Span}, interpreter::error::AsmrRuntimeError};
+
+/// An error along with the source it points at, rendered in the style of rustc.
+///
+/// # Examples
+///
+/// ```
+/// # use assembl_really::{parse_lines, diagnostics::Diagnostic};
+/// let source = ["mov eax, 1", "call #2"];
+/// let error = parse_lines(source.iter()).unwrap_err();
+///
+/// let rendered = Diagnostic::from(&error).render("main.asmr", &source, false);
+/// assert!(rendered.starts_with("error: Could not parse token `#2`."));
+/// assert!(rendered.contains(" --> main.asmr:2:6"));
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub primary: Label,
+    pub labels: Vec<Label>,
+    pub notes: Vec<Note>,
+}
+
+/// Points at a span of source, which is underlined with `^` for the primary label or `-` for secondary labels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub file: Option<String>, // The file of the primary label when None
+    pub line_number: usize,   // 1-based, as shown to users
+    pub span: Option<Span>,   // The whole line when None
+    pub message: String,      // Printed after the underline, may be empty
+}
+
+impl Label {
+    pub fn new(line_number: usize, span: Option<Span>, message: impl Into<String>) -> Self {
+        Label { file: None, line_number, span, message: message.into() }
+    }
+
+    /// Points the label into another file, such as where an included macro is defined.
+    pub fn in_file(mut self, file: Option<String>) -> Self {
+        self.file = file;
+        self
+    }
+}
+
+/// A message printed below the source, e.g. `= help: close the procedure with endp`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Note {
+    Note(String),
+    Help(String),
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, primary: Label) -> Self {
+        Diagnostic { message: message.into(), primary, labels: Vec::new(), notes: Vec::new() }
+    }
+
+    /// Adds a secondary label, such as where a duplicate name was first defined.
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Renders the diagnostic for a terminal, taking the offending lines from `source`.
+    /// `colour` adds ANSI colours, which should only be used when writing to a TTY.
+    pub fn render<S: AsRef<str>>(&self, file_name: &str, source: &[S], colour: bool) -> String {
+        self.render_files(&[(file_name, source)], colour)
+    }
+
+    /// Renders the diagnostic like [Diagnostic::render], taking the lines of each label from the file it points into.
+    /// Labels without a file point into the file of the primary label, which is the first of `files` when it has no file either.
+    pub fn render_files<S: AsRef<str>>(&self, files: &[(&str, &[S])], colour: bool) -> String {
+        let paint = |style: &str, s: &str| if colour { format!("\x1b[{style}m{s}\x1b[0m") } else { s.to_string() };
+        let (red, blue, bold) = ("1;31", "1;34", "1");
+
+        let primary_file = self.primary.file.as_deref().or(files.first().map(|(name, _)| *name)).unwrap_or_default();
+        let file_of = |label: &Label| label.file.clone().unwrap_or_else(|| primary_file.to_string());
+
+        // Labels are grouped by file, starting with the file of the primary label
+        let mut labels: Vec<_> = std::iter::once((&self.primary, true)).chain(self.labels.iter().map(|l| (l, false))).collect();
+        labels.sort_by_key(|(l, _)| (file_of(l) != primary_file, file_of(l), l.line_number));
+
+        let width = labels.iter().map(|(l, _)| l.line_number.to_string().len()).max().unwrap_or(1);
+        let pad = " ".repeat(width);
+        let gutter = paint(blue, "|");
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{}{}", paint(red, "error"), paint(bold, &format!(": {}", self.message)));
+
+        let mut current_file: Option<String> = None;
+        let mut previous: Option<usize> = None;
+        for (label, is_primary) in &labels {
+            // Start each file with its location, using `:::` for files other than that of the primary label
+            let file = file_of(label);
+            if current_file.as_ref() != Some(&file) {
+                let (arrow, location) = if current_file.is_none() { ("-->", &self.primary) } else { (":::", *label) };
+                let column = location.span.map_or(1, |s| s.column + 1);
+                let _ = writeln!(out, "{pad}{} {file}:{}:{column}", paint(blue, arrow), location.line_number);
+                let _ = writeln!(out, "{pad} {gutter}");
+                current_file = Some(file.clone());
+                previous = None;
+            }
+
+            let source = files.iter().find(|(name, _)| *name == file).map(|(_, source)| *source);
+            let Some(text) = label.line_number.checked_sub(1).and_then(|i| source?.get(i)) else { continue };
+            let text = text.as_ref().replace('\t', " ");
+
+            // Print each line once, with every label on it underneath
+            if previous != Some(label.line_number) {
+                if previous.is_some_and(|p| label.line_number > p + 1) {
+                    let _ = writeln!(out, "{}", paint(blue, "..."));
+                }
+                let _ = writeln!(out, "{} {gutter} {text}", paint(blue, &format!("{:>width$}", label.line_number)));
+                previous = Some(label.line_number);
+            }
+
+            let (start, end) = match label.span {
+                Some(span) => (span.column, span.column_end.max(span.column + 1)),
+                None => {
+                    let start = text.len() - text.trim_start().len();
+                    (start, start.max(text.trim_end().chars().count()))
+                },
+            };
+            let (mark, style) = if *is_primary { ("^", red) } else { ("-", blue) };
+            let underline = paint(style, format!("{}{}", mark.repeat(end - start), if label.message.is_empty() { String::new() } else { format!(" {}", label.message) }).as_str());
+            let _ = writeln!(out, "{pad} {gutter} {}{underline}", " ".repeat(start));
+        }
+
+        for note in &self.notes {
+            let (kind, message) = match note {
+                Note::Note(message) => ("note", message),
+                Note::Help(message) => ("help", message),
+            };
+            let _ = writeln!(out, "{pad} {} {message}", paint(bold, &format!("= {kind}:")));
+        }
+
+        out
+    }
+}
+
+impl From<&ParserError> for Diagnostic {
+    fn from(e: &ParserError) -> Self {
+        Diagnostic {
+            message: e.cause.clone(),
+            primary: Label::new(e.line_number as usize, Some(e.span), "").in_file(e.file.clone()),
+            labels: e.labels.clone(),
+            notes: e.notes.clone(),
+        }
+    }
+}
+
+impl From<&AsmrRuntimeError> for Diagnostic {
+    fn from(e: &AsmrRuntimeError) -> Self {
+        Diagnostic {
+            message: e.cause.clone(),
+            primary: Label::new(e.line_number + 1, e.span, "").in_file(e.file.clone()),
+            labels: e.labels.clone(),
+            notes: e.notes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_underlines_the_primary_span() {
+        let source = ["nop", "mov eax, 0xq ; load"];
+        let diagnostic = Diagnostic::new("Could not parse `0xq` as a hexadecimal value.", Label::new(2, Some(Span::new(source[1], 9, 12)), "not a hexadecimal value"));
+
+        assert_eq!(diagnostic.render("main.asmr", &source, false), [
+            "error: Could not parse `0xq` as a hexadecimal value.",
+            " --> main.asmr:2:10",
+            "  |",
+            "2 | mov eax, 0xq ; load",
+            "  |          ^^^ not a hexadecimal value",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn render_shows_secondary_labels_and_notes() {
+        let source = ["proc f", "  nop", "", "  proc g", "endp"];
+        let diagnostic = Diagnostic::new("Cannot nest procedures.", Label::new(4, Some(Span::new(source[3], 7, 8)), ""))
+            .with_label(Label::new(1, Some(Span::new(source[0], 5, 6)), "the previous procedure starts here"))
+            .with_label(Label::new(5, None, ""))
+            .with_note(Note::Note("procedures cannot be nested".to_string()))
+            .with_note(Note::Help("close the previous procedure with `endp`".to_string()));
+
+        assert_eq!(diagnostic.render("main.asmr", &source, false), [
+            "error: Cannot nest procedures.",
+            " --> main.asmr:4:8",
+            "  |",
+            "1 | proc f",
+            "  |      - the previous procedure starts here",
+            "...",
+            "4 |   proc g",
+            "  |        ^",
+            "5 | endp",
+            "  | ----",
+            "  = note: procedures cannot be nested",
+            "  = help: close the previous procedure with `endp`",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn render_colours_only_when_asked() {
+        let diagnostic = Diagnostic::new("oops", Label::new(1, None, ""));
+
+        assert!(!diagnostic.render("a.asmr", &["nop"], false).contains('\x1b'));
+        assert!(diagnostic.render("a.asmr", &["nop"], true).contains("\x1b[1;31merror\x1b[0m"));
+    }
+}
@@ -3,11 +3,16 @@ pub mod error;
 use std::process::ExitCode;
 
 use error::AsmrRuntimeError;
-use crate::parser::{line::{Line, MemType}, token::Token, instruction::Instruction};
-use crate::core::{register::{RegisterData, RegisterName}, builtins::call_builtin_fn, executing_context::ExecutingContext, flags::Flag};
+use crate::parser::{expression::Expression, line::{Line, MemType}, token::{Token, EffectiveAddress}, instruction::Instruction, prefix::Prefix, span::{Span, Spanned}};
+use crate::diagnostics::Label;
+use crate::core::{register::{RegisterData, RegisterName}, builtins::call_builtin_fn, executing_context::ExecutingContext, flags::{Flag, Flags, Condition}, memory::{DataSize, DATA_START}, alu};
 
 /// Executes parsed asmr code.
 /// 
+/// # Errors
+/// Any fault raised by the program, such as an unknown identifier, an unmapped memory access
+/// or a stack overflow, is returned as an [AsmrRuntimeError]. Execution never panics.
+/// 
 /// # Examples
 /// 
 /// ```
@@ -21,614 +26,1960 @@ pub fn execute(lines: Vec<Line>) -> Result<ExitCode, AsmrRuntimeError>
     // Manage the current context for execution
     // Stores registers values, variable buffers, label pointers, instruction pointers
     let mut ctx = ExecutingContext::new();
-    ctx.stack.push(RegisterData::Pointer(lines.len() as i32)); // Push final ret pointer (EOF)
-    ctx.stack.push(RegisterData::Pointer(0)); // Push initial ebp value
-    *ctx.registers.get(&RegisterName::Esp).unwrap().borrow_mut() += 1; // Point esp to ebp
+    run(&lines, &mut ctx)?;
+
+    // Return exit code from eax
+    let eax = ctx.registers.get(&RegisterName::Eax).unwrap().borrow().get_raw();
+    Ok(ExitCode::from(eax as u8))
+}
+
+/// Runs parsed asmr code until it returns from the top level or reaches EOF.
+fn run(lines: &[Line], ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError>
+{
+    ctx.push(RegisterData::Pointer(lines.len() as i32))?; // Push final ret pointer (EOF)
+    ctx.push(RegisterData::Pointer(0))?; // Push initial ebp value
+    handle_instruction(&Instruction::Mov, &[ Token::Register(RegisterName::Ebp).into(), Token::Register(RegisterName::Esp).into() ], ctx)?; // Point ebp to the initial frame
 
     // Get list of all label addresses
     for i in 0..lines.len() {
-        if let Some(Line::Label(label)) = lines.get(i) {
+        if let Some(Line::Label(label) | Line::Proc { name: label, .. }) = lines.get(i) {
             ctx.labels.insert(label.to_string(), i);
         }
     }
 
+    // Lay out every variable in the data segment, in declaration order, before execution starts
+    // Constants are evaluated in the same pass, so `$` is the end of the data declared before them
+    for (i, line) in lines.iter().enumerate() {
+        if let Line::Equ { identifier, value } = line {
+            ctx.ptr = i;
+            let data = evaluate(value, value.span, true, ctx)?;
+            ctx.constants.insert(identifier.to_string(), data);
+        }
+        else if let Line::Variable { identifier, mem_type, params } = line {
+            ctx.ptr = i;
+            handle_variable(identifier, mem_type, params, ctx).map_err(|e| {
+                // Point a duplicate identifier at its first definition
+                let first = lines[..i].iter().enumerate().find_map(|(j, line)| match line {
+                    Line::Variable { identifier: first, .. } if first.node == identifier.node => Some(Label::new(j + 1, Some(first.span), "first defined here")),
+                    _ => None,
+                });
+
+                let e = e.with_span(identifier.span);
+                match first {
+                    Some(label) => e.with_label(label),
+                    None => e,
+                }
+            })?;
+        }
+    }
+
+    // Evaluate the expressions of every instruction once, including memory operand offsets,
+    // so errors such as division by zero are raised before execution
+    for (i, line) in lines.iter().enumerate() {
+        if let Line::Instruction { params, .. } = line {
+            ctx.ptr = i;
+            for param in params {
+                match &param.node {
+                    Token::Expression(e) => { evaluate(e, param.span, false, ctx)?; },
+                    Token::Memory(EffectiveAddress { offset: Some(offset), .. }) => { evaluate(&offset.node, offset.span, false, ctx)?; },
+                    _ => {},
+                }
+            }
+        }
+    }
+    ctx.ptr = 0;
+
     loop {
         match lines.get(ctx.ptr) {
-            Some(Line::Instruction { instruction, params }) => handle_instruction(instruction, params, &mut ctx)?,
-            Some(Line::Variable { identifier, mem_type, params }) => handle_variable(identifier, mem_type, params, &mut ctx)?,
-            Some(_) => {}, // Labels already handled, ignore blank lines
-            None => return Ok(ExitCode::from(ctx.registers.get(&RegisterName::Eax).unwrap().borrow().get_raw() as u8)), // EOF -> Return exit code from eax
+            Some(Line::Instruction { prefix: None, instruction, params }) => handle_instruction(instruction, params, ctx)
+                .map_err(|e| e.with_span(instruction.span))?,
+            Some(Line::Instruction { prefix: Some(prefix), instruction, params }) => repeat(prefix, instruction, params, ctx)
+                .map_err(|e| e.with_span(instruction.span))?,
+            Some(Line::Proc { locals, .. }) => {
+                // Reserve the locals below the frame created by `call`
+                let esp = ctx.get_register(RegisterName::Esp);
+                ctx.set_register(RegisterName::Esp, esp.with_raw(esp.get_raw().wrapping_sub(4 * locals.len() as i32)));
+            },
+            Some(Line::Invalid(span)) => return Err(AsmrRuntimeError::from(ctx.ptr, "cannot execute a line that failed to parse").with_span(*span)),
+            Some(_) => {}, // Labels and variables already handled, ignore blank lines and the rest of procedures
+            None => return Ok(()), // EOF
         };
 
         // Set the current instruction pointer to the next line to execute
         // Increment the next instruction pointer
         ctx.ptr = ctx.next;
         ctx.registers.get(&RegisterName::Eip).unwrap().borrow_mut().data = RegisterData::Pointer(ctx.ptr as i32);
-        ctx.next += 1;
+        ctx.next = ctx.next.wrapping_add(1);
     }
 }
 
 /// Handles an instruction line
-fn handle_instruction(instruction: &Instruction, params: &Vec<Token>, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+fn handle_instruction(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    // The parser has already checked the operands, but instructions can also be built by the interpreter itself
+    let form = instruction.match_signature(params).map_err(|cause| AsmrRuntimeError::from(ctx.ptr, cause))?;
+
     match instruction {
         Instruction::Nop => Ok(()),
 
     // Stack
         Instruction::Push => {
-            if params.len() == 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`push` takes parameters of type <...Register>")) }
-
             for param in params {
-                if let Token::Register(r) = param {
-                    ctx.stack.push(ctx.registers.get(r).unwrap().borrow_mut().data);
-                    *ctx.registers.get(&RegisterName::Esp).unwrap().borrow_mut() += 1
-                } else {
-                    return Err(AsmrRuntimeError::from(ctx.ptr, "`push` takes parameters of type <...Register>"))
-                }
+                let data = read_operand(param, DataSize::Dword, ctx)?;
+                ctx.push(data)?;
             }
 
             Ok(())
         },
         Instruction::Pop => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`pop` takes one parameter of type <Register>")) }
-
-            let last = ctx.stack.remove(ctx.registers.get(&RegisterName::Esp).unwrap().borrow().get_raw() as usize);
-            *ctx.registers.get(&RegisterName::Esp).unwrap().borrow_mut() -= 1;
+            // Pops in the order given, so `push eax, ebx` is undone by `pop ebx, eax`
+            for param in params {
+                let last = ctx.pop()?;
+                write_operand(param, DataSize::Dword, last, ctx)?;
+            }
 
-            if let Some(Token::Register(reg)) = params.get(0) {
-                let mut reg = ctx.registers.get(reg).unwrap().borrow_mut();
-                reg.data = last;
-                Ok(())
+            Ok(())
+        },
+        Instruction::Pusha => {
+            // esp is pushed with its value from before the first push
+            let esp = ctx.get_register(RegisterName::Esp);
+            for r in [RegisterName::Eax, RegisterName::Ecx, RegisterName::Edx, RegisterName::Ebx] {
+                ctx.push(ctx.get_register(r))?;
             }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`pop` takes one parameter of type <Register>"))
+            ctx.push(esp)?;
+            for r in [RegisterName::Ebp, RegisterName::Esi, RegisterName::Edi] {
+                ctx.push(ctx.get_register(r))?;
+            }
+
+            Ok(())
+        },
+        Instruction::Popa => {
+            // The saved esp is discarded
+            for r in [RegisterName::Edi, RegisterName::Esi, RegisterName::Ebp, RegisterName::Esp, RegisterName::Ebx, RegisterName::Edx, RegisterName::Ecx, RegisterName::Eax] {
+                let data = ctx.pop()?;
+                if r != RegisterName::Esp { ctx.set_register(r, data) }
             }
+
+            Ok(())
+        },
+        Instruction::Pushf => ctx.push(RegisterData::Value(ctx.flags.eflags() as i32)),
+        Instruction::Popf => {
+            ctx.flags = Flags::from_eflags(ctx.pop()?.get_raw() as u32);
+            Ok(())
         },
 
     // Move
         Instruction::Mov => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`mov` takes parameters of type <Register, [Register | Identifier | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                match params.get(1) {
-                    Some(Token::Numeric(i)) => Ok(lhs.data = RegisterData::Value(*i)),
-                    Some(Token::Identifier(s)) => Ok(lhs.data = RegisterData::Pointer(*ctx.symtab.get(s).expect(format!("unknown identifier `{s}`").as_str()))),
-                    Some(Token::Register(r)) => Ok(lhs.data = ctx.registers.get(r).unwrap().borrow().data),
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`mov` takes parameters of type <Register, [Register | Identifier | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`mov` takes parameters of type <Register, [Register | Identifier | Numeric]>"))
-            }
+            let [lhs, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+            let size = operand_size(params, ctx)?;
+            let data = read_operand(rhs, size, ctx)?;
+            write_operand(lhs, size, data, ctx)
         },
-        Instruction::Xchg => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`xchg` takes parameters of type <Register, Register>")) }
+        Instruction::Lea => {
+            let [lhs, Spanned { node: Token::Memory(ea), .. }] = params else { return Err(invalid_operands(instruction, ctx)) };
 
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                if let Some(Token::Register(rhs)) = params.get(1) {
-                    let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                    let mut rhs = ctx.registers.get(rhs).unwrap().borrow_mut();
+            // The address points into memory when it is computed from a variable or a pointer
+            let addr = effective_address(ea, ctx)? as i32;
+            let is_pointer = ea.symbol.is_some() || [ea.base, ea.index.map(|(index, _)| index)].iter().flatten()
+                .any(|r| matches!(ctx.get_register(*r), RegisterData::Pointer(_)));
 
-                    let tmp = lhs.data;
-                    lhs.data = rhs.data;
-                    rhs.data = tmp;
+            let data = if is_pointer { RegisterData::Pointer(addr) } else { RegisterData::Value(addr) };
+            write_operand(lhs, DataSize::Dword, data, ctx)
+        },
+        Instruction::Movsx => extend(instruction, params, ctx, alu::sign_extend),
+        Instruction::Movzx => extend(instruction, params, ctx, |value, size| value & alu::mask(size)),
+        Instruction::Xchg => {
+            let [lhs, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
 
-                    Ok(())
-                }
-                else {
-                    Err(AsmrRuntimeError::from(ctx.ptr, "`xchg` takes parameters of type <Register, Register>"))
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`xchg` takes parameters of type <Register, Register>"))
-            }
+            let size = operand_size(params, ctx)?;
+            let lhs_data = read_operand(lhs, size, ctx)?;
+            let rhs_data = read_operand(rhs, size, ctx)?;
+
+            write_operand(lhs, size, rhs_data, ctx)?;
+            write_operand(rhs, size, lhs_data, ctx)
         },
 
     // Arithmetic
-        Instruction::Add => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`add` takes parameters of type <Register, [Register | Numeric]>")); }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() += *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs += *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`add` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`add` takes parameters of type <Register, [Register | Numeric]>"))
-            }
-        },
-        Instruction::Sub => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`sub` takes parameters of type <Register, [Register | Numeric]>")); }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() -= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs -= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`sub` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`sub` takes parameters of type <Register, [Register | Numeric]>"))
-            }
-        },
-        Instruction::Mul => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`mul` takes parameters of type <Register, [Register | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() *= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs *= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`mul` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`mul` takes parameters of type <Register, [Register | Numeric]>"))
+        Instruction::Add => binary_op(instruction, params, ctx, alu::add),
+        Instruction::Sub => binary_op(instruction, params, ctx, alu::sub),
+        Instruction::Adc => binary_op(instruction, params, ctx, alu::adc),
+        Instruction::Sbb => binary_op(instruction, params, ctx, alu::sbb),
+        Instruction::Mul => widening_multiply(instruction, params, ctx, alu::mul),
+        Instruction::Imul => {
+            // Dispatch on the operand form, see `Instruction::signatures`
+            match (form, params) {
+                (0, _) => widening_multiply(instruction, params, ctx, alu::imul),
+                (1, _) => binary_op(instruction, params, ctx, |lhs, rhs, size, flags| alu::imul(lhs, rhs, size, flags).0),
+                (_, [lhs, rhs, Spanned { node: Token::Numeric(imm), .. }]) => {
+                    // Three operand form: lhs = rhs * imm
+                    let size = operand_size(&params[..2], ctx)?;
+                    let rhs = read_operand(rhs, size, ctx)?.get_raw();
+                    let (result, _) = alu::imul(rhs as u32, *imm as u32, size, &mut ctx.flags);
+                    write_operand(lhs, size, RegisterData::Value(result as i32), ctx)
+                },
+                _ => Err(invalid_operands(instruction, ctx)),
             }
         },
-        Instruction::Div => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`div` takes parameters of type <Register, [Register | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() /= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs /= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`div` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`div` takes parameters of type <Register, [Register | Numeric]>"))
-            }
+        Instruction::Div => divide(instruction, params, ctx, alu::div),
+        Instruction::Idiv => divide(instruction, params, ctx, alu::idiv),
+        Instruction::Cdq => {
+            // Fill edx with the sign bit of eax
+            let eax = ctx.get_register(RegisterName::Eax).get_raw();
+            ctx.set_register(RegisterName::Edx, RegisterData::Value(eax >> 31));
+            Ok(())
         },
-        Instruction::Inc => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`inc` takes one parameter of type <Register>")) }
+        Instruction::Inc => unary_op(instruction, params, ctx, alu::inc),
+        Instruction::Dec => unary_op(instruction, params, ctx, alu::dec),
+        Instruction::Neg => unary_op(instruction, params, ctx, alu::neg),
 
-            if let Some(Token::Register(reg)) = params.get(0) {
-                *ctx.registers.get(reg).unwrap().borrow_mut() += 1;
-                Ok(())
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`inc` takes one parameter of type <Register>"))
-            }
-        },
-        Instruction::Dec => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`dec` takes one parameter of type <Register>")) }
-    
-            if let Some(Token::Register(reg)) = params.get(0) {
-                *ctx.registers.get(reg).unwrap().borrow_mut() -= 1;
-                Ok(())
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`dec` takes one parameter of type <Register>"))
-            }
-        },
-        Instruction::Shl => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`shl` takes parameters of type <Register, Numeric>")) }
+    // Shifts and rotates
+        Instruction::Shl => shift_op(instruction, params, ctx, alu::shl),
+        Instruction::Shr => shift_op(instruction, params, ctx, alu::shr),
+        Instruction::Sar => shift_op(instruction, params, ctx, alu::sar),
+        Instruction::Rol => shift_op(instruction, params, ctx, alu::rol),
+        Instruction::Ror => shift_op(instruction, params, ctx, alu::ror),
+        Instruction::Rcl => shift_op(instruction, params, ctx, alu::rcl),
+        Instruction::Rcr => shift_op(instruction, params, ctx, alu::rcr),
+        Instruction::Shld => double_shift_op(instruction, params, ctx, alu::shld),
+        Instruction::Shrd => double_shift_op(instruction, params, ctx, alu::shrd),
 
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                if let Some(Token::Numeric(rhs)) = params.get(1) {
-                    if *rhs < 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`shl` requires the parameter <Numeric> to be greater than or equal to 0")) }
-                    *ctx.registers.get(lhs).unwrap().borrow_mut() <<= *rhs;
-                    Ok(())
-                }
-                else {
-                    Err(AsmrRuntimeError::from(ctx.ptr, "`shl` takes parameters of type <Register, Numeric>"))
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`shl` takes parameters of type <Register, Numeric>"))
-            }
-        },
-        Instruction::Shr => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`shr` takes parameters of type <Register, Numeric>")) }
+    // Strings
+        Instruction::Movsb | Instruction::Movsd |
+        Instruction::Stosb | Instruction::Stosd |
+        Instruction::Lodsb | Instruction::Lodsd |
+        Instruction::Cmpsb | Instruction::Scasb => string_op(instruction, ctx),
 
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                if let Some(Token::Numeric(rhs)) = params.get(1) {
-                    if *rhs < 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`shr` requires the parameter <Numeric> to be greater than or equal to 0")) }
-                    *ctx.registers.get(lhs).unwrap().borrow_mut() >>= *rhs;
-                    Ok(())
-                }
-                else {
-                    Err(AsmrRuntimeError::from(ctx.ptr, "`shr` takes parameters of type <Register, Numeric>"))
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`shr` takes parameters of type <Register, Numeric>"))
+    // Flags
+        Instruction::Clc => set_flag(ctx, Flag::CF, |_| false),
+        Instruction::Stc => set_flag(ctx, Flag::CF, |_| true),
+        Instruction::Cmc => set_flag(ctx, Flag::CF, |cf| !cf),
+        Instruction::Cld => set_flag(ctx, Flag::DF, |_| false),
+        Instruction::Std => set_flag(ctx, Flag::DF, |_| true),
+        Instruction::Lahf => {
+            // ah = SF:ZF:0:AF:0:PF:1:CF
+            ctx.set_register(RegisterName::Ah, RegisterData::Value(ctx.flags.eflags() as i32));
+            Ok(())
+        },
+        Instruction::Sahf => {
+            let ah = ctx.get_register(RegisterName::Ah).get_raw() as u32;
+            for flag in [Flag::SF, Flag::ZF, Flag::AF, Flag::PF, Flag::CF] {
+                ctx.flags.assign(flag, ah & flag as u32 != 0);
             }
+            Ok(())
         },
 
     // Comparisons
         Instruction::Cmp => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`cmp` takes parameters of type <Register, [Register | Numeric]>")) }
-            
-            let mut cmp = |lhs: i32, rhs: i32| {
-                if lhs == rhs {
-                    ctx.flags.set(Flag::ZF);
-                    ctx.flags.unset(Flag::CF);
-                }
-                else if lhs > rhs {
-                    ctx.flags.unset(Flag::ZF);
-                    ctx.flags.unset(Flag::CF);
-                }
-                else {
-                    ctx.flags.unset(Flag::ZF);
-                    ctx.flags.set(Flag::CF);
-                }
-            };
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => {
-                        let lhs = ctx.registers.get(lhs).unwrap().borrow().get_raw();
-                        cmp(lhs, *rhs);
-                        Ok(())
-                    },
-                    Some(Token::Register(rhs)) => {
-                        let lhs = ctx.registers.get(lhs).unwrap().borrow().get_raw();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow().get_raw();
-                        cmp(lhs, rhs);
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`cmp` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`cmp` takes parameters of type <Register, Register>"))
-            }
-        },
-        Instruction::And => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`and` takes parameters of type <Register, [Register | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() &= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs &= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`and` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`and` takes parameters of type <Register, [Register | Numeric]>"))
-            }
-        },
-        Instruction::Or => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`or` takes parameters of type <Register, [Register | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() |= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs |= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`or` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`or` takes parameters of type <Register, [Register | Numeric]>"))
-            }
-        },
-        Instruction::Not => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`not` takes one parameter of type <Register>")) }
-
-            if let Some(Token::Register(reg)) = params.get(0) {
-                ctx.registers.get(reg).unwrap().borrow_mut().bitnot_assign_self();
-                Ok(())
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`not` takes one parameter of type <Register>"))
-            }
-        },
-        Instruction::Xor => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`xor` takes parameters of type <Register, [Register | Numeric]>")) }
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => Ok(*ctx.registers.get(lhs).unwrap().borrow_mut() ^= *rhs),
-                    Some(Token::Register(rhs)) => {
-                        let mut lhs = ctx.registers.get(lhs).unwrap().borrow_mut();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow();
-                        *lhs ^= *rhs;
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`xor` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`xor` takes parameters of type <Register, [Register | Numeric]>"))
-            }
+            let (lhs, rhs, size) = source_operands(instruction, params, ctx)?;
+            alu::sub(lhs, rhs, size, &mut ctx.flags);
+            Ok(())
         },
+        Instruction::And => binary_op(instruction, params, ctx, alu::and),
+        Instruction::Or => binary_op(instruction, params, ctx, alu::or),
+        Instruction::Not => unary_op(instruction, params, ctx, |value, _, _| !value), // Flags are not affected
+        Instruction::Xor => binary_op(instruction, params, ctx, alu::xor),
         Instruction::Test => {
-            if params.len() != 2 { return Err(AsmrRuntimeError::from(ctx.ptr, "`test` takes parameters of type <Register, [Register | Numeric]>")) }
-            
-            let mut test = |lhs: i32, rhs: i32| {
-                ctx.flags.unset(Flag::CF);
-                ctx.flags.unset(Flag::ZF);
-
-                let and = lhs & rhs;
-                if and & (1 << 31) == 0 { ctx.flags.unset(Flag::SF) } else { ctx.flags.set(Flag::SF) }
-
-                if and == 0 {
-                    ctx.flags.set(Flag::ZF);
-                }
-            };
-
-            if let Some(Token::Register(lhs)) = params.get(0) {
-                match params.get(1) {
-                    Some(Token::Numeric(rhs)) => {
-                        let lhs = ctx.registers.get(lhs).unwrap().borrow().get_raw();
-                        test(lhs, *rhs);
-                        Ok(())
-                    },
-                    Some(Token::Register(rhs)) => {
-                        let lhs = ctx.registers.get(lhs).unwrap().borrow().get_raw();
-                        let rhs = ctx.registers.get(rhs).unwrap().borrow().get_raw();
-                        test(lhs, rhs);
-                        Ok(())
-                    },
-                    Some(_) => Err(AsmrRuntimeError::from(ctx.ptr, "`test` takes parameters of type <Register, [Register | Numeric]>")),
-                    None => unreachable!("params.len() == 2"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`test` takes parameters of type <Register, Register>"))
-            }
+            let (lhs, rhs, size) = source_operands(instruction, params, ctx)?;
+            alu::and(lhs, rhs, size, &mut ctx.flags);
+            Ok(())
         },
 
     // Jumps
         Instruction::Jmp => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jmp` takes one parameter of type <Identifier>")) }
-
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                match addr {
-                    Some(addr) => Ok(ctx.next = *addr),
-                    None => panic!("no address associated with identifier `{s}`"),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jmp` takes one parameter of type <Identifier>"))
-            }
-        },
-        Instruction::Jz => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jz` takes one parameter of type <Identifier>")) }
+            let [target] = params else { return Err(invalid_operands(instruction, ctx)) };
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jz_flags_set = ctx.flags.get(Flag::ZF);
-                match addr {
-                    Some(addr) if jz_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jz` takes one parameter of type <Identifier>"))
-            }
+            ctx.next = jump_target(instruction, target, ctx)?;
+            Ok(())
         },
-        Instruction::Jnz => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jnz` takes one parameter of type <Identifier>")) }
-
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jnz_flags_set = !ctx.flags.get(Flag::ZF);
-                match addr {
-                    Some(addr) if jnz_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jnz` takes one parameter of type <Identifier>"))
-            }
+        Instruction::Jz | Instruction::Jnz | Instruction::Jg | Instruction::Jl |
+        Instruction::Jge | Instruction::Jle | Instruction::Je | Instruction::Jne |
+        Instruction::Ja | Instruction::Jae | Instruction::Jb | Instruction::Jbe |
+        Instruction::Jc | Instruction::Jnc | Instruction::Jo | Instruction::Jno |
+        Instruction::Js | Instruction::Jns | Instruction::Jp | Instruction::Jnp => conditional_jump(instruction, params, ctx, condition_holds(instruction, ctx)),
+        Instruction::Jcxz => {
+            let cx = ctx.get_register(RegisterName::Cx).get_raw();
+            conditional_jump(instruction, params, ctx, cx == 0)
         },
-        Instruction::Jg => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jg` takes one parameter of type <Identifier>")) }
-
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jg_flags_set = !ctx.flags.get(Flag::ZF) && (ctx.flags.get(Flag::SF) == ctx.flags.get(Flag::OF));
-                match addr {
-                    Some(addr) if jg_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jg` takes one parameter of type <Identifier>"))
-            }
+        Instruction::Jecxz => {
+            let ecx = ctx.get_register(RegisterName::Ecx).get_raw();
+            conditional_jump(instruction, params, ctx, ecx == 0)
         },
-        Instruction::Jl => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jl` takes one parameter of type <Identifier>")) }
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jl_flags_set = ctx.flags.get(Flag::SF) != ctx.flags.get(Flag::OF);
-                match addr {
-                    Some(addr) if jl_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jl` takes one parameter of type <Identifier>"))
-            }
-        },
-        Instruction::Jge => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jge` takes one parameter of type <Identifier>")) }
+    // Loops
+        Instruction::Loop => loop_jump(instruction, params, ctx, None),
+        Instruction::Loope => loop_jump(instruction, params, ctx, Some(Condition::Equal)),
+        Instruction::Loopne => loop_jump(instruction, params, ctx, Some(Condition::NotEqual)),
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jge_flags_set = ctx.flags.get(Flag::SF) == ctx.flags.get(Flag::OF);
-                match addr {
-                    Some(addr) if jge_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jge` takes one parameter of type <Identifier>"))
-            }
-        },
-        Instruction::Jle => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jle` takes one parameter of type <Identifier>")) }
+    // Conditional moves
+        Instruction::Cmovz | Instruction::Cmovnz | Instruction::Cmovg | Instruction::Cmovl |
+        Instruction::Cmovge | Instruction::Cmovle | Instruction::Cmove | Instruction::Cmovne |
+        Instruction::Cmova | Instruction::Cmovae | Instruction::Cmovb | Instruction::Cmovbe |
+        Instruction::Cmovc | Instruction::Cmovnc | Instruction::Cmovo | Instruction::Cmovno |
+        Instruction::Cmovs | Instruction::Cmovns | Instruction::Cmovp | Instruction::Cmovnp => {
+            let [lhs, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jle_flags_set = ctx.flags.get(Flag::ZF) || (ctx.flags.get(Flag::SF) != ctx.flags.get(Flag::OF));
-                match addr {
-                    Some(addr) if jle_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jle` takes one parameter of type <Identifier>"))
-            }
+            // The source is read even when the condition does not hold
+            let size = operand_size(params, ctx)?;
+            let data = read_operand(rhs, size, ctx)?;
+            if condition_holds(instruction, ctx) { write_operand(lhs, size, data, ctx)?; }
+            Ok(())
         },
-        Instruction::Je => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`je` takes one parameter of type <Identifier>")) }
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let je_flags_set = ctx.flags.get(Flag::ZF);
-                match addr {
-                    Some(addr) if je_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`je` takes one parameter of type <Identifier>"))
-            }
-        },
-        Instruction::Jne => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`jne` takes one parameter of type <Identifier>")) }
+    // Set on condition
+        Instruction::Setz | Instruction::Setnz | Instruction::Setg | Instruction::Setl |
+        Instruction::Setge | Instruction::Setle | Instruction::Sete | Instruction::Setne |
+        Instruction::Seta | Instruction::Setae | Instruction::Setb | Instruction::Setbe |
+        Instruction::Setc | Instruction::Setnc | Instruction::Seto | Instruction::Setno |
+        Instruction::Sets | Instruction::Setns | Instruction::Setp | Instruction::Setnp => {
+            let [param] = params else { return Err(invalid_operands(instruction, ctx)) };
 
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                let addr = ctx.labels.get(s);
-                let jne_flags_set = !ctx.flags.get(Flag::ZF);
-                match addr {
-                    Some(addr) if jne_flags_set => Ok(ctx.next = *addr),
-                    Some(_) => Ok(()),
-                    None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
-                }
-            }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`jne` takes one parameter of type <Identifier>"))
-            }
+            let data = RegisterData::Value(condition_holds(instruction, ctx) as i32);
+            write_operand(param, DataSize::Byte, data, ctx)
         },
 
     // Functions
         Instruction::Call => {
-            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`call` takes one parameter of type <Identifier>" )) }
-
-            if let Some(Token::Identifier(s)) = params.get(0) {
-                /*
-                 * push eip
-                 * push ebp
-                 * mov ebp, esp
-                 */
-                handle_instruction(&Instruction::Push, &vec![ Token::Register(RegisterName::Eip) ], ctx)?;
-                handle_instruction(&Instruction::Push, &vec![ Token::Register(RegisterName::Ebp) ], ctx)?;
-                handle_instruction(&Instruction::Mov, &vec![ Token::Register(RegisterName::Ebp), Token::Register(RegisterName::Esp) ], ctx)?;
-
-                if s.starts_with("asmr::") {
+            match params {
+                [Spanned { node: Token::Identifier(s), .. }] if s.starts_with("asmr::") => {
+                    push_frame(ctx)?;
                     call_builtin_fn(s, ctx)?;
-                    handle_instruction(&Instruction::Ret, &vec![], ctx)
-                }
-                else {
-                    // Keep params the same because it still holds the label
-                    handle_instruction(&Instruction::Jmp, params, ctx)
-                }
+                    handle_instruction(&Instruction::Ret, &[], ctx)
+                },
+                [target] => {
+                    // Resolve the target before the stack frame changes esp and ebp
+                    let addr = jump_target(instruction, target, ctx)?;
+                    push_frame(ctx)?;
+                    ctx.next = addr;
+                    Ok(())
+                },
+                _ => Err(invalid_operands(instruction, ctx)),
             }
-            else {
-                Err(AsmrRuntimeError::from(ctx.ptr, "`call` takes one parameter of type <Identifier>"))
+        },
+        Instruction::Enter => {
+            match params {
+                [Spanned { node: Token::Numeric(size), .. }, Spanned { node: Token::Numeric(0), .. }] if *size >= 0 => {
+                    /*
+                     * push ebp
+                     * mov ebp, esp
+                     * sub esp, size
+                     */
+                    handle_instruction(&Instruction::Push, &[ Token::Register(RegisterName::Ebp).into() ], ctx)?;
+                    handle_instruction(&Instruction::Mov, &[ Token::Register(RegisterName::Ebp).into(), Token::Register(RegisterName::Esp).into() ], ctx)?;
+                    let esp = ctx.get_register(RegisterName::Esp);
+                    ctx.set_register(RegisterName::Esp, esp.with_raw(esp.get_raw().wrapping_sub(*size)));
+                    Ok(())
+                },
+                [Spanned { node: Token::Numeric(_), .. }, Spanned { node: Token::Numeric(_), .. }] => Err(AsmrRuntimeError::from(ctx.ptr, "`enter` only supports a nesting level of 0")),
+                _ => Err(invalid_operands(instruction, ctx)),
             }
         },
+        Instruction::Leave => {
+            /*
+             * mov esp, ebp
+             * pop ebp
+             */
+            handle_instruction(&Instruction::Mov, &[ Token::Register(RegisterName::Esp).into(), Token::Register(RegisterName::Ebp).into() ], ctx)?;
+            handle_instruction(&Instruction::Pop, &[ Token::Register(RegisterName::Ebp).into() ], ctx)
+        },
         Instruction::Ret => {
-            if params.len() != 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`ret` takes no parameters")) }
-
             /*
              * mov esp, ebp
              * pop ebp
              * pop eip
              */
-            handle_instruction(&Instruction::Mov, &vec![ Token::Register(RegisterName::Esp), Token::Register(RegisterName::Ebp) ], ctx)?;
-            handle_instruction(&Instruction::Pop, &vec![ Token::Register(RegisterName::Ebp) ], ctx)?;
-            handle_instruction(&Instruction::Pop, &vec![ Token::Register(RegisterName::Eip) ], ctx)?;
+            handle_instruction(&Instruction::Mov, &[ Token::Register(RegisterName::Esp).into(), Token::Register(RegisterName::Ebp).into() ], ctx)?;
+            handle_instruction(&Instruction::Pop, &[ Token::Register(RegisterName::Ebp).into() ], ctx)?;
+            handle_instruction(&Instruction::Pop, &[ Token::Register(RegisterName::Eip).into() ], ctx)?;
 
-            ctx.next = ctx.registers.get(&RegisterName::Eip).unwrap().borrow().get_raw() as usize + 1;
+            ctx.next = (ctx.registers.get(&RegisterName::Eip).unwrap().borrow().get_raw() as u32 as usize).wrapping_add(1);
 
             Ok(())
         },
     }
 }
 
-/// Handles a variable declaration line
-fn handle_variable(identifier: &String, mem_type: &MemType, params: &Vec<Token>, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
-    if *mem_type == MemType::Db {
-        let mut bytes: Vec<u8> = Vec::new();
-        for token in params {
-            match token {
-                Token::String(s) => bytes.append(&mut s.clone().into_bytes()),
-                Token::Numeric(i) => bytes.append(&mut i.to_ne_bytes().to_vec()),
-                Token::Identifier(_) => return Err(AsmrRuntimeError::from(ctx.ptr, "`db` takes parameters of type <...[String | Numeric]>")),
-                Token::Register(_) => return Err(AsmrRuntimeError::from(ctx.ptr, "`db` takes parameters of type <...[String | Numeric]>")),
-            };
+/// Handles an instruction with a repeat prefix, executing it once per count in ecx.
+/// `repe` and `repne` also stop once the comparison made by the instruction is not or is equal.
+fn repeat(prefix: &Prefix, instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    loop {
+        let ecx = ctx.get_register(RegisterName::Ecx);
+        if ecx.get_raw() == 0 { return Ok(()) }
+
+        handle_instruction(instruction, params, ctx)?;
+        ctx.set_register(RegisterName::Ecx, ecx.with_raw(ecx.get_raw().wrapping_sub(1)));
+
+        match prefix {
+            Prefix::Rep => {},
+            Prefix::Repe => if !ctx.flags.check(Condition::Equal) { return Ok(()) },
+            Prefix::Repne => if ctx.flags.check(Condition::Equal) { return Ok(()) },
         }
-        ctx.heap.push(bytes);
-        ctx.symtab.insert(identifier.to_string(), (ctx.heap.len() - 1).try_into().unwrap());
-        Ok(())
     }
-    else {
-        if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`resb` takes one parameter of type <Numeric>")) }
+}
 
-        if let Some(Token::Numeric(i)) = params.first() {
-            if *i <= 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`resb` requires the parameter <Numeric> to be greater than 0")) } // Must reserve a positive integer number of bytes
-            ctx.heap.push(Vec::with_capacity(usize::try_from(*i).unwrap()));
-            ctx.symtab.insert(identifier.to_string(), (ctx.heap.len() - 1).try_into().unwrap());
-            Ok(())
-        }
-        else {
-            // Must be a numeric value for resb
-            Err(AsmrRuntimeError::from(ctx.ptr, "`resb` takes one parameter of type <Numeric>"))
+/// Handles the instructions that update a single flag from its current state.
+fn set_flag<F>(ctx: &mut ExecutingContext, flag: Flag, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(bool) -> bool,
+{
+    let value = op(ctx.flags.get(flag));
+    ctx.flags.assign(flag, value);
+    Ok(())
+}
+
+/// Handles the string instructions, which operate on the source at `[esi]` and the destination at `[edi]`,
+/// then step esi and edi forwards or, when DF is set, backwards by the width of the data.
+/// - `movs` copies `[esi]` to `[edi]`
+/// - `stos` stores the accumulator at `[edi]`
+/// - `lods` loads `[esi]` into the accumulator
+/// - `cmps` compares `[esi]` with `[edi]`
+/// - `scas` compares the accumulator with `[edi]`
+fn string_op(instruction: &Instruction, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    let size = match instruction {
+        Instruction::Movsd | Instruction::Stosd | Instruction::Lodsd => DataSize::Dword,
+        _ => DataSize::Byte,
+    };
+    let (acc, _) = accumulator(size);
+    let esi = ctx.get_register(RegisterName::Esi).get_raw() as u32;
+    let edi = ctx.get_register(RegisterName::Edi).get_raw() as u32;
+    let read = |addr: u32, ctx: &ExecutingContext| ctx.memory.read(addr, size).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e));
+
+    let (uses_esi, uses_edi) = match instruction {
+        Instruction::Movsb | Instruction::Movsd => {
+            let data = read(esi, ctx)?;
+            ctx.memory.write(edi, size, data).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+            (true, true)
+        },
+        Instruction::Stosb | Instruction::Stosd => {
+            let data = ctx.get_register(acc);
+            ctx.memory.write(edi, size, data).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+            (false, true)
+        },
+        Instruction::Lodsb | Instruction::Lodsd => {
+            let data = read(esi, ctx)?;
+            ctx.set_register(acc, data);
+            (true, false)
+        },
+        Instruction::Cmpsb => {
+            let (lhs, rhs) = (read(esi, ctx)?.get_raw(), read(edi, ctx)?.get_raw());
+            alu::sub(lhs as u32, rhs as u32, size, &mut ctx.flags);
+            (true, true)
+        },
+        _ => {
+            let (lhs, rhs) = (ctx.get_register(acc).get_raw(), read(edi, ctx)?.get_raw());
+            alu::sub(lhs as u32, rhs as u32, size, &mut ctx.flags);
+            (false, true)
+        },
+    };
+
+    let step = if ctx.flags.get(Flag::DF) { -(size.bytes() as i32) } else { size.bytes() as i32 };
+    for (r, used) in [(RegisterName::Esi, uses_esi), (RegisterName::Edi, uses_edi)] {
+        if used {
+            let data = ctx.get_register(r);
+            ctx.set_register(r, data.with_raw(data.get_raw().wrapping_add(step)));
         }
     }
+
+    Ok(())
+}
+
+/// Handles instructions of the form `op lhs, rhs` that store their result in `lhs`.
+/// The pointer or value type of `lhs` is preserved.
+fn binary_op<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, u32, DataSize, &mut Flags) -> u32,
+{
+    let [lhs, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(params, ctx)?;
+    let data = read_operand(lhs, size, ctx)?;
+    let rhs = read_operand(rhs, size, ctx)?.get_raw();
+    let result = op(data.get_raw() as u32, rhs as u32, size, &mut ctx.flags);
+    write_operand(lhs, size, data.with_raw(result as i32), ctx)
+}
+
+/// Handles shifts and rotates of the form `op operand, count`, where the count is a numeric or `cl`.
+/// The pointer or value type of `operand` is preserved.
+fn shift_op<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, u32, DataSize, &mut Flags) -> u32,
+{
+    let [lhs, count] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(&params[..1], ctx)?;
+    let data = read_operand(lhs, size, ctx)?;
+    let count = read_operand(count, DataSize::Byte, ctx)?.get_raw();
+    let result = op(data.get_raw() as u32, count as u32, size, &mut ctx.flags);
+    write_operand(lhs, size, data.with_raw(result as i32), ctx)
+}
+
+/// Handles `shld` and `shrd` of the form `op operand, src, count`, which shift bits of the `src` register into `operand`.
+/// The pointer or value type of `operand` is preserved.
+fn double_shift_op<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, u32, u32, DataSize, &mut Flags) -> u32,
+{
+    let [lhs, src, count] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(&params[..2], ctx)?;
+    let data = read_operand(lhs, size, ctx)?;
+    let src = read_operand(src, size, ctx)?.get_raw();
+    let count = read_operand(count, DataSize::Byte, ctx)?.get_raw();
+    let result = op(data.get_raw() as u32, src as u32, count as u32, size, &mut ctx.flags);
+    write_operand(lhs, size, data.with_raw(result as i32), ctx)
+}
+
+/// Handles instructions of the form `op operand` that store their result in `operand`.
+/// The pointer or value type of `operand` is preserved.
+fn unary_op<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, DataSize, &mut Flags) -> u32,
+{
+    let [param] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(params, ctx)?;
+    let data = read_operand(param, size, ctx)?;
+    let result = op(data.get_raw() as u32, size, &mut ctx.flags);
+    write_operand(param, size, data.with_raw(result as i32), ctx)
+}
+
+/// Handles the one operand forms of `mul` and `imul`, which multiply the accumulator by `params`
+/// and store the double-width product in the accumulator pair, e.g. `edx:eax = eax * src`.
+fn widening_multiply<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, u32, DataSize, &mut Flags) -> (u32, u32),
+{
+    let [src] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(params, ctx)?;
+    let (low, high) = accumulator(size);
+    let lhs = ctx.get_register(low).get_raw();
+    let rhs = read_operand(src, size, ctx)?.get_raw();
+
+    let (product_low, product_high) = op(lhs as u32, rhs as u32, size, &mut ctx.flags);
+    ctx.set_register(low, RegisterData::Value(product_low as i32));
+    ctx.set_register(high, RegisterData::Value(product_high as i32));
+    Ok(())
+}
+
+/// Handles `div` and `idiv`, which divide the double-width accumulator pair by `params`,
+/// storing the quotient in the low half and the remainder in the high half, e.g. `eax, edx = edx:eax / src`.
+fn divide<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, u32, u32, DataSize) -> Option<(u32, u32)>,
+{
+    let [src] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(params, ctx)?;
+    let (low, high) = accumulator(size);
+    let divisor = read_operand(src, size, ctx)?.get_raw();
+    if divisor as u32 & alu::mask(size) == 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "divide error (#DE): division by zero")) }
+
+    let dividend_low = ctx.get_register(low).get_raw();
+    let dividend_high = ctx.get_register(high).get_raw();
+    let (quotient, remainder) = op(dividend_high as u32, dividend_low as u32, divisor as u32, size) // Flags are undefined
+        .ok_or_else(|| AsmrRuntimeError::from(ctx.ptr, format!("divide error (#DE): quotient does not fit in `{low}`")))?;
+
+    ctx.set_register(low, RegisterData::Value(quotient as i32));
+    ctx.set_register(high, RegisterData::Value(remainder as i32));
+    Ok(())
+}
+
+/// Gets the registers holding the low and high halves of a double-width operand of width `size`
+/// in multiplication and division.
+fn accumulator(size: DataSize) -> (RegisterName, RegisterName) {
+    match size {
+        DataSize::Byte => (RegisterName::Al, RegisterName::Ah),
+        DataSize::Word => (RegisterName::Ax, RegisterName::Dx),
+        DataSize::Dword => (RegisterName::Eax, RegisterName::Edx),
+    }
+}
+
+/// Handles `movsx` and `movzx`, which widen a narrower source into a register using `op`.
+/// The width of the source comes from a sub-register or an explicitly sized memory operand.
+fn extend<F>(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, op: F) -> Result<(), AsmrRuntimeError>
+where
+    F: FnOnce(u32, DataSize) -> u32,
+{
+    let [lhs @ Spanned { node: Token::Register(r), .. }, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = match &rhs.node {
+        Token::Register(src) => src.size(),
+        Token::Memory(EffectiveAddress { size: Some(size), .. }) => *size,
+        _ => return Err(AsmrRuntimeError::from(ctx.ptr, format!("`{instruction}` requires the size of its memory operand, e.g. `byte [x]`"))),
+    };
+    if size.bytes() >= r.size().bytes() { return Err(AsmrRuntimeError::from(ctx.ptr, format!("`{instruction}` requires a source narrower than `{r}`"))) }
+
+    let value = read_operand(rhs, size, ctx)?.get_raw();
+    let result = op(value as u32, size) & alu::mask(r.size());
+    write_operand(lhs, r.size(), RegisterData::Value(result as i32), ctx)
+}
+
+/// Reads the raw values of both operands of an instruction that only inspects them, such as `cmp`.
+fn source_operands(instruction: &Instruction, params: &[Spanned<Token>], ctx: &ExecutingContext) -> Result<(u32, u32, DataSize), AsmrRuntimeError> {
+    let [lhs, rhs] = params else { return Err(invalid_operands(instruction, ctx)) };
+
+    let size = operand_size(params, ctx)?;
+    let lhs = read_operand(lhs, size, ctx)?.get_raw();
+    let rhs = read_operand(rhs, size, ctx)?.get_raw();
+    Ok((lhs as u32, rhs as u32, size))
+}
+
+/// Saves the return address and creates a new stack frame for a call.
+fn push_frame(ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    /*
+     * push eip
+     * push ebp
+     * mov ebp, esp
+     */
+    handle_instruction(&Instruction::Push, &[ Token::Register(RegisterName::Eip).into() ], ctx)?;
+    handle_instruction(&Instruction::Push, &[ Token::Register(RegisterName::Ebp).into() ], ctx)?;
+    handle_instruction(&Instruction::Mov, &[ Token::Register(RegisterName::Ebp).into(), Token::Register(RegisterName::Esp).into() ], ctx)
+}
+
+/// Resolves the line to continue from for `jmp` and `call`.
+/// The target is either a label or a register or memory operand holding the address of a label.
+fn jump_target(instruction: &Instruction, target: &Spanned<Token>, ctx: &ExecutingContext) -> Result<usize, AsmrRuntimeError> {
+    let addr = match &target.node {
+        Token::Identifier(s) => match ctx.labels.get(s) {
+            Some(addr) => Ok(*addr),
+            None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`"))),
+        },
+        Token::Register(_) | Token::Memory(_) => {
+            let addr = read_operand(target, DataSize::Dword, ctx)?.get_raw() as u32;
+            match ctx.labels.values().find(|l| **l as u32 == addr) {
+                Some(addr) => Ok(*addr),
+                None => Err(AsmrRuntimeError::from(ctx.ptr, format!("cannot `{instruction}` address `{addr:#x}`, it is not the address of a label"))),
+            }
+        },
+        _ => Err(invalid_operands(instruction, ctx)),
+    };
+
+    addr.map_err(|e| e.with_span(target.span))
+}
+
+/// Gets the error for operands that do not match an operand form of `instruction`.
+/// Operands are checked against [Instruction::signatures] before dispatch, so this only guards destructuring them.
+fn invalid_operands(instruction: &Instruction, ctx: &ExecutingContext) -> AsmrRuntimeError {
+    AsmrRuntimeError::from(ctx.ptr, instruction.describe_signatures())
+}
+
+/// Tests the condition of a conditional jump, move or set against the current flags.
+fn condition_holds(instruction: &Instruction, ctx: &ExecutingContext) -> bool {
+    instruction.condition().is_some_and(|condition| ctx.flags.check(condition))
+}
+
+/// Jumps to the label in `params` if `condition` is true.
+/// The label must exist even when the jump is not taken.
+fn conditional_jump(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, condition: bool) -> Result<(), AsmrRuntimeError> {
+    if let [Spanned { node: Token::Identifier(s), span }] = params {
+        match ctx.labels.get(s) {
+            Some(addr) => {
+                if condition { ctx.next = *addr }
+                Ok(())
+            },
+            None => Err(AsmrRuntimeError::from(ctx.ptr, format!("no address associated with identifier `{s}`")).with_span(*span)),
+        }
+    }
+    else {
+        Err(invalid_operands(instruction, ctx))
+    }
+}
+
+/// Decrements ecx without affecting the flags, then jumps to the label in `params`
+/// if ecx is not zero and `condition`, when given, holds.
+fn loop_jump(instruction: &Instruction, params: &[Spanned<Token>], ctx: &mut ExecutingContext, condition: Option<Condition>) -> Result<(), AsmrRuntimeError> {
+    let ecx = {
+        let mut ecx = ctx.registers.get(&RegisterName::Ecx).unwrap().borrow_mut();
+        ecx.data = ecx.data.with_raw(ecx.get_raw().wrapping_sub(1));
+        ecx.get_raw()
+    };
+
+    let taken = ecx != 0 && condition.is_none_or(|c| ctx.flags.check(c));
+    conditional_jump(instruction, params, ctx, taken)
+}
+
+/// Gets the width of the data an instruction operates on, from its registers or an explicitly sized memory operand.
+/// Every register and sized memory operand must have the same width, as widening loads are done by `movzx` and `movsx`.
+/// Memory operands default to a dword unless a size is specified.
+fn operand_size(params: &[Spanned<Token>], ctx: &ExecutingContext) -> Result<DataSize, AsmrRuntimeError> {
+    let registers: Vec<_> = params.iter().filter_map(|p| if let Token::Register(r) = &p.node { Some(r) } else { None }).collect();
+    if let [lhs, rhs] = registers.as_slice() {
+        if lhs.size() != rhs.size() { return Err(AsmrRuntimeError::from(ctx.ptr, format!("operand size mismatch between `{lhs}` and `{rhs}`"))) }
+    }
+
+    let memory_size = params.iter().find_map(|p| match &p.node {
+        Token::Memory(EffectiveAddress { size: Some(size), .. }) => Some(*size),
+        _ => None,
+    });
+    match (memory_size, registers.first()) {
+        (Some(size), Some(r)) if size != r.size() => Err(AsmrRuntimeError::from(ctx.ptr, format!("operand size mismatch between `{size}` memory operand and `{r}`"))),
+        (Some(size), _) => Ok(size),
+        (None, Some(r)) => Ok(r.size()),
+        (None, None) => Ok(DataSize::Dword),
+    }
+}
+
+/// Computes the address referenced by a memory operand.
+fn effective_address(ea: &EffectiveAddress, ctx: &ExecutingContext) -> Result<u32, AsmrRuntimeError> {
+    let reg = |r: &RegisterName| ctx.get_register(*r).get_raw();
+
+    let mut addr = ea.displacement;
+    if let Some(base) = &ea.base {
+        addr = addr.wrapping_add(reg(base));
+    }
+    if let Some((index, scale)) = &ea.index {
+        addr = addr.wrapping_add(reg(index).wrapping_mul(*scale as i32));
+    }
+    if let Some(symbol) = &ea.symbol {
+        match ctx.symtab.get(&symbol.node) {
+            Some(p) => addr = addr.wrapping_add(*p),
+            None => return Err(AsmrRuntimeError::from(ctx.ptr, format!("unknown identifier `{symbol}`")).with_span(symbol.span)),
+        }
+    }
+    if let Some(offset) = &ea.offset {
+        addr = addr.wrapping_add(evaluate(offset, offset.span, false, ctx)?.get_raw());
+    }
+
+    Ok(addr as u32)
+}
+
+/// Reads the data held by a register, memory, identifier or numeric operand.
+/// Memory reads narrower than a dword are zero-extended.
+fn read_operand(token: &Spanned<Token>, size: DataSize, ctx: &ExecutingContext) -> Result<RegisterData, AsmrRuntimeError> {
+    let data = match &token.node {
+        Token::Register(r) => Ok(ctx.get_register(*r)),
+        Token::Numeric(i) => Ok(RegisterData::Value(*i)),
+        Token::Identifier(s) => lookup(s, ctx).ok_or_else(|| AsmrRuntimeError::from(ctx.ptr, format!("unknown identifier `{s}`"))),
+        Token::Expression(e) => evaluate(e, token.span, false, ctx),
+        Token::Memory(ea) => {
+            let addr = effective_address(ea, ctx)?;
+            ctx.memory.read(addr, size).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))
+        },
+        Token::String(_) => Err(AsmrRuntimeError::from(ctx.ptr, "string literals can only be used to define memory")),
+    };
+
+    data.map_err(|e| e.with_span(token.span))
+}
+
+/// Looks up the value of a name, which is the address of a variable or label, or the value of a constant.
+fn lookup(name: &str, ctx: &ExecutingContext) -> Option<RegisterData> {
+    match (ctx.symtab.get(name), ctx.labels.get(name), ctx.constants.get(name)) {
+        (Some(p), _, _) => Some(RegisterData::Pointer(*p)),
+        (None, Some(addr), _) => Some(RegisterData::Pointer(*addr as i32)), // Labels are addressed by their line
+        (None, None, data) => data.copied(),
+    }
+}
+
+/// Evaluates an expression that could not be evaluated at parse time.
+/// In data, `$` is the address of the next variable to be allocated and `$$` is the start of the data segment.
+/// In code, `$` is the current line and `$$` is the first line.
+fn evaluate(expression: &Expression, span: Span, in_data: bool, ctx: &ExecutingContext) -> Result<RegisterData, AsmrRuntimeError> {
+    let (here, start) = match in_data {
+        true => (ctx.memory.data_end(), DATA_START),
+        false => (ctx.ptr as u32, 0),
+    };
+
+    expression.evaluate(&|name| lookup(name, ctx), RegisterData::Pointer(here as i32), RegisterData::Pointer(start as i32))
+        .map_err(|cause| AsmrRuntimeError::from(ctx.ptr, cause).with_span(span))
+}
+
+/// Writes data to a register or memory operand.
+/// Memory writes narrower than a dword store the low bytes of `data`.
+fn write_operand(token: &Spanned<Token>, size: DataSize, data: RegisterData, ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    let result = match &token.node {
+        Token::Register(r) => {
+            ctx.set_register(*r, data);
+            Ok(())
+        },
+        Token::Memory(ea) => {
+            let addr = effective_address(ea, ctx)?;
+            ctx.memory.write(addr, size, data).map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))
+        },
+        _ => Err(AsmrRuntimeError::from(ctx.ptr, "only registers and memory can be written to")),
+    };
+
+    result.map_err(|e| e.with_span(token.span))
+}
+
+/// Handles a variable declaration line by allocating it in the data segment
+fn handle_variable(identifier: &str, mem_type: &MemType, params: &[Spanned<Token>], ctx: &mut ExecutingContext) -> Result<(), AsmrRuntimeError> {
+    if ctx.symtab.contains_key(identifier) { return Err(AsmrRuntimeError::from(ctx.ptr, format!("identifier `{identifier}` is already defined"))) }
+
+    let addr = match mem_type {
+        MemType::Db => {
+            let mut bytes: Vec<u8> = Vec::new();
+            for token in params {
+                let i = match &token.node {
+                    Token::String(s) => {
+                        bytes.extend_from_slice(s);
+                        continue;
+                    },
+                    Token::Numeric(i) => *i,
+                    Token::Expression(e) => evaluate(e, token.span, true, ctx)?.get_raw(),
+                    _ => return Err(AsmrRuntimeError::from(ctx.ptr, "`db` takes parameters of type <...[String | Numeric]>").with_span(token.span)),
+                };
+                match u8::try_from(i).or_else(|_| i8::try_from(i).map(|i| i as u8)) {
+                    Ok(b) => bytes.push(b),
+                    Err(_) => return Err(AsmrRuntimeError::from(ctx.ptr, format!("`db` requires each <Numeric> to fit in a byte but found `{i}`")).with_span(token.span)),
+                }
+            }
+            ctx.memory.alloc(&bytes)
+        },
+        MemType::Dd => {
+            // Each dword is a numeric value or the address of a variable or label, allowing jump tables
+            let mut dwords = Vec::new();
+            for token in params {
+                match &token.node {
+                    Token::Numeric(_) | Token::Identifier(_) => dwords.push(read_operand(token, DataSize::Dword, ctx)?),
+                    Token::Expression(e) => dwords.push(evaluate(e, token.span, true, ctx)?),
+                    _ => return Err(AsmrRuntimeError::from(ctx.ptr, "`dd` takes parameters of type <...[Identifier | Numeric]>").with_span(token.span)),
+                };
+            }
+            if dwords.is_empty() { return Err(AsmrRuntimeError::from(ctx.ptr, "`dd` takes parameters of type <...[Identifier | Numeric]>")) }
+
+            ctx.memory.alloc_zeroed(4 * dwords.len() as u32).and_then(|addr| {
+                for (i, data) in dwords.into_iter().enumerate() {
+                    ctx.memory.write(addr + 4 * i as u32, DataSize::Dword, data)?;
+                }
+                Ok(addr)
+            })
+        },
+        MemType::Resb => {
+            if params.len() != 1 { return Err(AsmrRuntimeError::from(ctx.ptr, "`resb` takes one parameter of type <Numeric>")) }
+
+            let size = match params.first().map(|p| &p.node) {
+                Some(Token::Numeric(i)) => Some(*i),
+                Some(Token::Expression(e)) => Some(evaluate(e, params[0].span, true, ctx)?.get_raw()),
+                _ => None,
+            };
+            if let Some(i) = size {
+                if i <= 0 { return Err(AsmrRuntimeError::from(ctx.ptr, "`resb` requires the parameter <Numeric> to be greater than 0")) } // Must reserve a positive integer number of bytes
+                ctx.memory.alloc_zeroed(i as u32)
+            }
+            else {
+                // Must be a numeric value for resb
+                return Err(AsmrRuntimeError::from(ctx.ptr, "`resb` takes one parameter of type <Numeric>"))
+            }
+        },
+    };
+
+    let addr = addr.map_err(|e| AsmrRuntimeError::from(ctx.ptr, e))?;
+    ctx.symtab.insert(identifier.to_string(), addr as i32);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_lines, span::Span};
+    use crate::core::memory::DATA_START;
+
+    /// Gets the span of the bytes `start..end` of an ASCII line.
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end, column: start, column_end: end }
+    }
+
+    /// Parses and runs asmr code, returning the final context for inspection.
+    fn run_code(code: &[&str]) -> Result<ExecutingContext, AsmrRuntimeError> {
+        let lines = parse_lines(code.iter()).unwrap();
+        let mut ctx = ExecutingContext::new();
+        run(&lines, &mut ctx)?;
+        Ok(ctx)
+    }
+
+    fn reg(ctx: &ExecutingContext, r: RegisterName) -> i32 {
+        ctx.registers.get(&r).unwrap().borrow().get_raw()
+    }
+
+    #[test]
+    fn variables_can_be_declared_after_use() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "movzx eax, byte [msg]",
+            "ret",
+            "msg db \"A\"",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 'A' as i32);
+
+        Ok(())
+    }
+
+    #[test]
+    fn variables_are_allocated_once_in_declaration_order() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "first db 1, 2, 3",
+            "mov ecx, 3",
+            ".loop:",
+            "    counter resb 4",
+            "    inc dword [counter]",
+            "    dec ecx",
+            "    cmp ecx, 0",
+            "    jne .loop",
+            "mov eax, [counter]",
+            "last resb 1",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 3);
+        assert_eq!(*ctx.symtab.get("first").unwrap(), DATA_START as i32);
+        assert_eq!(*ctx.symtab.get("counter").unwrap(), DATA_START as i32 + 3);
+        assert_eq!(*ctx.symtab.get("last").unwrap(), DATA_START as i32 + 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn variables_err_on_duplicate_identifier() {
+        let ctx = run_code(&[
+            "buf resb 4",
+            "nop",
+            "buf db 0",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(2, "identifier `buf` is already defined").with_span(span(0, 3))
+            .with_label(Label::new(1, Some(span(0, 3)), "first defined here"))));
+    }
+
+    #[test]
+    fn signed_jumps_follow_cmp_with_negative_operands() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "sub eax, 5",       // eax = -5
+            "mov ebx, 0",
+            "cmp eax, 3",
+            "jg .greater",
+            "jl .less",
+            "jmp .end",
+            ".greater:",
+            "    mov ebx, 1",
+            "    jmp .end",
+            ".less:",
+            "    mov ebx, 2",
+            ".end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsigned_jumps_treat_operands_as_unsigned() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "sub eax, 1",       // eax = 0xffffffff
+            "mov ebx, 0",
+            "cmp eax, 1",
+            "jbe .end",
+            "ja .above",
+            "jmp .end",
+            ".above:",
+            "    mov ebx, 1",
+            ".end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flag_jumps_test_single_flags() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov ebx, 0",
+            "mov eax, 0x7fffffff",
+            "add eax, 1",       // OF, SF, no CF, even parity
+            "jno .end",
+            "jns .end",
+            "jc .end",
+            "jnp .end",
+            "mov ebx, 1",
+            "jo .overflow",
+            "jmp .end",
+            ".overflow:",
+            "    js .sign",
+            "    jmp .end",
+            ".sign:",
+            "    jnc .no_carry",
+            "    jmp .end",
+            ".no_carry:",
+            "    jp .parity",
+            "    jmp .end",
+            ".parity:",
+            "    mov ebx, 2",
+            ".end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jcxz_jumps_when_counter_is_zero() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "mov ecx, 0x10000",
+            "jcxz .cx_zero",
+            "jmp .end",
+            ".cx_zero:",
+            "    inc eax",
+            "    jecxz .end",
+            "    inc eax",
+            ".end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn loop_decrements_ecx_until_zero() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "mov ecx, 5",
+            ".loop:",
+            "    add eax, ecx",
+            "    loop .loop",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 15);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn loope_and_loopne_also_test_zero_flag() -> Result<(), AsmrRuntimeError> {
+        // Find the first non-zero byte
+        let ctx = run_code(&[
+            "buf db 0, 0, 0, 7, 0",
+            "mov ecx, 5",
+            "mov ebx, buf",
+            "dec ebx",
+            ".scan:",
+            "    inc ebx",
+            "    cmp byte [ebx], 0",
+            "    loope .scan",
+            "movzx eax, byte [ebx]",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 7);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 1);
+
+        // Find the first zero byte
+        let ctx = run_code(&[
+            "buf db 3, 2, 0, 1",
+            "mov ecx, 4",
+            "mov ebx, buf",
+            "dec ebx",
+            ".scan:",
+            "    inc ebx",
+            "    cmp byte [ebx], 0",
+            "    loopne .scan",
+            "mov eax, ebx",
+            "mov edx, buf",
+            "sub eax, edx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_sets_flags() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0x7fffffff",
+            "add eax, 1",
+        ])?;
+
+        assert!(ctx.flags.get(Flag::OF));
+        assert!(ctx.flags.get(Flag::SF));
+        assert!(!ctx.flags.get(Flag::CF));
+        assert!(!ctx.flags.get(Flag::ZF));
+
+        let ctx = run_code(&[
+            "mov eax, 1",
+            "neg eax",
+            "shr eax, 31",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 1);
+        assert!(ctx.flags.get(Flag::CF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_wraps_instead_of_panicking() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0x7fffffff",
+            "inc eax",
+            "mov ebx, 0x10000",
+            "imul ebx, ebx, 0x10000",
+            "mov ecx, 0",
+            "dec ecx",
+            "sub ecx, 0x7fffffff",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), i32::MIN);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 0);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), i32::MIN);
+
+        let ctx = run_code(&[
+            "mov eax, 1",
+            "shl eax, 31",
+            "mov ebx, 0",
+            "dec ebx",
+            "imul eax, ebx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), i32::MIN);
+        assert!(ctx.flags.get(Flag::OF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn shift_counts_are_masked_to_five_bits() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 1",
+            "shl eax, 33",
+            "mov ebx, 1",
+            "shl ebx, 31",
+            "shr ebx, 63",
+            "mov ecx, 5",
+            "shl ecx, 32",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 2);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 1);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn div_err_on_division_by_zero() {
+        let ctx = run_code(&[
+            "mov eax, 10",
+            "mov ebx, 0",
+            "div ebx",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(2, "divide error (#DE): division by zero").with_span(span(0, 3))));
+
+        let ctx = run_code(&[
+            "mov eax, 1",
+            "shl eax, 31",
+            "cdq",
+            "mov ebx, 0",
+            "dec ebx",
+            "idiv ebx",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(5, "divide error (#DE): quotient does not fit in `eax`").with_span(span(0, 4))));
+
+        let ctx = run_code(&[
+            "mov edx, 0x80000000",
+            "mov eax, 0",
+            "mov ebx, -1",
+            "idiv ebx",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(3, "divide error (#DE): quotient does not fit in `eax`").with_span(span(0, 4))));
+    }
+
+    #[test]
+    fn memory_operands_read_and_write_bytes() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "msg db \"hello\", 0",
+            "mov ecx, 0",
+            ".upper:",
+            "    movzx eax, byte [msg+ecx]",
+            "    sub eax, 32",
+            "    mov byte [msg+ecx], al",
+            "    inc ecx",
+            "    cmp ecx, 5",
+            "    jne .upper",
+            "mov ebx, msg",
+            "mov edx, dword [ebx+1]",
+        ])?;
+
+        let msg = *ctx.symtab.get("msg").unwrap() as u32;
+        assert_eq!(ctx.memory.read_str(msg).unwrap(), b"HELLO");
+        assert_eq!(reg(&ctx, RegisterName::Edx), i32::from_le_bytes(*b"ELLO"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_operands_support_strlen() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "str db \"assembl-really\", 0",
+            "mov ebx, str",
+            "mov eax, 0",
+            ".loop:",
+            "    movzx ecx, byte [ebx+eax]",
+            "    cmp ecx, 0",
+            "    je .done",
+            "    inc eax",
+            "    jmp .loop",
+            ".done:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 14);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_operands_support_scaled_index() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "nums resb 16",
+            "mov ecx, 3",
+            "mov [nums + ecx*4], 7",
+            "add dword [nums + 12], 5",
+            "mov eax, [nums + ecx*4]",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 12);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_operands_address_the_stack() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 42",
+            "push eax",
+            "mov ebx, [esp]",
+            "inc dword [esp]",
+            "pop ecx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 42);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 43);
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_operands_err_on_unmapped_address() {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "mov ebx, [eax]",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "segmentation fault: address `0x0` is not mapped").with_span(span(9, 14))));
+    }
+
+    #[test]
+    fn instructions_err_on_operands_outside_their_signatures() {
+        // Lines that did not come from the parser are checked before dispatch
+        let memory = || Token::Memory(EffectiveAddress { base: Some(RegisterName::Esp), ..Default::default() });
+        let lines = vec![
+            Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![ memory().into(), memory().into() ] },
+        ];
+        let result = run(&lines, &mut ExecutingContext::new());
+        assert_eq!(result.err(), Some(AsmrRuntimeError::from(0, "`mov` cannot take two memory operands").with_span(span(0, 0))));
+
+        let lines = vec![
+            Line::Instruction { prefix: None, instruction: Instruction::Push.into(), params: vec![ Token::Register(RegisterName::Al).into() ] },
+        ];
+        let result = run(&lines, &mut ExecutingContext::new());
+        assert_eq!(result.err(), Some(AsmrRuntimeError::from(0, "`push` takes parameters of type <...[Register32 | Memory | Identifier | Numeric]>").with_span(span(0, 0))));
+    }
+
+    #[test]
+    fn malformed_programs_err_instead_of_panicking() {
+        let negations = format!("mov eax, {}1", "-".repeat(200000));
+        let parentheses = format!("mov eax, {}1{}", "(".repeat(5000), ")".repeat(5000));
+        let offsets = format!("mov eax, [msg{}]", " + msg".repeat(200000));
+
+        let programs: &[&[&str]] = &[
+            // Unknown identifiers and labels
+            &["mov eax, missing"],
+            &["mov eax, [missing + 4]"],
+            &["jmp missing"],
+            &["jz 5"],
+            &["call missing"],
+            &["call asmr::io::missing"],
+            &["loop"],
+            // Invalid operands
+            &["mov"],
+            &["mov eax"],
+            &["mov 5, eax"],
+            &["mov eax, \"hi\""],
+            &["add eax, ebx, ecx"],
+            &["shl eax, ebx"],
+            &["pop"],
+            &["pop 5"],
+            &["push \"hi\""],
+            &["inc 5"],
+            &["not"],
+            // Arithmetic edge cases
+            &["mov eax, 1", "mov ebx, 0", "div ebx"],
+            &["mov eax, -2147483648", "mov ebx, -1", "div ebx"],
+            &["mov edx, 0x80000000", "mov eax, 0", "mov ebx, -1", "idiv ebx"],
+            &["mov eax, 2147483647", "add eax, 1", "mul eax", "shl eax, 255", "neg eax"],
+            // Stack misuse
+            &["pop eax", "pop eax", "pop eax"],
+            &["ret", "ret"],
+            &["mov esp, 0", "push eax"],
+            &["mov esp, -4", "pop eax"],
+            &["mov ebp, 5", "ret"],
+            &["mov ebp, -1", "ret"],
+            &["f:", "call f"],
+            // Builtins with bad frames or parameters
+            &["call asmr::io::print"],
+            &["mov esp, 0x100000", "call asmr::io::print"],
+            &["mov esp, 4", "call asmr::io::print"],
+            &["mov eax, 5", "push eax", "call asmr::io::readln"],
+            &["buf resb 4", "mov eax, buf", "sub eax, 8", "push eax", "call asmr::io::print"],
+            // Memory misuse
+            &["mov eax, [0]"],
+            &["mov dword [-1], eax"],
+            &["buf resb 2", "mov eax, dword [buf + 1]"],
+            &["buf resb 2147483647"],
+            &["buf resb -1"],
+            &["buf db 256"],
+            &["buf db \"a\"", "buf db \"b\""],
+            // Deeply nested expressions
+            &[&negations],
+            &[&parentheses],
+            &[&offsets, "msg db 0"],
+        ];
+
+        for code in programs {
+            let result = std::panic::catch_unwind(|| {
+                if let Ok(lines) = parse_lines(code.iter()) {
+                    let _ = run(&lines, &mut ExecutingContext::new());
+                }
+            });
+
+            assert!(result.is_ok(), "panicked while running {code:?}");
+        }
+    }
+
+    #[test]
+    fn sub_registers_alias_their_parent() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "not eax",
+            "mov al, 0x12",
+            "mov ah, 0x34",
+            "mov ecx, eax",
+            "mov bx, ax",
+            "mov dl, ah",
+            "mov esi, 7",
+            "mov edi, esi",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax) as u32, 0xFFFF_3412);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 0x3412);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 0x34);
+        assert_eq!(reg(&ctx, RegisterName::Edi), 7);
+        assert_eq!(ctx.get_register(RegisterName::Cx).get_raw(), 0x3412);
+        assert_eq!(ctx.get_register(RegisterName::Ch).get_raw(), 0x34);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sub_registers_operate_at_their_width() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0x1FF",
+            "add al, 1",
+            "buf db 0xAB, 0xCD",
+            "mov ebx, 0",
+            "mov bh, byte [buf + 1]",
+            "mov byte [buf], bh",
+            "mov ecx, 0",
+            "mov cl, byte [buf]",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 0x100);
+        assert!(ctx.flags.get(Flag::CF) && ctx.flags.get(Flag::ZF));
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 0xCD00);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 0xCD);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sub_registers_err_on_size_mismatch() {
+        let ctx = run_code(&[
+            "mov eax, bl",
+        ]);
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(0, "operand size mismatch between `eax` and `bl`").with_span(span(0, 3))));
+
+        // Widening loads need `movzx` or `movsx`
+        let ctx = run_code(&[
+            "msg db \"A\"",
+            "mov eax, byte [msg]",
+        ]);
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "operand size mismatch between `byte` memory operand and `eax`").with_span(span(0, 3))));
+
+        let ctx = run_code(&[
+            "msg db \"A\"",
+            "mov word [msg], al",
+        ]);
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "operand size mismatch between `word` memory operand and `al`").with_span(span(0, 3))));
+    }
+
+    #[test]
+    fn mul_and_div_use_edx_eax() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0x10000",
+            "mov ebx, 0x30000",
+            "mul ebx",
+            "mov esi, edx",
+            "mov edi, eax",
+            "mov eax, 1234",
+            "mov edx, 0",
+            "mov ecx, 10",
+            "div ecx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Esi), 3);
+        assert_eq!(reg(&ctx, RegisterName::Edi), 0);
+        assert_eq!(reg(&ctx, RegisterName::Eax), 123);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 4);
+
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "mov al, 200",
+            "mov bl, 3",
+            "mul bl",
+            "mov cx, ax",
+            "mov bl, 7",
+            "div bl",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 600);
+        assert_eq!(ctx.get_register(RegisterName::Al).get_raw(), 85);
+        assert_eq!(ctx.get_register(RegisterName::Ah).get_raw(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn imul_and_idiv_are_signed() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "sub eax, 47",
+            "cdq",
+            "mov ebx, 10",
+            "idiv ebx",
+            "mov esi, eax",
+            "mov edi, edx",
+            "mov eax, 3",
+            "neg eax",
+            "mov ecx, 5",
+            "imul ecx",
+            "mov ebx, 6",
+            "neg ebx",
+            "imul ebx, 7",
+            "imul ecx, ebx, 2",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Esi), -4);
+        assert_eq!(reg(&ctx, RegisterName::Edi), -7);
+        assert_eq!(reg(&ctx, RegisterName::Eax), -15);
+        assert_eq!(reg(&ctx, RegisterName::Edx), -1);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), -42);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), -84);
+        assert!(!ctx.flags.get(Flag::CF) && !ctx.flags.get(Flag::OF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn movsx_and_movzx_widen_their_source() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "buf db 0xFE",
+            "movsx eax, byte [buf]",
+            "movzx ebx, byte [buf]",
+            "mov cl, 0x80",
+            "movsx dx, cl",
+            "movzx esi, cl",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), -2);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 0xFE);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 0xFF80);
+        assert_eq!(reg(&ctx, RegisterName::Esi), 0x80);
+
+        let ctx = run_code(&[
+            "buf db 1",
+            "movzx eax, [buf]",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "`movzx` requires the size of its memory operand, e.g. `byte [x]`").with_span(span(0, 5))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn shifts_and_rotates_take_counts_from_cl() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "sub eax, 64",
+            "mov ebx, eax",
+            "mov cl, 4",
+            "sar eax, cl",
+            "shr ebx, cl",
+            "mov edx, 0x12345678",
+            "rol edx, 8",
+            "mov esi, 0x12345678",
+            "mov edi, 0x7ABCDEF0",
+            "shld esi, edi, cl",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), -4);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 0x0FFF_FFFC);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 0x3456_7812);
+        assert_eq!(reg(&ctx, RegisterName::Esi), 0x2345_6787);
+
+        let lines = parse_lines([
+            "mov eax, 1",
+            "shl eax, ebx",
+        ].iter());
+
+        assert_eq!(lines.err().map(|e| e.cause), Some("`shl` takes parameters of type <[Register | Memory], [Numeric | cl]>.".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_through_carry_chain_multi_word_shifts() -> Result<(), AsmrRuntimeError> {
+        // Shift the 64-bit value edx:eax left by one
+        let ctx = run_code(&[
+            "mov edx, 1",
+            "mov eax, 1",
+            "shl eax, 31",
+            "shl eax, 1",
+            "rcl edx, 1",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Edx), 3);
+        assert_eq!(reg(&ctx, RegisterName::Eax), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_instructions_step_esi_and_edi() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "src db \"abc\", 0",
+            "dst resb 8",
+            "mov esi, src",
+            "mov edi, dst",
+            "mov ecx, 4",
+            "rep movsb",
+            "mov eax, 0x21212121",
+            "stosd",
+            "mov esi, dst",
+            "lodsd",
+        ])?;
+
+        let dst = *ctx.symtab.get("dst").unwrap() as u32;
+        assert_eq!(ctx.memory.read_bytes(dst, 8).unwrap(), b"abc\0!!!!");
+        assert_eq!(reg(&ctx, RegisterName::Eax), 0x0063_6261);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 0);
+        assert_eq!(reg(&ctx, RegisterName::Esi), dst as i32 + 4);
+        assert_eq!(reg(&ctx, RegisterName::Edi), dst as i32 + 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_instructions_step_backwards_when_direction_flag_is_set() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "buf resb 4",
+            "mov edi, buf",
+            "add edi, 3",
+            "mov al, 0x2A",
+            "mov ecx, 4",
+            "std",
+            "rep stosb",
+            "cld",
+        ])?;
+
+        let buf = *ctx.symtab.get("buf").unwrap() as u32;
+        assert_eq!(ctx.memory.read_bytes(buf, 4).unwrap(), b"****");
+        assert_eq!(reg(&ctx, RegisterName::Edi), buf as i32 - 1);
+        assert!(!ctx.flags.get(Flag::DF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn repne_scasb_finds_string_length() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "msg db \"hello\", 0",
+            "mov edi, msg",
+            "mov al, 0",
+            "mov ecx, 0",
+            "not ecx",
+            "repne scasb",
+            "not ecx",
+            "dec ecx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repe_cmpsb_stops_at_first_difference() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "a db \"abcd\"",
+            "b db \"abxd\"",
+            "mov esi, a",
+            "mov edi, b",
+            "mov ecx, 4",
+            "repe cmpsb",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 1);
+        assert!(!ctx.flags.get(Flag::ZF) && ctx.flags.get(Flag::CF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn strings_are_stored_as_escaped_utf8_bytes() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "msg db \"caf\\u{e9}\\n\", `\\x1B`, 'A', 0",
+        ])?;
+
+        let msg = *ctx.symtab.get("msg").unwrap() as u32;
+        assert_eq!(ctx.memory.read_str(msg).unwrap(), "café\n\x1bA".as_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn push_takes_numerics_and_variables() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "msg db \"hi\"",
+            "push 5, msg",
+            "pop ebx, eax",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 5);
+        assert!(matches!(ctx.get_register(RegisterName::Ebx), RegisterData::Pointer(p) if p == DATA_START as i32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pusha_and_popa_save_every_register() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 1",
+            "mov ebx, 2",
+            "mov esi, 3",
+            "mov edx, esp",
+            "pusha",
+            "mov ecx, [esp + 12]",
+            "mov eax, 0",
+            "mov ebx, 0",
+            "mov esi, 0",
+            "popa",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 1);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 2);
+        assert_eq!(reg(&ctx, RegisterName::Esi), 3);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 0);
+        assert_eq!(reg(&ctx, RegisterName::Esp), reg(&ctx, RegisterName::Edx));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pushf_and_popf_save_flags() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 0",
+            "cmp eax, 1",
+            "pushf",
+            "cmp eax, 0",
+            "popf",
+        ])?;
+
+        assert!(ctx.flags.get(Flag::CF) && !ctx.flags.get(Flag::ZF));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lea_computes_addresses_without_memory_access() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "msg db \"hello\"",
+            "lea eax, [msg + 2]",
+            "mov ecx, 3",
+            "lea ebx, [ecx + ecx*4 + 1]",
+            "lea edx, [0]",
+        ])?;
+
+        assert!(matches!(ctx.get_register(RegisterName::Eax), RegisterData::Pointer(p) if p == DATA_START as i32 + 2));
+        assert!(matches!(ctx.get_register(RegisterName::Ebx), RegisterData::Value(16)));
+        assert_eq!(reg(&ctx, RegisterName::Edx), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn labels_can_be_called_through_registers() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, double",
+            "mov ebx, 21",
+            "call eax",
+            "jmp end",
+            "double:",
+            "add ebx, ebx",
+            "ret",
+            "end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn jump_tables_dispatch_on_index() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "table dd case0, case1, case2",
+            "mov ecx, 2",
+            "jmp [table + ecx*4]",
+            "case0:",
+            "mov eax, 10",
+            "jmp end",
+            "case1:",
+            "mov eax, 11",
+            "jmp end",
+            "case2:",
+            "mov eax, 12",
+            "end:",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 12);
+
+        Ok(())
+    }
+
+    #[test]
+    fn indirect_calls_err_on_non_code_address() {
+        let ctx = run_code(&[
+            "msg db \"hi\"",
+            "mov eax, msg",
+            "call eax",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(2, "cannot `call` address `0x1000`, it is not the address of a label").with_span(span(5, 8))));
+
+        let ctx = run_code(&[
+            "mov eax, 5",
+            "jmp eax",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "cannot `jmp` address `0x5`, it is not the address of a label").with_span(span(4, 7))));
+    }
+
+    #[test]
+    fn enter_and_leave_reserve_stack_space() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, esp",
+            "enter 8, 0",
+            "mov dword [ebp - 4], 42",
+            "mov ebx, [ebp - 4]",
+            "mov ecx, ebp",
+            "sub ecx, esp",
+            "leave",
+            "mov edx, esp",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 42);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 8);
+        assert_eq!(reg(&ctx, RegisterName::Edx), reg(&ctx, RegisterName::Eax));
+
+        let ctx = run_code(&[
+            "enter 8, 1",
+        ]);
+
+        assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(0, "`enter` only supports a nesting level of 0").with_span(span(0, 5))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn procedures_resolve_args_and_locals_on_the_stack() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "push 10, 3",
+            "call difference_times_first",
+            "jmp end",
+            "",
+            "proc difference_times_first a, b",
+            "    local tmp",
+            "    mov eax, a",
+            "    mov [tmp], eax",
+            "    sub eax, b",
+            "    imul eax, [tmp]",
+            "    ret",
+            "endp",
+            "",
+            "end:",
+        ])?;
+
+        // (a - b) * a depends on the order of the arguments, which would give 70 if they were swapped
+        assert_eq!(reg(&ctx, RegisterName::Eax), -21);
+
+        Ok(())
+    }
+
+    #[test]
+    fn constants_are_evaluated_when_data_is_laid_out() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov ecx, msg_len",
+            "mov edx, end - msg",
+            "mov esi, msg + 1",
+            "mov edi, $",
+            "msg db \"Hello\", 0",
+            "msg_len equ $ - msg",
+            "table dd $$, $",
+            "end:",
+        ])?;
+
+        let msg = *ctx.symtab.get("msg").unwrap();
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 6);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 7 - msg);
+        assert_eq!(ctx.get_register(RegisterName::Esi), RegisterData::Pointer(msg + 1));
+        assert_eq!(reg(&ctx, RegisterName::Edi), 3);
+        assert_eq!(ctx.memory.read(DATA_START + 6, DataSize::Dword).unwrap().get_raw(), DATA_START as i32);
+        assert_eq!(ctx.memory.read(DATA_START + 10, DataSize::Dword).unwrap().get_raw(), DATA_START as i32 + 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn constants_err_when_used_before_their_data() {
+        let lines = parse_lines(["len equ end - start", "start db 1", "end db 0"].iter()).unwrap();
+        let e = execute(lines).err();
+
+        assert!(e == Some(AsmrRuntimeError::from(0, "`end` must be defined before it is used in an expression.").with_span(span(8, 19))));
+    }
+
+    #[test]
+    fn expressions_err_before_execution() {
+        // Neither instruction runs, but both of their expressions are evaluated
+        for (code, expression) in [("mov eax, msg / 0", span(9, 16)), ("mov eax, [msg + msg / 0]", span(16, 23))] {
+            let ctx = run_code(&["jmp end", code, "end:", "msg db 0"]);
+
+            assert_eq!(ctx.err(), Some(AsmrRuntimeError::from(1, "Division by zero in expression.").with_span(expression)));
+        }
+    }
+
+    #[test]
+    fn cmov_moves_only_when_condition_holds() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "mov eax, 3",
+            "mov ebx, 7",
+            "mov ecx, eax",
+            "cmp eax, ebx",
+            "cmovl ecx, ebx",
+            "mov edx, eax",
+            "cmovg edx, ebx",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 7);
+        assert_eq!(reg(&ctx, RegisterName::Edx), 3);
+
+        let lines = parse_lines([
+            "cmove al, bl",
+        ].iter());
+
+        assert_eq!(lines.err().map(|e| e.cause), Some("`cmove` takes parameters of type <Register16/32, [Register | Memory]>.".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn setcc_stores_condition_as_byte() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "flag resb 1",
+            "mov eax, 0",
+            "not eax",
+            "mov ebx, 0",
+            "cmp ebx, 1",
+            "setb al",
+            "seta ah",
+            "setl byte [flag]",
+            "movzx ecx, byte [flag]",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax) as u32, 0xFFFF_0001);
+        assert_eq!(reg(&ctx, RegisterName::Ecx), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn adc_and_sbb_chain_multi_word_arithmetic() -> Result<(), AsmrRuntimeError> {
+        // edx:eax = 0x1_FFFFFFFF + 1, then ebx:ecx = edx:eax - 2
+        let ctx = run_code(&[
+            "mov edx, 1",
+            "mov eax, 0",
+            "not eax",
+            "add eax, 1",
+            "adc edx, 0",
+            "mov ecx, eax",
+            "mov ebx, edx",
+            "sub ecx, 2",
+            "sbb ebx, 0",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Edx), 2);
+        assert_eq!(reg(&ctx, RegisterName::Eax), 0);
+        assert_eq!(reg(&ctx, RegisterName::Ebx), 1);
+        assert_eq!(reg(&ctx, RegisterName::Ecx) as u32, 0xFFFF_FFFE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flag_instructions_update_single_flags() -> Result<(), AsmrRuntimeError> {
+        let ctx = run_code(&[
+            "stc",
+            "cmc",
+            "cmc",
+            "mov eax, 0",
+            "lahf",
+            "clc",
+            "mov ebx, 0",
+            "cmp ebx, 0",
+            "sahf",
+        ])?;
+
+        // ah holds CF and the reserved bit, restoring it clears ZF again
+        assert_eq!(ctx.get_register(RegisterName::Ah).get_raw(), 0b11);
+        assert!(ctx.flags.get(Flag::CF) && !ctx.flags.get(Flag::ZF));
+
+        let ctx = run_code(&[
+            "pushf",
+            "pop eax",
+            "or eax, 0x400",
+            "push eax",
+            "popf",
+        ])?;
+
+        assert_eq!(reg(&ctx, RegisterName::Eax), 0x402);
+        assert!(ctx.flags.get(Flag::DF));
+
+        Ok(())
+    }
 }
@@ -1,14 +1,37 @@
 use std::{fmt::{self, Display, Formatter}, error::Error};
 
+use crate::{parser::span::Span, diagnostics::{Label, Note}};
+
 #[derive(Debug, PartialEq)]
 pub struct AsmrRuntimeError {
+    pub file: Option<String>,  // The file the line is in, when the code was preprocessed from files
     pub line_number: usize,
+    pub span: Option<Span>,
     pub cause: String,
+    pub labels: Vec<Label>,  // Secondary spans, such as where a duplicate identifier was first defined
+    pub notes: Vec<Note>,
 }
 
 impl AsmrRuntimeError {
     pub fn from(line_number: usize, cause: impl Into<String>) -> Self {
-        AsmrRuntimeError { line_number, cause: cause.into() }
+        AsmrRuntimeError { file: None, line_number, span: None, cause: cause.into(), labels: Vec::new(), notes: Vec::new() }
+    }
+
+    /// Points the error at `span` on its line, unless it already points at a narrower span,
+    /// such as an operand within the instruction that failed.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
     }
 }
 
@@ -16,6 +39,14 @@ impl Error for AsmrRuntimeError { }
 
 impl Display for AsmrRuntimeError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Error on line {}: {}", self.line_number + 1, self.cause)
+        let location = match &self.file {
+            Some(file) => format!("in {file} on line {}", self.line_number + 1),
+            None => format!("on line {}", self.line_number + 1),
+        };
+
+        match self.span {
+            Some(span) => write!(f, "Error {location}, column {}: {}", span.column + 1, self.cause),
+            None => write!(f, "Error {location}: {}", self.cause),
+        }
     }
 }
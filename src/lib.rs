@@ -1,7 +1,13 @@
+// Errors carry their file, spans, labels and notes for diagnostics, and are only built on the cold path
+#![allow(clippy::result_large_err)]
+
 pub mod core;
 pub mod parser;
+pub mod preprocessor;
 pub mod interpreter;
+pub mod diagnostics;
 
-pub use parser::{parse_lines, is_valid_identifier};
+pub use parser::{parse_lines, parse_lines_recovering, is_valid_identifier};
+pub use preprocessor::{preprocess, preprocess_recovering, preprocess_file};
 pub use interpreter::execute;
 pub use interpreter::error::AsmrRuntimeError;
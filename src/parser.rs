@@ -1,14 +1,23 @@
 pub mod error;
+pub mod expression;
 pub mod instruction;
+pub mod lexer;
 pub mod line;
+pub mod prefix;
+pub mod signature;
+pub mod span;
 pub mod token;
 
-use self::{error::ParserError, instruction::Instruction, line::{Line, MemType}, token::Token};
+use self::{error::ParserError, expression::Expression, instruction::Instruction, lexer::{lex, split_commas, text_of, Lexeme, LexemeKind}, line::{Line, MemType}, prefix::Prefix, span::{Span, Spanned}, token::{Token, EffectiveAddress}};
+use crate::{core::register::RegisterName, diagnostics::{Label, Note}};
 
-use std::{iter::Iterator, str::FromStr};
+use std::{collections::HashMap, iter::Iterator, str::FromStr};
 
 /// Parses lines of asmr code into their corresponding representation as [`Line`]s.
 /// 
+/// # Errors
+/// Returns the first error in the code. Use [parse_lines_recovering] to get every error.
+/// 
 /// # Examples
 /// 
 /// ```
@@ -17,126 +26,428 @@ use std::{iter::Iterator, str::FromStr};
 /// let lines = parse_lines(v.iter());
 /// ```
 pub fn parse_lines<L, S>(lines: L) -> Result<Vec<Line>, ParserError>
+where
+    L: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    let (parsed_lines, errors) = parse_lines_recovering(lines);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(parsed_lines),
+    }
+}
+
+/// Parses lines of asmr code like [parse_lines], but recovers from errors to report every error at once.
+/// Lines that fail to parse are kept as [`Line::Invalid`] so that line indices still match the source.
+/// The errors are ordered by line number.
+/// 
+/// # Examples
+/// 
+/// ```
+/// # use assembl_really::parser::{parse_lines_recovering, line::Line};
+/// let v = vec!["mov eax, 0xq", "nop", "call #2"];
+/// let (lines, errors) = parse_lines_recovering(v.iter());
+/// assert_eq!(lines.len(), 3);
+/// assert!(matches!(lines[0], Line::Invalid(_)));
+/// assert_eq!(errors.len(), 2);
+/// ```
+pub fn parse_lines_recovering<L, S>(lines: L) -> (Vec<Line>, Vec<ParserError>)
 where
     L: Iterator<Item = S>,
     S: AsRef<str>,
 {
     let mut parsed_lines = Vec::new();
+    let mut errors = Vec::new();
 
     let mut line_number = 0;
     for line in lines {
         line_number += 1;
-        parsed_lines.push(parse_line(line.as_ref(), line_number)?);
+        match parse_line(line.as_ref(), line_number) {
+            Ok(parsed) => parsed_lines.push(parsed),
+            Err(e) => {
+                let lexemes = lex(line.as_ref());
+                let span = if lexemes.is_empty() { Span::default() } else { text_of(line.as_ref(), &lexemes).1 };
+                parsed_lines.push(Line::Invalid(span));
+                errors.push(e);
+            },
+        }
     }
 
-    Ok(parsed_lines)
+    resolve_procs(&mut parsed_lines, &mut errors);
+    resolve_constants(&mut parsed_lines, &mut errors);
+    check_operands(&parsed_lines, &mut errors);
+
+    // Errors from later passes can be on earlier lines
+    errors.sort_by_key(|e| e.line_number);
+    (parsed_lines, errors)
 }
 
 /// Parses a line of asmr code into its corresponding representation as a [`Line`].
+/// 
+/// Lines are matched on their lexemes against the grammar
+/// ```text
+/// line        = [instruction | directive | constant | declaration | label] [";" comment]
+/// instruction = [prefix] mnemonic [operand {"," operand}]
+/// directive   = "proc" identifier [identifier {"," identifier}]
+///             | "local" identifier {"," identifier}
+///             | "endp"
+/// constant    = identifier "equ" expression
+/// declaration = identifier ("db" | "dd" | "resb") operand {"," operand}
+/// label       = identifier ":"
+/// ```
 fn parse_line(line: &str, line_number: i32) -> Result<Line, ParserError>
 {
-    let mut line = line.trim();
+    let lexemes = lex(line);
+    let error = |span: Span, cause: String| ParserError::from(line_number, span, cause);
 
-    // Check if the line is blank
-    let first_o = line.split_whitespace().next();
-    if first_o == None {
+    // Blank lines and full line comments have no lexemes
+    let Some(first) = lexemes.first() else {
         return Ok(Line::Blank);
-    }
-
-    // Remove inline comments
-    let inline_comments = string_literal_aware_split(line, ';');
-    if inline_comments.len() > 1 {
-        line = inline_comments.first().unwrap().trim();
-    }
+    };
 
-    // Get the first token in the line
-    let first = first_o.unwrap();
+    // The line without surrounding whitespace and comments
+    let (text, line_span) = text_of(line, &lexemes);
 
-    // Ignore full line comments
-    if first.chars().next() == Some(';') {
-        return Ok(Line::Blank);
+    // Split off an instruction prefix
+    let mut rest = lexemes.as_slice();
+    let mut prefix = None;
+    if let Ok(p) = Prefix::from_str(first.text) {
+        rest = &rest[1..];
+        prefix = Some(p);
     }
 
     // Parse instructions
-    if let Ok(instruction) = Instruction::from_str(first) {
-        let remainder = line[first.len()..].trim();
-        let mut params = Vec::new();
-        
-        // Convert the instruction arguments into their corresponding tokens
-        if remainder.chars().last() != None {
-            let tokens: Vec<_> = string_literal_aware_split(remainder, ',').iter()
-                            .map(|s| Token::from_str(s.trim())).collect();
-            
-            // Get all the tokens or throw a ParserError
-            for tok in tokens {
-                match tok {
-                    Ok(t) => params.push(t),
-                    Err(cause) => return Err(ParserError { line_number, cause }),
-                }
+    let keyword = rest.first().map(|l| l.text).unwrap_or_default();
+    if let Ok(instruction) = Instruction::from_str(keyword) {
+        let instruction = Spanned::new(instruction, rest[0].span);
+        if let Some(p) = prefix {
+            if !p.applies_to(&instruction) {
+                return Err(error(first.span.to(instruction.span), format!("`{p}` cannot prefix `{}`. Only string instructions such as `movsb` or `cmpsb` can be repeated.", *instruction)));
             }
         }
 
-        return Ok(Line::Instruction { instruction, params });
+        let params = parse_operands(line, &rest[1..], line_number)?;
+        return Ok(Line::Instruction { prefix, instruction, params });
     }
 
-    // Parse labels
-    else if line.chars().last() == Some(':') {
-        let label = line[..line.len() - 1].trim();
-        if !is_valid_identifier(label) {
-            return Err(ParserError { line_number, cause: format!("Invalid label `{label}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].") });
+    // A prefix must be followed by an instruction
+    else if let Some(p) = prefix {
+        return Err(error(first.span, format!("`{p}` must be followed by an instruction.")));
+    }
+
+    // Parse procedure directives
+    else if keyword == "proc" {
+        let name = match rest.get(1) {
+            Some(name) if is_valid_identifier(name.text) => Spanned::new(name.text.to_string(), name.span),
+            name => {
+                let (name, span) = name.map(|l| (l.text, l.span)).unwrap_or(("", first.span));
+                return Err(error(span, format!("Invalid procedure name `{name}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
+            },
+        };
+
+        let args = parse_identifier_list(line, &rest[2..], line_number)?;
+        return Ok(Line::Proc { name, args, locals: Vec::new() });
+    }
+    else if keyword == "local" {
+        let locals = parse_identifier_list(line, &rest[1..], line_number)?;
+        if locals.is_empty() {
+            return Err(error(first.span, "`local` requires at least one identifier.".to_string()));
         }
-        
-        return Ok(Line::Label(label.to_string()));
+
+        return Ok(Line::Local(locals));
     }
+    else if keyword == "endp" {
+        if rest.len() > 1 {
+            return Err(error(text_of(line, &rest[1..]).1, "`endp` takes no parameters.".to_string()));
+        }
 
-    // Parse variables
-    else if line.contains("db") || line.contains("resb") {
-        let mut identifier;
-        let mut args;
-        let mem_type;
+        return Ok(Line::Endp(first.span));
+    }
 
-        // Get the memory definition type
-        if line.contains("db"){
-            (identifier, args) = line.split_once("db").unwrap();
-            mem_type = MemType::Db;
+    // Parse constant definitions
+    else if lexemes.get(1).is_some_and(|l| l.text == "equ") {
+        if !is_valid_identifier(first.text) {
+            return Err(error(first.span, format!("Invalid identifier `{}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].", first.text)));
         }
-        else {
-            (identifier, args) = line.split_once("resb").unwrap();
-            mem_type = MemType::Resb;
+        else if lexemes.len() == 2 {
+            return Err(error(line_span, "Invalid constant definition syntax. Expected `name equ expression`.".to_string()));
         }
-            
-        identifier = identifier.trim();
-        args = args.trim();
+
+        let (_, span) = text_of(line, &lexemes[2..]);
+        let value = Expression::parse(line, &lexemes[2..]).map_err(|cause| error(span, cause))?;
+        return Ok(Line::Equ { identifier: Spanned::new(first.text.to_string(), first.span), value: Spanned::new(value, span) });
+    }
+
+    // Parse data declarations, where the type must be its own word so identifiers such as `mydb` are not split
+    else if let Some(i) = lexemes.iter().position(|l| l.kind == LexemeKind::Word && MemType::from_str(l.text).is_ok()) {
+        let mem_type = MemType::from_str(lexemes[i].text).unwrap();
+        let args = &lexemes[i + 1..];
 
         // Ensure proper syntax
-        if identifier == "" || args == "" {
-            return Err(ParserError { line_number, cause: "Invalid memory definition syntax.".to_string() });
+        if i == 0 || args.is_empty() {
+            return Err(error(line_span, "Invalid memory definition syntax.".to_string()));
         }
 
         // Ensure valid identifier
+        let (identifier, span) = text_of(line, &lexemes[..i]);
         if !is_valid_identifier(identifier) {
-            return Err(ParserError { line_number, cause: format!("Invalid identifier `{identifier}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].") });
+            return Err(error(span, format!("Invalid identifier `{identifier}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
+        }
+        
+        let params = parse_operands(line, args, line_number)?;
+        return Ok(Line::Variable { identifier: Spanned::new(identifier.to_string(), span), mem_type, params });
+    }
+
+    // Parse labels, which end with a colon
+    else if let Some(label) = text.strip_suffix(':') {
+        let label = label.trim_end();
+        let span = Span::new(line, line_span.start, line_span.start + label.len());
+        if !is_valid_identifier(label) {
+            return Err(error(span, format!("Invalid label `{label}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
         }
         
-        // Convert the memory arguments into their corresponding tokens
-        let mut params = Vec::new();
-        if args.chars().last() != None {
-            let tokens: Vec<_> = string_literal_aware_split(args, ',').iter()
-                            .map(|s| Token::from_str(s.trim())).collect();
-            
-            // Get all the tokens or throw a ParserError
-            for tok in tokens {
-                match tok {
-                    Ok(t) => params.push(t),
-                    Err(cause) => return Err(ParserError { line_number, cause }),
+        return Ok(Line::Label(Spanned::new(label.to_string(), span)));
+    }
+
+    Err(error(line_span, "Could not parse the line. There is likely an uncaught syntax error.".to_string()))
+}
+
+/// Parses the comma separated operands of an instruction or variable into their corresponding tokens.
+fn parse_operands(line: &str, lexemes: &[Lexeme], line_number: i32) -> Result<Vec<Spanned<Token>>, ParserError> {
+    split_commas(lexemes).into_iter().enumerate()
+        .map(|(i, operand)| {
+            if operand.is_empty() {
+                return Err(ParserError::from(line_number, missing_item_span(lexemes, i), "Expected an operand between commas."));
+            }
+
+            let (_, span) = text_of(line, operand);
+            Token::parse(line, operand)
+                .map(|token| Spanned::new(token, span))
+                .map_err(|cause| ParserError::from(line_number, span, cause))
+        })
+        .collect()
+}
+
+/// Parses a comma separated list of identifiers, such as the arguments of a `proc`.
+fn parse_identifier_list(line: &str, lexemes: &[Lexeme], line_number: i32) -> Result<Vec<Spanned<String>>, ParserError> {
+    split_commas(lexemes).into_iter().enumerate()
+        .map(|(i, identifier)| {
+            let (identifier, span) = match identifier {
+                [] => ("", missing_item_span(lexemes, i)),
+                _ => text_of(line, identifier),
+            };
+
+            if is_valid_identifier(identifier) { Ok(Spanned::new(identifier.to_string(), span)) }
+            else { Err(ParserError::from(line_number, span, format!("Invalid identifier `{identifier}`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .]."))) }
+        })
+        .collect()
+}
+
+/// Gets the span of a missing item in a comma separated list,
+/// which is the comma before it, or the comma after it for the first item.
+fn missing_item_span(lexemes: &[Lexeme], index: usize) -> Span {
+    lexemes.iter()
+        .filter(|l| l.kind == LexemeKind::Comma)
+        .nth(index.saturating_sub(1))
+        .map(|l| l.span)
+        .unwrap_or_default()
+}
+
+/// Resolves the arguments and locals of each `proc` to their offsets from ebp within its body,
+/// and records the locals on the `proc` line so that calling it reserves space for them.
+/// 
+/// Because `call` pushes eip then ebp, the frame of a procedure resembles
+/// ```nasm
+/// arg2    ; ebp + 12
+/// arg1    ; ebp + 8
+/// eip     ; ebp + 4
+/// ebp     ; ebp
+/// local1  ; ebp - 4
+/// local2  ; ebp - 8
+/// ```
+/// 
+/// Errors are collected into `errors`, and resolution carries on past them.
+fn resolve_procs(lines: &mut [Line], errors: &mut Vec<ParserError>) {
+    let mut current: Option<(usize, Span)> = None;
+    let mut offsets: HashMap<String, i32> = HashMap::new();
+
+    for i in 0..lines.len() {
+        let line_number = i as i32 + 1;
+
+        match &mut lines[i] {
+            Line::Proc { name, args, .. } => {
+                // Recover by starting the new procedure in place of the unclosed one
+                if let Some((previous, previous_span)) = current {
+                    errors.push(ParserError::from(line_number, name.span, format!("Cannot declare `proc {}` inside another procedure. Close the previous procedure with `endp`.", **name))
+                        .with_label(Label::new(previous + 1, Some(previous_span), "the previous procedure starts here")));
+                }
+
+                current = Some((i, name.span));
+                offsets = args.iter().enumerate().map(|(n, arg)| (arg.node.clone(), 8 + 4 * n as i32)).collect();
+            },
+            Line::Local(names) => {
+                let Some((proc_index, _)) = current else {
+                    let span = names[0].span.to(names[names.len() - 1].span);
+                    errors.push(ParserError::from(line_number, span, "`local` can only be used inside a `proc`.")
+                        .with_note(Note::Help("declare locals on the lines following `proc name`".to_string())));
+                    continue;
+                };
+
+                let names = names.clone();
+                if let Line::Proc { locals, .. } = &mut lines[proc_index] {
+                    for local in names {
+                        offsets.insert(local.node.clone(), -4 * (locals.len() as i32 + 1));
+                        locals.push(local);
+                    }
+                }
+            },
+            Line::Endp(span) if current.is_none() => {
+                errors.push(ParserError::from(line_number, *span, "`endp` without a matching `proc`."));
+            },
+            Line::Endp(_) => current = None,
+            Line::Instruction { params, .. } if current.is_some() => {
+                for param in params {
+                    let span = param.span;
+                    if let Err(cause) = resolve_frame_offset(&mut param.node, &offsets) {
+                        errors.push(ParserError::from(line_number, span, cause));
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if let Some((i, span)) = current {
+        if let Line::Proc { name, .. } = &lines[i] {
+            errors.push(ParserError::from(i as i32 + 1, span, format!("`proc {}` is missing a matching `endp`.", **name))
+                .with_note(Note::Help("add `endp` after the last line of the procedure".to_string())));
+        }
+    }
+}
+
+/// Replaces references to an argument or local in `token` with its address relative to ebp.
+fn resolve_frame_offset(token: &mut Token, offsets: &HashMap<String, i32>) -> Result<(), String> {
+    match token {
+        Token::Identifier(s) => if let Some(offset) = offsets.get(s) {
+            *token = Token::Memory(EffectiveAddress { base: Some(RegisterName::Ebp), displacement: *offset, ..Default::default() });
+        },
+        Token::Memory(ea) => if let Some((symbol, offset)) = ea.symbol.as_ref().and_then(|s| Some((s.node.clone(), *offsets.get(&s.node)?))) {
+            // ebp becomes the base, so an existing base register is used as the index instead
+            match (ea.base, ea.index) {
+                (None, _) => ea.base = Some(RegisterName::Ebp),
+                (Some(base), None) => (ea.base, ea.index) = (Some(RegisterName::Ebp), Some((base, 1))),
+                (Some(_), Some(_)) => return Err(format!("Too many registers to address `{symbol}`. A memory operand can use at most a base and an index register.")),
+            }
+            ea.displacement = ea.displacement.wrapping_add(offset);
+            ea.symbol = None;
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+/// Evaluates `equ` constants and substitutes them into operands, folding constant expressions to their values.
+/// Expressions using `$`, `$$` or the address of a variable or label are left to be evaluated once the program is loaded.
+/// 
+/// Errors are collected into `errors`, and resolution carries on past them.
+fn resolve_constants(lines: &mut [Line], errors: &mut Vec<ParserError>) {
+    // Every name an expression can refer to, where constants cannot share a name with anything else
+    let mut names: HashMap<String, (usize, Span)> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        let (name, is_constant) = match line {
+            Line::Label(name) | Line::Proc { name, .. } | Line::Variable { identifier: name, .. } => (name, false),
+            Line::Equ { identifier, .. } => (identifier, true),
+            _ => continue,
+        };
+
+        match names.get(&name.node) {
+            Some((first, first_span)) if is_constant => {
+                errors.push(ParserError::from(i as i32 + 1, name.span, format!("`{}` is already defined.", name.node))
+                    .with_label(Label::new(first + 1, Some(*first_span), "first defined here")));
+            },
+            Some(_) => {},
+            None => { names.insert(name.node.clone(), (i, name.span)); },
+        }
+    }
+
+    // Constants can refer to constants defined after them, so resolve them until no more can be resolved
+    let mut constants: HashMap<String, i32> = HashMap::new();
+    loop {
+        let resolved = constants.len();
+        for line in lines.iter() {
+            if let Line::Equ { identifier, value } = line {
+                if let (false, Ok(Some(i))) = (constants.contains_key(&identifier.node), value.constant(&constants)) {
+                    constants.insert(identifier.node.clone(), i);
                 }
             }
         }
 
-        return Ok(Line::Variable { identifier: identifier.to_string(), mem_type, params });
+        if constants.len() == resolved { break }
+    }
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let line_number = i as i32 + 1;
+        let mut fold = |expression: &Expression, span: Span| -> Option<i32> {
+            let undefined: Vec<_> = expression.symbols().into_iter().filter(|s| !names.contains_key(&s.node)).collect();
+            for symbol in &undefined {
+                errors.push(ParserError::from(line_number, symbol.span, format!("Undefined name `{}` in expression.", symbol.node)));
+            }
+            if !undefined.is_empty() {
+                return None;
+            }
+
+            expression.constant(&constants).unwrap_or_else(|cause| {
+                errors.push(ParserError::from(line_number, span, cause));
+                None
+            })
+        };
+
+        match line {
+            Line::Equ { value, .. } => if let Some(i) = fold(&value.node, value.span) {
+                value.node = Expression::Number(i);
+            },
+            Line::Instruction { params, .. } | Line::Variable { params, .. } => for param in params {
+                let folded = match &mut param.node {
+                    Token::Expression(e) => fold(e, param.span),
+                    Token::Identifier(name) => constants.get(name).copied(),
+                    Token::Memory(ea) => {
+                        if let Some(i) = ea.symbol.as_ref().and_then(|s| constants.get(&s.node)) {
+                            ea.displacement = ea.displacement.wrapping_add(*i);
+                            ea.symbol = None;
+                        }
+                        if let Some(i) = ea.offset.as_ref().and_then(|offset| fold(offset, offset.span)) {
+                            ea.displacement = ea.displacement.wrapping_add(i);
+                            ea.offset = None;
+                        }
+                        None
+                    },
+                    _ => None,
+                };
+
+                if let Some(i) = folded {
+                    param.node = Token::Numeric(i);
+                }
+            },
+            _ => {},
+        }
     }
+}
 
-    Err(ParserError { line_number, cause: "Could not parse the line. There is likely an uncaught syntax error.".to_string() })
+/// Checks the operands of every instruction against the operand forms in [Instruction::signatures].
+/// This runs once procedures are resolved, as their arguments and locals become memory operands.
+fn check_operands(lines: &[Line], errors: &mut Vec<ParserError>) {
+    for (i, line) in lines.iter().enumerate() {
+        if let Line::Instruction { instruction, params, .. } = line {
+            let span = match (params.first(), params.last()) {
+                (Some(first), Some(last)) => first.span.to(last.span),
+                _ => instruction.span,
+            };
+            if let Err(cause) = instruction.match_signature(params) {
+                errors.push(ParserError::from(i as i32 + 1, span, format!("{cause}.")));
+            }
+        }
+    }
 }
 
 /// Checks whether a given string is a valid asmr identifier.
@@ -160,44 +471,17 @@ pub fn is_valid_identifier(s: &str) -> bool {
     true
 }
 
-/// Splits a string by a pattern provided the pattern is not inside an asmr string literal.
-/// Quotation marks are kept, the matched pattern is not.
-/// 
-/// # Panics
-/// This will panic if `pat == '"'` since splitting is performed based on quotation marks in `s`.
-fn string_literal_aware_split(s: &str, pat: char) -> Vec<String>
-{
-    if pat == '"' {
-        panic!("Split pattern cannot be a quotation mark.")
-    }
-
-    let mut split = Vec::new();
-    let mut is_quoted = false;
-    let mut segment = String::new();
-
-    for c in s.chars() {
-        if c == pat && !is_quoted {
-            split.push(segment);
-            segment = String::new();
-            continue;
-        }
-        
-        if c == '"' {
-            is_quoted = !is_quoted;
-        }
-        segment.push(c);
-    }
-    split.push(segment);
-
-    split
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::register::RegisterName;
+    use crate::core::memory::DataSize;
     type ParserResult = Result<(), ParserError>;
 
+    /// Gets the span of the bytes `start..end` of an ASCII line.
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end, column: start, column_end: end }
+    }
+
     #[test]
     fn parse_line_parses_blank_line() -> ParserResult {
         let s1 = parse_line("", 0)?;
@@ -214,9 +498,9 @@ mod tests {
         let s1 = parse_line("nop ; inline comment", 0)?;
         let s2 = parse_line("push \"semicolon; in; string\" ; inline comment", 0)?;
 
-        assert_eq!(s1, Line::Instruction { instruction: Instruction::Nop, params: vec![] });
-        assert_eq!(s2, Line::Instruction { instruction: Instruction::Push, params: vec![
-            Token::String("semicolon; in; string".to_string()),
+        assert_eq!(s1, Line::Instruction { prefix: None, instruction: Instruction::Nop.into(), params: vec![] });
+        assert_eq!(s2, Line::Instruction { prefix: None, instruction: Instruction::Push.into(), params: vec![
+            Token::String(b"semicolon; in; string".to_vec()).into(),
         ] });
 
         Ok(())
@@ -239,37 +523,238 @@ mod tests {
         let s2 = parse_line("add edx, 5", 0)?;
         let s3 = parse_line("call asmr::io::println", 0)?;
 
-        assert_eq!(s1, Line::Instruction { instruction: Instruction::Mov, params: vec![
-            Token::Register(RegisterName::Eax),
-            Token::Register(RegisterName::Ecx),
+        assert_eq!(s1, Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![
+            Token::Register(RegisterName::Eax).into(),
+            Token::Register(RegisterName::Ecx).into(),
         ] });
 
-        assert_eq!(s2, Line::Instruction { instruction: Instruction::Add, params: vec![
-            Token::Register(RegisterName::Edx),
-            Token::Numeric(5),
+        assert_eq!(s2, Line::Instruction { prefix: None, instruction: Instruction::Add.into(), params: vec![
+            Token::Register(RegisterName::Edx).into(),
+            Token::Numeric(5).into(),
         ] });
 
-        assert_eq!(s3, Line::Instruction { instruction: Instruction::Call, params: vec![
-            Token::Identifier("asmr::io::println".to_string()),
+        assert_eq!(s3, Line::Instruction { prefix: None, instruction: Instruction::Call.into(), params: vec![
+            Token::Identifier("asmr::io::println".to_string()).into(),
         ] });
 
         Ok(())
     }
 
+    #[test]
+    fn parse_line_parses_negative_and_char_operands() -> ParserResult {
+        let s1 = parse_line("mov eax, -1", 0)?;
+        let s2 = parse_line("chars db 'a', '\\n', -128", 0)?;
+
+        assert_eq!(s1, Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![
+            Token::Register(RegisterName::Eax).into(),
+            Token::Numeric(-1).into(),
+        ] });
+        assert_eq!(s2, Line::Variable { identifier: "chars".to_string().into(), mem_type: MemType::Db, params: vec![
+            Token::Numeric(97).into(),
+            Token::Numeric(10).into(),
+            Token::Numeric(-128).into(),
+        ] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_line_records_spans() -> ParserResult {
+        let s1 = parse_line("  add x, [x + 4] ; x", 0)?;
+        let s2 = parse_line("x db \"é\", x", 0)?;
+
+        let Line::Instruction { instruction, params, .. } = s1 else { panic!("expected an instruction") };
+        assert_eq!(instruction.span, span(2, 5));
+        assert_eq!(params.iter().map(|p| p.span).collect::<Vec<_>>(), [span(6, 7), span(9, 16)]);
+        let Token::Memory(EffectiveAddress { symbol: Some(symbol), .. }) = &params[1].node else { panic!("expected a memory operand") };
+        assert_eq!(symbol.span, span(10, 11));
+
+        let Line::Variable { identifier, params, .. } = s2 else { panic!("expected a variable") };
+        assert_eq!(identifier.span, span(0, 1));
+        assert_eq!(params[1].span, Span { start: 11, end: 12, column: 10, column_end: 11 });
+
+        Ok(())
+    }
+
     #[test]
     fn parse_line_parses_instructions_err_on_invalid_param() {
         let s1 = parse_line("mov edx, 0xq", 0);
         let s2 = parse_line("call #2", 0);
         
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Could not parse `0xq` as a hexadecimal value.".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(9, 12), "Could not parse `0xq` as a hexadecimal value.")));
+
+        assert!(s2.err() == Some(ParserError::from(0, span(5, 7), "Could not parse token `#2`. Was this meant to be an identifier? Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
+    }
+
+    #[test]
+    fn parse_line_parses_prefixed_instructions() -> ParserResult {
+        let s1 = parse_line("rep movsb", 0)?;
+        let s2 = parse_line("  repne scasb ; find the terminator", 0)?;
+
+        assert_eq!(s1, Line::Instruction { prefix: Some(Prefix::Rep), instruction: Instruction::Movsb.into(), params: vec![] });
+        assert_eq!(s2, Line::Instruction { prefix: Some(Prefix::Repne), instruction: Instruction::Scasb.into(), params: vec![] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_line_parses_prefixed_instructions_err_on_invalid_prefix() {
+        let s1 = parse_line("rep add eax, 1", 0);
+        let s2 = parse_line("repe movsb", 0);
+        let s3 = parse_line("rep", 0);
+
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 7), "`rep` cannot prefix `add`. Only string instructions such as `movsb` or `cmpsb` can be repeated.")));
+
+        assert!(s2.err() == Some(ParserError::from(0, span(0, 10), "`repe` cannot prefix `movsb`. Only string instructions such as `movsb` or `cmpsb` can be repeated.")));
+
+        assert!(s3.err() == Some(ParserError::from(0, span(0, 3), "`rep` must be followed by an instruction.")));
+    }
+
+    #[test]
+    fn parse_lines_resolves_proc_args_and_locals() -> ParserResult {
+        let lines = parse_lines([
+            "proc add_to a, b",
+            "local tmp, total",
+            "mov eax, a",
+            "add eax, [b + 4]",
+            "mov [total], eax",
+            "mov ecx, dword [esi + tmp]",
+            "endp",
+            "mov eax, a",
+        ].iter())?;
+
+        let ebp = |displacement, index| Token::Memory(EffectiveAddress { base: Some(RegisterName::Ebp), index, displacement, ..Default::default() }).into();
+        let eax = || Token::Register(RegisterName::Eax).into();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string().into()).collect::<Vec<_>>();
+
+        assert_eq!(lines[0], Line::Proc { name: "add_to".to_string().into(), args: names(&["a", "b"]), locals: names(&["tmp", "total"]) });
+        assert_eq!(lines[1], Line::Local(names(&["tmp", "total"])));
+        assert_eq!(lines[2], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![eax(), ebp(8, None)] });
+        assert_eq!(lines[3], Line::Instruction { prefix: None, instruction: Instruction::Add.into(), params: vec![eax(), ebp(16, None)] });
+        assert_eq!(lines[4], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![ebp(-8, None), eax()] });
+        assert_eq!(lines[5], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![
+            Token::Register(RegisterName::Ecx).into(),
+            Token::Memory(EffectiveAddress { size: Some(DataSize::Dword), base: Some(RegisterName::Ebp), index: Some((RegisterName::Esi, 1)), displacement: -4, ..Default::default() }).into(),
+        ] });
+        assert_eq!(lines[6], Line::Endp(span(0, 4)));
+
+        // Arguments are out of scope after `endp`
+        assert_eq!(lines[7], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![eax(), Token::Identifier("a".to_string()).into()] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lines_resolves_proc_args_err_on_unbalanced_procs() {
+        let s1 = parse_lines(["proc f", "proc g", "endp"].iter());
+        let s2 = parse_lines(["local tmp"].iter());
+        let s3 = parse_lines(["nop", "endp"].iter());
+        let s4 = parse_lines(["proc f a", "ret"].iter());
+
+        assert!(s1.err() == Some(ParserError::from(2, span(5, 6), "Cannot declare `proc g` inside another procedure. Close the previous procedure with `endp`.")
+            .with_label(Label::new(1, Some(span(5, 6)), "the previous procedure starts here"))));
+
+        assert!(s2.err() == Some(ParserError::from(1, span(6, 9), "`local` can only be used inside a `proc`.")
+            .with_note(Note::Help("declare locals on the lines following `proc name`".to_string()))));
+
+        assert!(s3.err() == Some(ParserError::from(2, span(0, 4), "`endp` without a matching `proc`.")));
+
+        assert!(s4.err() == Some(ParserError::from(1, span(5, 6), "`proc f` is missing a matching `endp`.")
+            .with_note(Note::Help("add `endp` after the last line of the procedure".to_string()))));
+    }
+
+    #[test]
+    fn parse_lines_err_on_invalid_operands() {
+        let s1 = parse_lines(["mov eax, 1", "mov 5, eax"].iter());
+        let s2 = parse_lines(["push al"].iter());
+        let s3 = parse_lines(["buf resb 8", "mov [buf], [buf + 4]"].iter());
+        let s4 = parse_lines(["ret eax"].iter());
+
+        assert!(s1.err() == Some(ParserError::from(2, span(4, 10), "`mov` takes parameters of type <[Register | Memory], [Register | Memory | Identifier | Numeric]>.")));
+        assert!(s2.err() == Some(ParserError::from(1, span(5, 7), "`push` takes parameters of type <...[Register32 | Memory | Identifier | Numeric]>.")));
+        assert!(s3.err() == Some(ParserError::from(2, span(4, 20), "`mov` cannot take two memory operands.")));
+        assert!(s4.err() == Some(ParserError::from(1, span(4, 7), "`ret` takes no parameters.")));
+    }
+
+    #[test]
+    fn parse_lines_recovering_collects_every_error() {
+        let (lines, errors) = parse_lines_recovering([
+            "mov 5, eax",
+            "foo db 0b12",
+            "nop",
+            "local tmp",
+            "call #2",
+            "12 xor 3",
+        ].iter());
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[1], Line::Invalid(span(0, 11)));
+        assert_eq!(lines[2], Line::Instruction { prefix: None, instruction: Instruction::Nop.into(), params: vec![] });
+        assert_eq!(lines[3], Line::Local(vec!["tmp".to_string().into()]));
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.span)).collect::<Vec<_>>(), [
+            (1, span(4, 10)),
+            (2, span(7, 11)),
+            (4, span(6, 9)),
+            (5, span(5, 7)),
+            (6, span(0, 8)),
+        ]);
+
+        // The first error is the one reported by `parse_lines`
+        assert!(parse_lines(["nop", "mov 5, eax", "call #2"].iter()).err().map(|e| e.line_number) == Some(2));
+    }
+
+    #[test]
+    fn parse_lines_checks_operands_after_resolving_procs() -> ParserResult {
+        let lines = parse_lines(["proc f count", "inc count", "endp"].iter())?;
 
-        assert!(s2.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Could not parse token `#2`. Was this meant to be an identifier? Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].".to_string(),
-        }));
+        assert_eq!(lines[1], Line::Instruction { prefix: None, instruction: Instruction::Inc.into(), params: vec![
+            Token::Memory(EffectiveAddress { base: Some(RegisterName::Ebp), displacement: 8, ..Default::default() }).into(),
+        ] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lines_folds_constant_expressions() -> ParserResult {
+        let lines = parse_lines([
+            "mov ecx, LEN * 2 + 1",
+            "mov eax, [buf + LEN]",
+            "buf resb BUF_SIZE",
+            "LEN equ BUF_SIZE / 4",
+            "BUF_SIZE equ 1 << 4",
+            "msg_len equ $ - buf",
+        ].iter())?;
+
+        assert_eq!(lines[0], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![
+            Token::Register(RegisterName::Ecx).into(),
+            Token::Numeric(9).into(),
+        ] });
+        assert_eq!(lines[1], Line::Instruction { prefix: None, instruction: Instruction::Mov.into(), params: vec![
+            Token::Register(RegisterName::Eax).into(),
+            Token::Memory(EffectiveAddress { symbol: Some("buf".to_string().into()), displacement: 4, ..Default::default() }).into(),
+        ] });
+        assert_eq!(lines[2], Line::Variable { identifier: "buf".to_string().into(), mem_type: MemType::Resb, params: vec![Token::Numeric(16).into()] });
+        assert_eq!(lines[3], Line::Equ { identifier: "LEN".to_string().into(), value: Expression::Number(4).into() });
+
+        // Expressions using positions or addresses are evaluated once the program is loaded
+        let Line::Equ { value, .. } = &lines[5] else { panic!("expected a constant") };
+        assert!(matches!(value.node, Expression::Binary(..)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_lines_folds_constant_expressions_err_on_invalid_constant() {
+        let s1 = parse_lines(["mov eax, SIZE + 1"].iter());
+        let s2 = parse_lines(["x equ 1", "x equ 2"].iter());
+        let s3 = parse_lines(["ZERO equ 0", "push 4 / ZERO"].iter());
+        let s4 = parse_lines(["x equ"].iter());
+
+        assert!(s1.err() == Some(ParserError::from(1, span(9, 13), "Undefined name `SIZE` in expression.")));
+        assert!(s2.err() == Some(ParserError::from(2, span(0, 1), "`x` is already defined.")
+            .with_label(Label::new(1, Some(span(0, 1)), "first defined here"))));
+        assert!(s3.err() == Some(ParserError::from(2, span(5, 13), "Division by zero in expression.")));
+        assert!(s4.err() == Some(ParserError::from(1, span(0, 5), "Invalid constant definition syntax. Expected `name equ expression`.")));
     }
 
     #[test]
@@ -277,8 +762,8 @@ mod tests {
         let s1 = parse_line(".loop:", 0)?;
         let s2 = parse_line(" main: ", 0)?;
 
-        assert_eq!(s1, Line::Label(".loop".to_string()));
-        assert_eq!(s2, Line::Label("main".to_string()));
+        assert_eq!(s1, Line::Label(".loop".to_string().into()));
+        assert_eq!(s2, Line::Label("main".to_string().into()));
 
         Ok(())
     }
@@ -288,15 +773,9 @@ mod tests {
         let s1 = parse_line("#2:", 0);
         let s2 = parse_line("another label:", 0);
 
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Invalid label `#2`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 2), "Invalid label `#2`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
 
-        assert!(s2.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Invalid label `another label`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].".to_string(),
-        }));
+        assert!(s2.err() == Some(ParserError::from(0, span(0, 13), "Invalid label `another label`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
     }
 
     #[test]
@@ -306,67 +785,108 @@ mod tests {
         let s3 = parse_line(" cmplx_ex  db \"this db'ed string, ends with a newline (0xa)\", 0xa", 0)?;
 
         assert_eq!(s1, Line::Variable { 
-            identifier: "msg".to_string(),
+            identifier: "msg".to_string().into(),
             mem_type: MemType::Db,
             params: vec![
-                Token::String("my message".to_string()),
+                Token::String(b"my message".to_vec()).into(),
             ],
         });
 
         assert_eq!(s2, Line::Variable { 
-            identifier: "buffer".to_string(),
+            identifier: "buffer".to_string().into(),
             mem_type: MemType::Resb,
             params: vec![
-                Token::Numeric(50),
+                Token::Numeric(50).into(),
             ],
         });
 
         assert_eq!(s3, Line::Variable { 
-            identifier: "cmplx_ex".to_string(),
+            identifier: "cmplx_ex".to_string().into(),
             mem_type: MemType::Db,
             params: vec![
-                Token::String("this db'ed string, ends with a newline (0xa)".to_string()),
-                Token::Numeric(0xa),
+                Token::String(b"this db'ed string, ends with a newline (0xa)".to_vec()).into(),
+                Token::Numeric(0xa).into(),
+            ],
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_line_parses_dword_tables() -> ParserResult {
+        let s1 = parse_line("jump_table dd case0, case1, 7", 0)?;
+        let s2 = parse_line("address dd 0", 0)?;
+
+        assert_eq!(s1, Line::Variable { 
+            identifier: "jump_table".to_string().into(),
+            mem_type: MemType::Dd,
+            params: vec![
+                Token::Identifier("case0".to_string()).into(),
+                Token::Identifier("case1".to_string()).into(),
+                Token::Numeric(7).into(),
+            ],
+        });
+
+        assert_eq!(s2, Line::Variable { 
+            identifier: "address".to_string().into(),
+            mem_type: MemType::Dd,
+            params: vec![
+                Token::Numeric(0).into(),
             ],
         });
 
         Ok(())
     }
 
+    #[test]
+    fn parse_line_parses_identifiers_containing_directive_names() -> ParserResult {
+        let s1 = parse_line("feedback:", 0)?;
+        let s2 = parse_line("mydb2 db 1", 0)?;
+        let s3 = parse_line("resbuf resb 4", 0)?;
+        let s4 = parse_line("add_dd dd feedback", 0)?;
+        let s5 = parse_line("mov eax, [mydb2 + 4]", 0)?;
+
+        assert_eq!(s1, Line::Label("feedback".to_string().into()));
+        assert_eq!(s2, Line::Variable { identifier: "mydb2".to_string().into(), mem_type: MemType::Db, params: vec![Token::Numeric(1).into()] });
+        assert_eq!(s3, Line::Variable { identifier: "resbuf".to_string().into(), mem_type: MemType::Resb, params: vec![Token::Numeric(4).into()] });
+        assert_eq!(s4, Line::Variable { identifier: "add_dd".to_string().into(), mem_type: MemType::Dd, params: vec![Token::Identifier("feedback".to_string()).into()] });
+        assert!(matches!(s5, Line::Instruction { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_line_parses_identifiers_containing_directive_names_err_on_misplaced_directive() {
+        let s1 = parse_line("dbg db", 0);
+        let s2 = parse_line("my var db 1", 0);
+
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 6), "Invalid memory definition syntax.")));
+
+        assert!(s2.err() == Some(ParserError::from(0, span(0, 6), "Invalid identifier `my var`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
+    }
+
     #[test]
     fn parse_line_parses_variables_err_on_invalid_syntax() {
         let s1 = parse_line("msg db  ", 0);
         let s2 = parse_line("db 35", 0);
 
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Invalid memory definition syntax.".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 6), "Invalid memory definition syntax.")));
 
-        assert!(s2.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Invalid memory definition syntax.".to_string(),
-        }));
+        assert!(s2.err() == Some(ParserError::from(0, span(0, 5), "Invalid memory definition syntax.")));
     }
 
     #[test]
     fn parse_line_parses_variables_err_on_invalid_identifier() {
         let s1 = parse_line("$5 db \"five dollars\"", 0);
 
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Invalid identifier `$5`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 2), "Invalid identifier `$5`. Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")));
     }
 
     #[test]
     fn parse_line_parses_variables_err_on_invalid_param() {
         let s1 = parse_line("foo db 0b12", 0);
         
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Could not parse `0b12` as a binary value.".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(7, 11), "Could not parse `0b12` as a binary value.")));
     }
 
     #[test]
@@ -374,15 +894,9 @@ mod tests {
         let s1 = parse_line("#this should never_compile", 0);
         let s2 = parse_line("12 xor 3 ; invalid instruction order", 0);
 
-        assert!(s1.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Could not parse the line. There is likely an uncaught syntax error.".to_string(),
-        }));
+        assert!(s1.err() == Some(ParserError::from(0, span(0, 26), "Could not parse the line. There is likely an uncaught syntax error.")));
 
-        assert!(s2.err() == Some(ParserError {
-            line_number: 0,
-            cause: "Could not parse the line. There is likely an uncaught syntax error.".to_string(),
-        }));
+        assert!(s2.err() == Some(ParserError::from(0, span(0, 8), "Could not parse the line. There is likely an uncaught syntax error.")));
     }
 
     #[test]
@@ -406,30 +920,4 @@ mod tests {
         assert!(!is_valid_identifier(s2));
         assert!(!is_valid_identifier(s3));
     }
-
-    #[test]
-    fn string_literal_aware_split_parses_with_no_strings() {
-        let s1 = string_literal_aware_split("regular,list,of,words", ',');
-        let s2 = string_literal_aware_split("semicolons;work;too", ';');
-
-        assert_eq!(s1, vec!["regular", "list", "of", "words"]);
-        assert_eq!(s2, vec!["semicolons", "work", "too"]);
-    }
-    
-    #[test]
-    fn string_literal_aware_split_parses_with_strings() {
-        let s1 = string_literal_aware_split("\"Hello, World!\"", ',');
-        let s2 = string_literal_aware_split("mov eax, \"Welcome, friend.\"", ',');
-        let s3 = string_literal_aware_split("push \"programming; computers\" ; this is a comment", ';');
-
-        assert_eq!(s1, vec!["\"Hello, World!\""]);
-        assert_eq!(s2, vec!["mov eax", " \"Welcome, friend.\""]);
-        assert_eq!(s3, vec!["push \"programming; computers\" ", " this is a comment"]);
-    }
-
-    #[test]
-    #[should_panic(expected = "Split pattern cannot be a quotation mark.")]
-    fn string_literal_aware_split_panics_on_quote_pat() {
-        string_literal_aware_split("some arbitrary string", '"');
-    }
 }
@@ -1,15 +1,41 @@
 use std::{fmt::{self, Display, Formatter}, error::Error};
 
+use super::span::Span;
+use crate::diagnostics::{Label, Note};
+
 #[derive(Debug, PartialEq)]
 pub struct ParserError {
+    pub file: Option<String>,  // The file the line is in, when the code was preprocessed from files
     pub line_number: i32,
+    pub span: Span,
     pub cause: String,
+    pub labels: Vec<Label>,  // Secondary spans, such as where a conflicting item was declared
+    pub notes: Vec<Note>,
+}
+
+impl ParserError {
+    pub fn from(line_number: i32, span: Span, cause: impl Into<String>) -> Self {
+        ParserError { file: None, line_number, span, cause: cause.into(), labels: Vec::new(), notes: Vec::new() }
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
+    }
 }
 
 impl Error for ParserError { }
 
 impl Display for ParserError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Error on line {}: {}", self.line_number, self.cause)
+        match &self.file {
+            Some(file) => write!(f, "Error in {file} on line {}, column {}: {}", self.line_number, self.span.column + 1, self.cause),
+            None => write!(f, "Error on line {}, column {}: {}", self.line_number, self.span.column + 1, self.cause),
+        }
     }
 }
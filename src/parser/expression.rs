@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use crate::core::register::RegisterData;
+use super::{lexer::{text_of, Lexeme, LexemeKind}, span::Spanned, token::Token};
+
+/// How deeply operators and parentheses can nest in an expression, which stops deep expressions from overflowing the stack.
+pub(super) const MAX_NESTING: usize = 256;
+
+/// A constant expression, such as `(BUF_SIZE + 1) * 4` or `$ - msg`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(i32),
+    Symbol(Spanned<String>),  // An `equ` constant, or the address of a variable or label
+    Here,                     // $, the address of the current line
+    Start,                    // $$, the address of the start of the current section
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOperator {
+    Negate,  // -
+    Not,     // ~
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Or,          // |
+    Xor,         // ^
+    And,         // &
+    ShiftLeft,   // <<
+    ShiftRight,  // >>, which is logical
+    Add,         // +
+    Subtract,    // -
+    Multiply,    // *
+    Divide,      // /, which is signed
+    Remainder,   // %, which is signed
+}
+
+impl BinaryOperator {
+    fn from_lexeme(kind: LexemeKind) -> Option<Self> {
+        match kind {
+            LexemeKind::Pipe => Some(BinaryOperator::Or),
+            LexemeKind::Caret => Some(BinaryOperator::Xor),
+            LexemeKind::Ampersand => Some(BinaryOperator::And),
+            LexemeKind::ShiftLeft => Some(BinaryOperator::ShiftLeft),
+            LexemeKind::ShiftRight => Some(BinaryOperator::ShiftRight),
+            LexemeKind::Plus => Some(BinaryOperator::Add),
+            LexemeKind::Minus => Some(BinaryOperator::Subtract),
+            LexemeKind::Star => Some(BinaryOperator::Multiply),
+            LexemeKind::Slash => Some(BinaryOperator::Divide),
+            LexemeKind::Percent => Some(BinaryOperator::Remainder),
+            _ => None,
+        }
+    }
+
+    /// Operators with a higher precedence bind tighter, following NASM.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 0,
+            BinaryOperator::Xor => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 3,
+            BinaryOperator::Add | BinaryOperator::Subtract => 4,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 5,
+        }
+    }
+}
+
+impl Expression {
+    /// Parses the lexemes of an expression, such as `LEN * 2 + 1`, that were lexed from `line`.
+    ///
+    /// # Panics
+    /// This will panic if `lexemes` is empty.
+    pub fn parse(line: &str, lexemes: &[Lexeme]) -> Result<Expression, String> {
+        let mut parser = ExpressionParser { line, lexemes, pos: 0 };
+        let (expression, _) = parser.binary(0, 0)?;
+
+        match lexemes.get(parser.pos) {
+            None => Ok(expression),
+            Some(_) => Err(parser.invalid()),
+        }
+    }
+
+    /// Calls `f` on this expression and every expression within it.
+    fn visit<'a>(&'a self, f: &mut impl FnMut(&'a Expression)) {
+        f(self);
+        match self {
+            Expression::Unary(_, e) => e.visit(f),
+            Expression::Binary(_, lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            },
+            _ => {},
+        }
+    }
+
+    /// Gets every name used in the expression.
+    pub fn symbols(&self) -> Vec<&Spanned<String>> {
+        let mut symbols = Vec::new();
+        self.visit(&mut |e| if let Expression::Symbol(s) = e { symbols.push(s) });
+        symbols
+    }
+
+    /// Evaluates the expression at parse time, if every name in it is one of `constants` and it does not use `$` or `$$`.
+    /// Returns None when it can only be evaluated once the program is loaded.
+    pub fn constant(&self, constants: &HashMap<String, i32>) -> Result<Option<i32>, String> {
+        let mut is_constant = true;
+        self.visit(&mut |e| match e {
+            Expression::Symbol(s) => is_constant &= constants.contains_key(&s.node),
+            Expression::Here | Expression::Start => is_constant = false,
+            _ => {},
+        });
+
+        if !is_constant {
+            return Ok(None);
+        }
+
+        let lookup = |name: &str| constants.get(name).map(|v| RegisterData::Value(*v));
+        self.evaluate(&lookup, RegisterData::Value(0), RegisterData::Value(0)).map(|v| Some(v.get_raw()))
+    }
+
+    /// Evaluates the expression, looking up names with `lookup`, where `$` is `here` and `$$` is `start`.
+    /// The result is a pointer when it is an address offset by a constant, such as `msg + 1`, and a value otherwise.
+    pub fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<RegisterData>, here: RegisterData, start: RegisterData) -> Result<RegisterData, String> {
+        use RegisterData::{Pointer, Value};
+
+        match self {
+            Expression::Number(i) => Ok(Value(*i)),
+            Expression::Symbol(s) => lookup(&s.node).ok_or_else(|| format!("`{s}` must be defined before it is used in an expression.")),
+            Expression::Here => Ok(here),
+            Expression::Start => Ok(start),
+            Expression::Unary(op, e) => {
+                let i = e.evaluate(lookup, here, start)?.get_raw();
+                Ok(Value(match op {
+                    UnaryOperator::Negate => i.wrapping_neg(),
+                    UnaryOperator::Not => !i,
+                }))
+            },
+            Expression::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(lookup, here, start)?, rhs.evaluate(lookup, here, start)?);
+                let (a, b) = (lhs.get_raw(), rhs.get_raw());
+
+                Ok(match (op, lhs, rhs) {
+                    // An address stays an address when offset, but the distance between two addresses is a value
+                    (BinaryOperator::Add, Pointer(_), Value(_)) | (BinaryOperator::Add, Value(_), Pointer(_)) => Pointer(a.wrapping_add(b)),
+                    (BinaryOperator::Subtract, Pointer(_), Value(_)) => Pointer(a.wrapping_sub(b)),
+
+                    (BinaryOperator::Add, ..) => Value(a.wrapping_add(b)),
+                    (BinaryOperator::Subtract, ..) => Value(a.wrapping_sub(b)),
+                    (BinaryOperator::Multiply, ..) => Value(a.wrapping_mul(b)),
+                    (BinaryOperator::Divide | BinaryOperator::Remainder, ..) if b == 0 => return Err("Division by zero in expression.".to_string()),
+                    (BinaryOperator::Divide, ..) => Value(a.wrapping_div(b)),
+                    (BinaryOperator::Remainder, ..) => Value(a.wrapping_rem(b)),
+                    (BinaryOperator::ShiftLeft, ..) => Value((a as u32).checked_shl(b as u32).unwrap_or(0) as i32),
+                    (BinaryOperator::ShiftRight, ..) => Value((a as u32).checked_shr(b as u32).unwrap_or(0) as i32),
+                    (BinaryOperator::And, ..) => Value(a & b),
+                    (BinaryOperator::Or, ..) => Value(a | b),
+                    (BinaryOperator::Xor, ..) => Value(a ^ b),
+                })
+            },
+        }
+    }
+}
+
+/// Parses expressions by precedence climbing over their lexemes.
+struct ExpressionParser<'a, 'b> {
+    line: &'a str,
+    lexemes: &'b [Lexeme<'a>],
+    pos: usize,
+}
+
+impl<'a, 'b> ExpressionParser<'a, 'b> {
+    fn invalid(&self) -> String {
+        let (s, _) = text_of(self.line, self.lexemes);
+        format!("Could not parse `{s}` as an expression. Expressions are made of numbers, names, `$`, `$$`, parentheses and the operators `+ - * / % << >> & | ^ ~`.")
+    }
+
+    /// Checks that an expression nested `depth` deep is within [MAX_NESTING].
+    fn nest(&self, depth: usize) -> Result<usize, String> {
+        match depth {
+            depth if depth <= MAX_NESTING => Ok(depth),
+            _ => Err(format!("This expression is nested too deeply. Expressions can nest operators and parentheses at most {MAX_NESTING} deep.")),
+        }
+    }
+
+    fn next(&mut self) -> Option<&'b Lexeme<'a>> {
+        let lexeme = self.lexemes.get(self.pos);
+        self.pos += 1;
+        lexeme
+    }
+
+    /// Parses binary operations whose operators have at least `min_precedence`, `depth` operators and parentheses deep.
+    /// Returns the expression along with how deeply operations nest within it.
+    fn binary(&mut self, min_precedence: u8, depth: usize) -> Result<(Expression, usize), String> {
+        let (mut lhs, mut height) = self.unary(depth)?;
+
+        while let Some(op) = self.lexemes.get(self.pos).and_then(|l| BinaryOperator::from_lexeme(l.kind)) {
+            if op.precedence() < min_precedence { break }
+
+            // Operators of equal precedence are left associative
+            self.pos += 1;
+            let (rhs, rhs_height) = self.binary(op.precedence() + 1, self.nest(depth + 1)?)?;
+            height = self.nest(height.max(rhs_height) + 1)?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok((lhs, height))
+    }
+
+    /// Parses unary operations, parentheses and single values, `depth` operators and parentheses deep.
+    fn unary(&mut self, depth: usize) -> Result<(Expression, usize), String> {
+        self.nest(depth)?;
+        let Some(lexeme) = self.next() else { return Err(self.invalid()) };
+
+        let unary = |parser: &mut Self, op| {
+            let (e, height) = parser.unary(depth + 1)?;
+            Ok((Expression::Unary(op, Box::new(e)), parser.nest(height + 1)?))
+        };
+
+        match lexeme.kind {
+            LexemeKind::Plus => self.unary(depth + 1),
+            LexemeKind::Minus => unary(self, UnaryOperator::Negate),
+            LexemeKind::Tilde => unary(self, UnaryOperator::Not),
+            LexemeKind::OpenParen => {
+                let expression = self.binary(0, depth + 1)?;
+                match self.next() {
+                    Some(l) if l.kind == LexemeKind::CloseParen => Ok(expression),
+                    _ => {
+                        let (s, _) = text_of(self.line, self.lexemes);
+                        Err(format!("Missing `)` in `{s}`."))
+                    },
+                }
+            },
+            LexemeKind::Word if lexeme.text == "$" => Ok((Expression::Here, 0)),
+            LexemeKind::Word if lexeme.text == "$$" => Ok((Expression::Start, 0)),
+            LexemeKind::Word | LexemeKind::String => match Token::parse(self.line, std::slice::from_ref(lexeme))? {
+                Token::Numeric(i) => Ok((Expression::Number(i), 0)),
+                Token::Identifier(name) => Ok((Expression::Symbol(Spanned::new(name, lexeme.span)), 0)),
+                Token::Register(r) => Err(format!("Registers such as `{r}` cannot be used in expressions, which are evaluated before the program runs.")),
+                _ => Err(self.invalid()),
+            },
+            _ => Err(self.invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::lex;
+
+    fn evaluate(s: &str, constants: &[(&str, i32)]) -> Result<Option<i32>, String> {
+        let constants = constants.iter().map(|(name, v)| (name.to_string(), *v)).collect();
+        Expression::parse(s, &lex(s))?.constant(&constants)
+    }
+
+    #[test]
+    fn expressions_follow_operator_precedence() -> Result<(), String> {
+        assert_eq!(evaluate("1 + 2 * 3", &[])?, Some(7));
+        assert_eq!(evaluate("(1 + 2) * 3", &[])?, Some(9));
+        assert_eq!(evaluate("10 - 4 - 3", &[])?, Some(3));
+        assert_eq!(evaluate("1 << 4 | 1 & 3 ^ 2", &[])?, Some(0b10011));
+        assert_eq!(evaluate("-7 / 2 + -7 % 2", &[])?, Some(-4));
+        assert_eq!(evaluate("~0 >> 28", &[])?, Some(0xf));
+        assert_eq!(evaluate("'A' + LEN * 2", &[("LEN", 3)])?, Some(71));
+
+        Ok(())
+    }
+
+    #[test]
+    fn expressions_with_positions_or_addresses_are_not_constant() -> Result<(), String> {
+        assert_eq!(evaluate("$ - msg", &[])?, None);
+        assert_eq!(evaluate("$$", &[])?, None);
+        assert_eq!(evaluate("LEN + 1", &[])?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn expressions_track_addresses() -> Result<(), String> {
+        let e = |s: &str| Expression::parse(s, &lex(s));
+        let lookup = |name: &str| (name == "msg").then_some(RegisterData::Pointer(0x1000));
+        let here = RegisterData::Pointer(0x1005);
+
+        assert_eq!(e("msg + 1")?.evaluate(&lookup, here, here)?, RegisterData::Pointer(0x1001));
+        assert_eq!(e("$ - msg")?.evaluate(&lookup, here, here)?, RegisterData::Value(5));
+        assert_eq!(e("msg * 2")?.evaluate(&lookup, here, here)?, RegisterData::Value(0x2000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn expressions_err_on_invalid_syntax() {
+        assert_eq!(evaluate("1 / (2 - 2)", &[]).err(), Some("Division by zero in expression.".to_string()));
+        assert_eq!(evaluate("(1 + 2", &[]).err(), Some("Missing `)` in `(1 + 2`.".to_string()));
+        assert_eq!(evaluate("eax + 1", &[]).err(), Some("Registers such as `eax` cannot be used in expressions, which are evaluated before the program runs.".to_string()));
+        assert_eq!(evaluate("1 +", &[]).err(), Some("Could not parse `1 +` as an expression. Expressions are made of numbers, names, `$`, `$$`, parentheses and the operators `+ - * / % << >> & | ^ ~`.".to_string()));
+        assert_eq!(evaluate("1 2", &[]).err(), Some("Could not parse `1 2` as an expression. Expressions are made of numbers, names, `$`, `$$`, parentheses and the operators `+ - * / % << >> & | ^ ~`.".to_string()));
+
+        let nested = "This expression is nested too deeply. Expressions can nest operators and parentheses at most 256 deep.".to_string();
+        assert_eq!(evaluate(&format!("{}1", "-".repeat(257)), &[]).err(), Some(nested.clone()));
+        assert_eq!(evaluate(&format!("{}1{}", "(".repeat(257), ")".repeat(257)), &[]).err(), Some(nested.clone()));
+        assert_eq!(evaluate(&format!("1{}", " + 1".repeat(257)), &[]).err(), Some(nested));
+        assert_eq!(evaluate(&format!("{}1", "-".repeat(256)), &[]), Ok(Some(1)));
+    }
+}
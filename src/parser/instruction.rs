@@ -1,5 +1,9 @@
 use strum::{EnumString, EnumIter, Display};
 
+use crate::core::flags::Condition;
+
+use super::{signature::{Operand::{self, *}, Signature}, span::Spanned, token::Token};
+
 /// https://github.com/michaelsergio/nasm-instruction-set/blob/master/README.md
 #[derive(Debug, PartialEq)]
 #[derive(EnumString, EnumIter, Display)]
@@ -10,21 +14,62 @@ pub enum Instruction {
 // Stack
     Push,
     Pop,
+    Pusha,
+    Popa,
+    Pushf,
+    Popf,
 
 // Move
     Mov,
+    Lea,
+    Movsx,
+    Movzx,
     Xchg,
     
 // Arithmetic
     Add,
     Sub,
+    Adc,
+    Sbb,
     Mul,
+    Imul,
     Div,
+    Idiv,
+    Cdq,
     Inc,
     Dec,
+    Neg,
+
+// Shifts and rotates
     Shl,
     Shr,
+    Sar,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shld,
+    Shrd,
     
+// Strings
+    Movsb,
+    Movsd,
+    Stosb,
+    Stosd,
+    Lodsb,
+    Lodsd,
+    Cmpsb,
+    Scasb,
+
+// Flags
+    Clc,
+    Stc,
+    Cmc,
+    Cld,
+    Std,
+    Lahf,
+    Sahf,
+
 // Comparisons
     Cmp,
     And,
@@ -43,8 +88,239 @@ pub enum Instruction {
     Jle,
     Je,
     Jne,
+    Ja,
+    Jae,
+    Jb,
+    Jbe,
+    Jc,
+    Jnc,
+    Jo,
+    Jno,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jcxz,
+    Jecxz,
+
+// Loops
+    Loop,
+    Loope,
+    Loopne,
+
+// Conditional moves
+    Cmovz,
+    Cmovnz,
+    Cmovg,
+    Cmovl,
+    Cmovge,
+    Cmovle,
+    Cmove,
+    Cmovne,
+    Cmova,
+    Cmovae,
+    Cmovb,
+    Cmovbe,
+    Cmovc,
+    Cmovnc,
+    Cmovo,
+    Cmovno,
+    Cmovs,
+    Cmovns,
+    Cmovp,
+    Cmovnp,
+
+// Set on condition
+    Setz,
+    Setnz,
+    Setg,
+    Setl,
+    Setge,
+    Setle,
+    Sete,
+    Setne,
+    Seta,
+    Setae,
+    Setb,
+    Setbe,
+    Setc,
+    Setnc,
+    Seto,
+    Setno,
+    Sets,
+    Setns,
+    Setp,
+    Setnp,
     
 // Functions
     Call,
     Ret,
+    Enter,
+    Leave,
+}
+
+// Operand kinds shared by many instructions
+const RM: &[Operand] = &[Register, Memory];
+const RMN: &[Operand] = &[Register, Memory, Numeric];
+const COUNT: &[Operand] = &[Numeric, Cl];
+const TARGET: &[Operand] = &[Identifier, Register, Memory];
+
+const NONE: &[Signature] = &[Signature::Fixed(&[])];
+const UNARY: &[Signature] = &[Signature::Fixed(&[RM])];
+const BINARY: &[Signature] = &[Signature::Fixed(&[RM, RMN])];
+const SHIFT: &[Signature] = &[Signature::Fixed(&[RM, COUNT])];
+const DOUBLE_SHIFT: &[Signature] = &[Signature::Fixed(&[RM, &[Register], COUNT])];
+const JUMP: &[Signature] = &[Signature::Fixed(&[TARGET])];
+const CONDITIONAL_JUMP: &[Signature] = &[Signature::Fixed(&[&[Identifier]])];
+const CONDITIONAL_MOVE: &[Signature] = &[Signature::Fixed(&[&[WideRegister], RM])];
+const SET: &[Signature] = &[Signature::Fixed(&[&[Register8, Memory8]])];
+
+impl Instruction {
+    /// Gets the operand forms accepted by the instruction.
+    /// The parser rejects any other operands, and the interpreter dispatches on the matched form.
+    pub fn signatures(&self) -> &'static [Signature] {
+        use Instruction::*;
+
+        match self {
+            Nop | Pusha | Popa | Pushf | Popf | Cdq => NONE,
+            Movsb | Movsd | Stosb | Stosd | Lodsb | Lodsd | Cmpsb | Scasb => NONE,
+            Clc | Stc | Cmc | Cld | Std | Lahf | Sahf => NONE,
+            Ret | Leave => NONE,
+
+            Push => &[Signature::Variadic(&[Register32, Memory, Identifier, Numeric])],
+            Pop => &[Signature::Variadic(&[Register32, Memory])],
+
+            Mov => &[Signature::Fixed(&[RM, &[Register, Memory, Identifier, Numeric]])],
+            Lea => &[Signature::Fixed(&[&[Register32], &[Memory]])],
+            Movsx | Movzx => &[Signature::Fixed(&[&[WideRegister], RM])],
+            Xchg => &[Signature::Fixed(&[RM, RM])],
+
+            Add | Sub | Adc | Sbb | Cmp | And | Or | Xor | Test => BINARY,
+            Mul | Div | Idiv | Inc | Dec | Neg | Not => UNARY,
+            Imul => &[
+                Signature::Fixed(&[RM]),
+                Signature::Fixed(&[&[Register], RMN]),
+                Signature::Fixed(&[&[Register], RM, &[Numeric]]),
+            ],
+
+            Shl | Shr | Sar | Rol | Ror | Rcl | Rcr => SHIFT,
+            Shld | Shrd => DOUBLE_SHIFT,
+
+            Jmp | Call => JUMP,
+            Jz | Jnz | Jg | Jl | Jge | Jle | Je | Jne | Ja | Jae | Jb | Jbe |
+            Jc | Jnc | Jo | Jno | Js | Jns | Jp | Jnp | Jcxz | Jecxz => CONDITIONAL_JUMP,
+            Loop | Loope | Loopne => CONDITIONAL_JUMP,
+            Enter => &[Signature::Fixed(&[&[Numeric], &[Numeric]])],
+
+            Cmovz | Cmovnz | Cmovg | Cmovl | Cmovge | Cmovle | Cmove | Cmovne | Cmova | Cmovae |
+            Cmovb | Cmovbe | Cmovc | Cmovnc | Cmovo | Cmovno | Cmovs | Cmovns | Cmovp | Cmovnp => CONDITIONAL_MOVE,
+            Setz | Setnz | Setg | Setl | Setge | Setle | Sete | Setne | Seta | Setae |
+            Setb | Setbe | Setc | Setnc | Seto | Setno | Sets | Setns | Setp | Setnp => SET,
+        }
+    }
+
+    /// Finds the operand form accepted by `params`, returning its index in [Instruction::signatures].
+    /// 
+    /// # Errors
+    /// Returns a description of the accepted operand forms if none match,
+    /// or if more than one operand is a memory operand.
+    pub fn match_signature(&self, params: &[Spanned<Token>]) -> Result<usize, String> {
+        let signatures = self.signatures();
+        let Some(index) = signatures.iter().position(|s| s.accepts(params)) else {
+            return Err(self.describe_signatures());
+        };
+
+        let memory_operands = params.iter().filter(|p| matches!(p.node, Token::Memory(_))).count();
+        if memory_operands > 1 && matches!(signatures[index], Signature::Fixed(_)) {
+            return Err(format!("`{self}` cannot take two memory operands"));
+        }
+
+        Ok(index)
+    }
+
+    /// Describes the operand forms accepted by the instruction,
+    /// e.g. "`shl` takes parameters of type <[Register | Memory], [Numeric | cl]>".
+    pub fn describe_signatures(&self) -> String {
+        match self.signatures() {
+            [Signature::Fixed([])] => format!("`{self}` takes no parameters"),
+            [signature @ Signature::Fixed([_])] => format!("`{self}` takes one parameter of type {signature}"),
+            signatures => {
+                let forms: Vec<_> = signatures.iter().map(Signature::to_string).collect();
+                format!("`{self}` takes parameters of type {}", forms.join(" or "))
+            },
+        }
+    }
+
+    /// Gets the condition tested by a conditional jump, move or set.
+    /// Each condition is shared by its jump, e.g. `jg`, its move, e.g. `cmovg`, and its set, e.g. `setg`.
+    pub fn condition(&self) -> Option<Condition> {
+        use Instruction::*;
+
+        match self {
+            Jo | Cmovo | Seto => Some(Condition::Overflow),
+            Jno | Cmovno | Setno => Some(Condition::NotOverflow),
+            Jb | Jc | Cmovb | Cmovc | Setb | Setc => Some(Condition::Below),
+            Jae | Jnc | Cmovae | Cmovnc | Setae | Setnc => Some(Condition::AboveOrEqual),
+            Je | Jz | Cmove | Cmovz | Sete | Setz => Some(Condition::Equal),
+            Jne | Jnz | Cmovne | Cmovnz | Setne | Setnz => Some(Condition::NotEqual),
+            Jbe | Cmovbe | Setbe => Some(Condition::BelowOrEqual),
+            Ja | Cmova | Seta => Some(Condition::Above),
+            Js | Cmovs | Sets => Some(Condition::Sign),
+            Jns | Cmovns | Setns => Some(Condition::NotSign),
+            Jp | Cmovp | Setp => Some(Condition::Parity),
+            Jnp | Cmovnp | Setnp => Some(Condition::NotParity),
+            Jl | Cmovl | Setl => Some(Condition::Less),
+            Jge | Cmovge | Setge => Some(Condition::GreaterOrEqual),
+            Jle | Cmovle | Setle => Some(Condition::LessOrEqual),
+            Jg | Cmovg | Setg => Some(Condition::Greater),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::register::RegisterName, parser::token::EffectiveAddress};
+    use std::str::FromStr;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn condition_is_shared_by_jumps_moves_and_sets() {
+        for instruction in Instruction::iter() {
+            let name = instruction.to_string();
+            let Some(suffix) = name.strip_prefix("cmov").or_else(|| name.strip_prefix("set")) else { continue };
+
+            let jump = Instruction::from_str(&format!("j{suffix}")).unwrap();
+            assert!(instruction.condition().is_some(), "`{instruction}` has no condition");
+            assert_eq!(instruction.condition(), jump.condition(), "`{instruction}` does not test the same condition as `{jump}`");
+        }
+    }
+
+    #[test]
+    fn match_signature_finds_operand_form() {
+        let eax = || Token::Register(RegisterName::Eax).into();
+        let mem = || Token::Memory(EffectiveAddress::default()).into();
+
+        assert_eq!(Instruction::Imul.match_signature(&[mem()]), Ok(0));
+        assert_eq!(Instruction::Imul.match_signature(&[eax(), Token::Numeric(3).into()]), Ok(1));
+        assert_eq!(Instruction::Imul.match_signature(&[eax(), mem(), Token::Numeric(3).into()]), Ok(2));
+        assert_eq!(Instruction::Push.match_signature(&[mem(), mem()]), Ok(0));
+
+        assert_eq!(Instruction::Mov.match_signature(&[Token::Numeric(5).into(), eax()]), Err("`mov` takes parameters of type <[Register | Memory], [Register | Memory | Identifier | Numeric]>".to_string()));
+        assert_eq!(Instruction::Mov.match_signature(&[mem(), mem()]), Err("`mov` cannot take two memory operands".to_string()));
+        assert_eq!(Instruction::Cdq.match_signature(&[eax()]), Err("`cdq` takes no parameters".to_string()));
+        assert_eq!(Instruction::Setz.match_signature(&[eax()]), Err("`setz` takes one parameter of type <[Register8 | Memory8]>".to_string()));
+    }
+
+    #[test]
+    fn every_instruction_describes_its_signatures() {
+        for instruction in Instruction::iter() {
+            assert!(!instruction.signatures().is_empty(), "`{instruction}` has no operand forms");
+        }
+
+        assert_eq!(Instruction::Cmp.describe_signatures(), "`cmp` takes parameters of type <[Register | Memory], [Register | Memory | Numeric]>");
+        assert_eq!(Instruction::Imul.describe_signatures(), "`imul` takes parameters of type <[Register | Memory]> or <Register, [Register | Memory | Numeric]> or <Register, [Register | Memory], Numeric>");
+        assert_eq!(Instruction::Jz.describe_signatures(), "`jz` takes one parameter of type <Identifier>");
+    }
 }
@@ -0,0 +1,200 @@
+use super::span::Span;
+
+/// The kinds of lexeme in a line of asmr code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LexemeKind {
+    Word,         // Identifiers, keywords, registers, numbers and positions, e.g. `mov`, `.loop:`, `0x1F` or `$$`
+    String,       // "Hello", 'Hello' or `Hello`, including the quotes, which may be unterminated
+    Comma,
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    ShiftLeft,    // <<
+    ShiftRight,   // >>
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Unknown,      // Any other character, e.g. `#`
+}
+
+/// A piece of source text along with its kind and position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lexeme<'a> {
+    pub kind: LexemeKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+/// Splits a line of asmr code into lexemes, skipping whitespace and stopping at a comment.
+///
+/// # Examples
+///
+/// ```
+/// # use assembl_really::parser::lexer::{lex, LexemeKind};
+/// let lexemes = lex("mov eax, [ebx + 4] ; load");
+/// let kinds: Vec<_> = lexemes.iter().map(|l| l.kind).collect();
+/// assert_eq!(kinds, [
+///     LexemeKind::Word, LexemeKind::Word, LexemeKind::Comma,
+///     LexemeKind::OpenBracket, LexemeKind::Word, LexemeKind::Plus, LexemeKind::Word, LexemeKind::CloseBracket,
+/// ]);
+/// ```
+pub fn lex(line: &str) -> Vec<Lexeme<'_>> {
+    let mut lexemes = Vec::new();
+    let mut chars = line.char_indices().enumerate().peekable();
+
+    while let Some((column, (start, c))) = chars.next() {
+        let kind = match c {
+            _ if c.is_whitespace() => continue,
+            ';' => break,
+            ',' => LexemeKind::Comma,
+            '[' => LexemeKind::OpenBracket,
+            ']' => LexemeKind::CloseBracket,
+            '+' => LexemeKind::Plus,
+            '-' => LexemeKind::Minus,
+            '*' => LexemeKind::Star,
+            '(' => LexemeKind::OpenParen,
+            ')' => LexemeKind::CloseParen,
+            '/' => LexemeKind::Slash,
+            '%' => LexemeKind::Percent,
+            '&' => LexemeKind::Ampersand,
+            '|' => LexemeKind::Pipe,
+            '^' => LexemeKind::Caret,
+            '~' => LexemeKind::Tilde,
+            '<' | '>' if chars.next_if(|(_, (_, next))| *next == c).is_some() => {
+                if c == '<' { LexemeKind::ShiftLeft } else { LexemeKind::ShiftRight }
+            },
+            '"' | '\'' | '`' => {
+                // Consume up to and including the closing quote, or to the end of the line,
+                // skipping escaped characters such as `\"`
+                while let Some((_, (_, next))) = chars.next() {
+                    match next {
+                        '\\' => { chars.next(); },
+                        _ if next == c => break,
+                        _ => {},
+                    }
+                }
+                LexemeKind::String
+            },
+            _ if is_word_char(c) => {
+                while chars.next_if(|(_, (_, c))| is_word_char(*c)).is_some() {}
+                LexemeKind::Word
+            },
+            _ => LexemeKind::Unknown,
+        };
+
+        let (column_end, end) = chars.peek().map(|(column, (end, _))| (*column, *end))
+            .unwrap_or_else(|| (line.chars().count(), line.len()));
+        lexemes.push(Lexeme { kind, text: &line[start..end], span: Span { start, end, column, column_end } });
+    }
+
+    lexemes
+}
+
+/// Checks whether `c` can be part of a word, see [LexemeKind::Word].
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '$'
+}
+
+/// Splits lexemes into the comma separated list they form, e.g. the operands of an instruction.
+/// An empty list has no items, but every comma separates two items, which may be empty.
+pub fn split_commas<'a, 'b>(lexemes: &'b [Lexeme<'a>]) -> Vec<&'b [Lexeme<'a>]> {
+    if lexemes.is_empty() {
+        return Vec::new();
+    }
+
+    lexemes.split(|l| l.kind == LexemeKind::Comma).collect()
+}
+
+/// Gets the source text and span covered by `lexemes`, which must be non-empty.
+pub fn text_of<'a>(line: &'a str, lexemes: &[Lexeme]) -> (&'a str, Span) {
+    let span = lexemes.first().unwrap().span.to(lexemes.last().unwrap().span);
+    (&line[span.start..span.end], span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(lexemes: &[Lexeme<'a>]) -> Vec<&'a str> {
+        lexemes.iter().map(|l| l.text).collect()
+    }
+
+    #[test]
+    fn lex_splits_words_and_punctuation() {
+        let l1 = lex("  mov eax,[ebx*4-8]");
+        let l2 = lex("call asmr::io::print");
+        let l3 = lex(".loop:");
+
+        assert_eq!(texts(&l1), ["mov", "eax", ",", "[", "ebx", "*", "4", "-", "8", "]"]);
+        assert_eq!(l1[0].span, Span { start: 2, end: 5, column: 2, column_end: 5 });
+        assert_eq!(l1[9].span, Span { start: 18, end: 19, column: 18, column_end: 19 });
+        assert_eq!(texts(&l2), ["call", "asmr::io::print"]);
+        assert_eq!(texts(&l3), [".loop:"]);
+    }
+
+    #[test]
+    fn lex_keeps_strings_whole() {
+        let l1 = lex("msg db \"Hello, World; bye\", 10 ; comment");
+        let l2 = lex("db \"unterminated, string");
+        let l3 = lex("db \"é\", x");
+
+        assert_eq!(texts(&l1), ["msg", "db", "\"Hello, World; bye\"", ",", "10"]);
+        assert_eq!(l1[2].kind, LexemeKind::String);
+        assert_eq!(texts(&l2), ["db", "\"unterminated, string"]);
+        assert_eq!(l3[3].span, Span { start: 9, end: 10, column: 8, column_end: 9 });
+    }
+
+    #[test]
+    fn lex_keeps_escaped_quotes_in_strings() {
+        let l1 = lex("mov al, ';' ; comment");
+        let l2 = lex("push '\\'', 'A");
+        let l3 = lex("db \"say \\\"hi\\\"\", `it's`");
+
+        assert_eq!(texts(&l1), ["mov", "al", ",", "';'"]);
+        assert_eq!(l1[3].kind, LexemeKind::String);
+        assert_eq!(texts(&l2), ["push", "'\\''", ",", "'A"]);
+        assert_eq!(texts(&l3), ["db", "\"say \\\"hi\\\"\"", ",", "`it's`"]);
+    }
+
+    #[test]
+    fn lex_stops_at_comments() {
+        assert!(lex("; a comment").is_empty());
+        assert!(lex("   ").is_empty());
+        assert_eq!(texts(&lex("ret;done")), ["ret"]);
+    }
+
+    #[test]
+    fn lex_splits_operators() {
+        let l1 = lex("len equ ($-msg)/2 << 1|~x>>3");
+        let kinds: Vec<_> = lex("%&^<< >>").iter().map(|l| l.kind).collect();
+
+        assert_eq!(texts(&l1), ["len", "equ", "(", "$", "-", "msg", ")", "/", "2", "<<", "1", "|", "~", "x", ">>", "3"]);
+        assert_eq!(kinds, [LexemeKind::Percent, LexemeKind::Ampersand, LexemeKind::Caret, LexemeKind::ShiftLeft, LexemeKind::ShiftRight]);
+    }
+
+    #[test]
+    fn lex_marks_unknown_characters() {
+        let l1 = lex("call #2 < 3");
+
+        assert_eq!(texts(&l1), ["call", "#", "2", "<", "3"]);
+        assert_eq!(l1[1].kind, LexemeKind::Unknown);
+        assert_eq!(l1[3].kind, LexemeKind::Unknown);
+    }
+
+    #[test]
+    fn split_commas_separates_items() {
+        let l1 = lex("eax, \"a, b\", 5");
+        let l2 = lex("eax,");
+
+        assert_eq!(split_commas(&l1).iter().map(|item| texts(item)).collect::<Vec<_>>(), [vec!["eax"], vec!["\"a, b\""], vec!["5"]]);
+        assert_eq!(split_commas(&l2).len(), 2);
+        assert!(split_commas(&[]).is_empty());
+    }
+}
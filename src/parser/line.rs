@@ -1,21 +1,46 @@
-use super::{Instruction, Token};
+use strum::{EnumString, Display};
+
+use super::{Instruction, Prefix, Token, expression::Expression, span::{Span, Spanned}};
 
 #[derive(Debug, PartialEq)]
 pub enum Line {
     Instruction {
-        instruction: Instruction,
-        params: Vec<Token>,
+        prefix: Option<Prefix>,
+        instruction: Spanned<Instruction>,
+        params: Vec<Spanned<Token>>,
     },
-    Label(String),
+    Label(Spanned<String>),
     Variable {
-        identifier: String,
+        identifier: Spanned<String>,
         mem_type: MemType,
-        params: Vec<Token>,
+        params: Vec<Spanned<Token>>,
+    },
+    /// Defines a constant, such as `LEN equ 16` or `msg_len equ $ - msg`.
+    /// Its value is substituted at parse time when it is known, otherwise it is evaluated once the program is loaded.
+    Equ {
+        identifier: Spanned<String>,
+        value: Spanned<Expression>,
+    },
+    /// Starts a procedure, which can be called like a label.
+    /// Its arguments and locals are resolved to `[ebp + x]` and `[ebp - x]` within its body.
+    Proc {
+        name: Spanned<String>,
+        args: Vec<Spanned<String>>,
+        locals: Vec<Spanned<String>>,
     },
+    /// Declares local variables of the enclosing procedure.
+    Local(Vec<Spanned<String>>),
+    /// Ends a procedure, spanning the `endp` keyword. Like MASM, it does not return, the body must `ret` itself.
+    Endp(Span),
     Blank,
+    /// A line that failed to parse, spanning the whole line, in place of the line in a recovering parse.
+    Invalid(Span),
 }
 
+/// The kinds of data declaration, e.g. `msg db "Hello"`.
 #[derive(Debug, PartialEq)]
+#[derive(EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
 pub enum MemType {
-    Db, Resb
+    Db, Dd, Resb
 }
@@ -0,0 +1,28 @@
+use strum::{EnumString, EnumIter, Display};
+
+use super::Instruction;
+
+/// Prefixes that repeat a string instruction, e.g. `rep movsb`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(EnumString, EnumIter, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Prefix {
+    Rep,   // Repeat ecx times
+    Repe,  // Repeat ecx times while equal
+    Repne, // Repeat ecx times while not equal
+}
+
+impl Prefix {
+    /// Checks whether the prefix can be applied to `instruction`.
+    /// `rep` applies to the string instructions that move data,
+    /// `repe` and `repne` apply to the string instructions that compare data.
+    pub fn applies_to(&self, instruction: &Instruction) -> bool {
+        match self {
+            Prefix::Rep => matches!(instruction,
+                Instruction::Movsb | Instruction::Movsd |
+                Instruction::Stosb | Instruction::Stosd |
+                Instruction::Lodsb | Instruction::Lodsd),
+            Prefix::Repe | Prefix::Repne => matches!(instruction, Instruction::Cmpsb | Instruction::Scasb),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::core::{register::RegisterName, memory::DataSize};
+
+use super::{span::Spanned, token::Token};
+
+/// The kinds of operand an instruction can take, e.g. `Register` for `eax` or `Numeric` for `5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(strum::Display)]
+pub enum Operand {
+    Register,                               // Any register
+    Register32,                             // eax, esp, ...
+    #[strum(serialize = "Register16/32")]
+    WideRegister,                           // ax, eax, ...
+    Register8,                              // al, ah, ...
+    #[strum(serialize = "cl")]
+    Cl,                                     // Shift counts
+    Memory,                                 // [ebx + 4], ...
+    Memory8,                                // [ebx] or byte [ebx]
+    Identifier,
+    Numeric,
+}
+
+impl Operand {
+    /// Checks whether `token` is an operand of this kind.
+    pub fn accepts(&self, token: &Token) -> bool {
+        match (self, token) {
+            (Operand::Register, Token::Register(_)) => true,
+            (Operand::Register32, Token::Register(r)) => r.size() == DataSize::Dword,
+            (Operand::WideRegister, Token::Register(r)) => r.size() != DataSize::Byte,
+            (Operand::Register8, Token::Register(r)) => r.size() == DataSize::Byte,
+            (Operand::Cl, Token::Register(r)) => *r == RegisterName::Cl,
+            (Operand::Memory, Token::Memory(_)) => true,
+            (Operand::Memory8, Token::Memory(ea)) => matches!(ea.size, None | Some(DataSize::Byte)),
+            (Operand::Identifier, Token::Identifier(_)) => true,
+            (Operand::Numeric, Token::Numeric(_) | Token::Expression(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// An operand form accepted by an instruction.
+/// Each operand position lists the kinds of operand allowed in that position.
+#[derive(Debug, PartialEq)]
+pub enum Signature {
+    Fixed(&'static [&'static [Operand]]), // e.g. `mov eax, 5`
+    Variadic(&'static [Operand]),         // One or more operands, e.g. `push eax, ebx`
+}
+
+impl Signature {
+    /// Checks whether `params` match the operand form.
+    pub fn accepts(&self, params: &[Spanned<Token>]) -> bool {
+        match self {
+            Signature::Fixed(operands) => operands.len() == params.len()
+                && operands.iter().zip(params).all(|(kinds, param)| kinds.iter().any(|k| k.accepts(&param.node))),
+            Signature::Variadic(kinds) => !params.is_empty()
+                && params.iter().all(|param| kinds.iter().any(|k| k.accepts(&param.node))),
+        }
+    }
+}
+
+/// Formats the operand form in the style of `<[Register | Memory], Numeric>`.
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let kinds = |kinds: &[Operand]| match kinds {
+            [kind] => kind.to_string(),
+            _ => format!("[{}]", kinds.iter().map(Operand::to_string).collect::<Vec<_>>().join(" | ")),
+        };
+
+        match self {
+            Signature::Fixed(operands) => write!(f, "<{}>", operands.iter().map(|o| kinds(o)).collect::<Vec<_>>().join(", ")),
+            Signature::Variadic(operand) => write!(f, "<...{}>", kinds(operand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::token::EffectiveAddress;
+
+    #[test]
+    fn signature_accepts_matching_operands() {
+        use Operand::*;
+        let fixed = Signature::Fixed(&[&[Register, Memory], &[Numeric, Cl]]);
+        let variadic = Signature::Variadic(&[Register32, Identifier]);
+
+        assert!(fixed.accepts(&[Token::Register(RegisterName::Al).into(), Token::Register(RegisterName::Cl).into()]));
+        assert!(fixed.accepts(&[Token::Memory(EffectiveAddress::default()).into(), Token::Numeric(3).into()]));
+        assert!(!fixed.accepts(&[Token::Numeric(3).into(), Token::Numeric(3).into()]));
+        assert!(!fixed.accepts(&[Token::Register(RegisterName::Eax).into(), Token::Register(RegisterName::Ebx).into()]));
+        assert!(!fixed.accepts(&[Token::Register(RegisterName::Eax).into()]));
+
+        assert!(variadic.accepts(&[Token::Register(RegisterName::Eax).into(), Token::Identifier("msg".to_string()).into()]));
+        assert!(!variadic.accepts(&[Token::Register(RegisterName::Al).into()]));
+        assert!(!variadic.accepts(&[]));
+    }
+
+    #[test]
+    fn signature_displays_operand_kinds() {
+        use Operand::*;
+
+        assert_eq!(Signature::Fixed(&[]).to_string(), "<>");
+        assert_eq!(Signature::Fixed(&[&[Register, Memory], &[Numeric, Cl]]).to_string(), "<[Register | Memory], [Numeric | cl]>");
+        assert_eq!(Signature::Fixed(&[&[WideRegister], &[Memory]]).to_string(), "<Register16/32, Memory>");
+        assert_eq!(Signature::Variadic(&[Register32, Memory]).to_string(), "<...[Register32 | Memory]>");
+    }
+}
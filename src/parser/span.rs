@@ -0,0 +1,101 @@
+use std::{fmt::{self, Display, Formatter}, ops::Deref};
+
+/// A range of source text on a single line.
+/// `start` and `end` are byte offsets into the line, `column` and `column_end` are the matching character columns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub column: usize,
+    pub column_end: usize,
+}
+
+impl Span {
+    /// Creates the span of the bytes `start..end` of `line`.
+    ///
+    /// # Panics
+    /// This will panic if `start..end` is not a range of `line` on character boundaries.
+    pub fn new(line: &str, start: usize, end: usize) -> Self {
+        let column = line[..start].chars().count();
+        let column_end = column + line[start..end].chars().count();
+        Span { start, end, column, column_end }
+    }
+
+    /// Creates a span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Self {
+        Span { start: self.start, end: other.end, column: self.column, column_end: other.column_end }
+    }
+}
+
+/// A node of the parsed source, such as a [Token](super::token::Token), along with the span it was parsed from.
+///
+/// Spans are not compared, so two nodes parsed from different places are equal if the nodes are equal.
+#[derive(Clone, Debug, Default)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+/// Wraps a node that was not parsed from source, such as one built by the interpreter.
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned { node, span: Span::default() }
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for Spanned<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.node == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_counts_columns_in_characters() {
+        let line = "db \"héllo\", x";
+        let x = line.rfind('x').unwrap();
+
+        assert_eq!(Span::new(line, 3, 11), Span { start: 3, end: 11, column: 3, column_end: 10 });
+        assert_eq!(Span::new(line, x, x + 1), Span { start: 13, end: 14, column: 12, column_end: 13 });
+        assert_eq!(Span::new(line, 0, 2).to(Span::new(line, x, x + 1)), Span { start: 0, end: 14, column: 0, column_end: 13 });
+    }
+
+    #[test]
+    fn spanned_nodes_compare_without_spans() {
+        let a = Spanned::new("eax", Span::new("mov eax", 4, 7));
+        let b = Spanned::from("eax");
+
+        assert_eq!(a, b);
+        assert_eq!(a, "eax");
+        assert_ne!(a.span, b.span);
+    }
+}
@@ -1,59 +1,86 @@
 use std::str::FromStr;
 
-use crate::core::register::RegisterName;
+use crate::core::{register::RegisterName, memory::DataSize};
 
-use super::is_valid_identifier;
+use super::{expression::{BinaryOperator, Expression, UnaryOperator, MAX_NESTING}, is_valid_identifier, lexer::{lex, text_of, Lexeme, LexemeKind}, span::Spanned};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Identifier(String),
     Numeric(i32),
     Register(RegisterName),
-    String(String),
+    String(Vec<u8>),  // UTF-8 text, except where escapes such as `\xFF` add other bytes
+    Memory(EffectiveAddress),
+    Expression(Expression),  // Evaluated once the program is loaded, as it uses `$`, `$$` or an address
 }
 
-impl FromStr for Token {
-    type Err = String;
+/// A memory operand of the form `size [base + index*scale + symbol + displacement]`.
+/// Every component is optional, `size` defaults to the size of the other operand.
+/// Other terms, such as `LEN * 4`, are summed into `offset`, which is folded into `displacement` when it is constant.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct EffectiveAddress {
+    pub size: Option<DataSize>,
+    pub base: Option<RegisterName>,
+    pub index: Option<(RegisterName, u8)>,
+    pub symbol: Option<Spanned<String>>,
+    pub displacement: i32,
+    pub offset: Option<Spanned<Expression>>,
+}
 
-    fn from_str(s: &str) -> Result<Token, Self::Err> {
-        // Parse registers
-        if let Ok(register) = RegisterName::from_str(s) {
-            return Ok(Token::Register(register));
+impl Token {
+    /// Parses the lexemes of a single operand, such as `byte [msg + ecx]`, that were lexed from `line`.
+    /// 
+    /// # Panics
+    /// This will panic if `lexemes` is empty.
+    pub fn parse(line: &str, lexemes: &[Lexeme]) -> Result<Token, String> {
+        let (s, _) = text_of(line, lexemes);
+
+        // Parse memory operands
+        if lexemes.iter().any(|l| matches!(l.kind, LexemeKind::OpenBracket | LexemeKind::CloseBracket)) {
+            return Ok(Token::Memory(EffectiveAddress::parse(line, lexemes)?));
         }
 
-        // Parse string values
-        else if s.contains('"') {
-            if s.matches('"').count() == 2 && s.chars().next() == Some('"') && s.chars().last() == Some('"') {
-                return Ok(Token::String(s[1..s.len() - 1].to_string()));
+        // Parse string and character values
+        else if lexemes.iter().any(|l| l.kind == LexemeKind::String) {
+            if let [l] = lexemes {
+                if let Some(contents) = quoted_contents(l.text) {
+                    let bytes = unescape(contents)?;
+
+                    // A single character in single quotes is a character value, such as `'A'`
+                    if l.text.starts_with('\'') {
+                        if let Some(c) = char_value(&bytes) {
+                            return Ok(Token::Numeric(c));
+                        }
+                    }
+                    return Ok(Token::String(bytes));
+                }
             }
             return Err(format!("Could not parse `{s}` as a string value."));
         }
 
-        // Parse hexadecimal values
-        else if s.starts_with("0x") {
-            let int = i32::from_str_radix(s.trim_start_matches("0x"), 16);
-            match int {
-                Ok(i) => return Ok(Token::Numeric(i)),
-                Err(_) => return Err(format!("Could not parse `{s}` as a hexadecimal value.")),
-            }
+        // Parse constant expressions, such as `LEN * 2 + 1` or `$ - msg`, except negative literals, whose range is checked
+        else if !matches!(lexemes, [sign, number] if sign.kind == LexemeKind::Minus && number.text.starts_with(|c: char| c.is_ascii_digit()))
+            && lexemes.iter().any(|l| is_operator(l.kind) || l.text.starts_with('$')) {
+            let expression = Expression::parse(line, lexemes)?;
+            return Ok(match expression.constant(&Default::default())? {
+                Some(i) => Token::Numeric(i),
+                None => Token::Expression(expression),
+            });
         }
 
-        // Parse binary values
-        else if s.starts_with("0b") {
-            let int = i32::from_str_radix(s.trim_start_matches("0b"), 2);
-            match int {
-                Ok(i) => return Ok(Token::Numeric(i)),
-                Err(_) => return Err(format!("Could not parse `{s}` as a binary value.")),
-            }
+        Token::parse_scalar(s)
+    }
+
+    /// Parses registers, numeric values and identifiers.
+    pub(super) fn parse_scalar(s: &str) -> Result<Token, String> {
+        // Parse registers
+        if let Ok(register) = RegisterName::from_str(s) {
+            return Ok(Token::Register(register));
         }
 
-        // Parse decimal values
-        else if s.starts_with(&['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']) {
-            let int = i32::from_str(s);
-            match int {
-                Ok(i) => return Ok(Token::Numeric(i)),
-                Err(_) => return Err(format!("Could not parse `{s}` as a decimal value.")),
-            }
+        // Parse numeric values, which start with a digit or a minus sign
+        else if s.trim_start_matches('-').trim_start().starts_with(|c: char| c.is_ascii_digit()) {
+            return parse_integer(s).map(Token::Numeric);
         }
 
         // Parse identifiers
@@ -65,6 +92,262 @@ impl FromStr for Token {
     }
 }
 
+/// Checks whether a lexeme can only be part of an expression, such as `*` or `(`.
+fn is_operator(kind: LexemeKind) -> bool {
+    use LexemeKind::*;
+    matches!(kind, Plus | Minus | Star | Slash | Percent | ShiftLeft | ShiftRight | Ampersand | Pipe | Caret | Tilde | OpenParen | CloseParen)
+}
+
+/// Parses an integer literal such as `-5`, `0xFFFFFFFF`, `0o17`, `17q`, `0Ah` or `1010_0101b`.
+/// Any value from `i32::MIN` to `u32::MAX` is accepted, and unsigned values above `i32::MAX` keep their bit pattern.
+fn parse_integer(s: &str) -> Result<i32, String> {
+    let (negative, literal) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, s),
+    };
+
+    // Prefixes are checked first, so `0b1` is binary while `0b1h` is hexadecimal
+    let strip_prefix = |prefix: &str| literal.strip_prefix(prefix).or_else(|| literal.strip_prefix(&prefix.to_uppercase()));
+    let (radix, name, digits) = if let Some(digits) = strip_prefix("0x") { (16, "a hexadecimal", digits) }
+        else if let Some(digits) = literal.strip_suffix(['h', 'H']) { (16, "a hexadecimal", digits) }
+        else if let Some(digits) = strip_prefix("0o") { (8, "an octal", digits) }
+        else if let Some(digits) = literal.strip_suffix(['q', 'Q', 'o', 'O']) { (8, "an octal", digits) }
+        else if let Some(digits) = strip_prefix("0b") { (2, "a binary", digits) }
+        else if let Some(digits) = literal.strip_suffix(['b', 'B', 'y', 'Y']) { (2, "a binary", digits) }
+        else { (10, "a decimal", literal) };
+
+    // Digits can be separated by underscores, such as `1_000_000`
+    let digits = digits.replace('_', "");
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(format!("Could not parse `{s}` as {name} value."));
+    }
+
+    let out_of_range = || format!("`{s}` is out of range. Numeric values must be from -2147483648 to 4294967295.");
+    let magnitude = u32::from_str_radix(&digits, radix).map_err(|_| out_of_range())?;
+    if negative && magnitude > i32::MIN.unsigned_abs() {
+        return Err(out_of_range());
+    }
+
+    Ok(if negative { (magnitude as i32).wrapping_neg() } else { magnitude as i32 })
+}
+
+/// Gets the text between the quotes of a string lexeme, or None if it is unterminated.
+fn quoted_contents(s: &str) -> Option<&str> {
+    let quote = s.chars().next()?;
+    let contents = s.strip_prefix(quote)?.strip_suffix(quote)?;
+
+    // The closing quote must not be escaped, as in `"C:\"`
+    let backslashes = contents.chars().rev().take_while(|c| *c == '\\').count();
+    (backslashes % 2 == 0).then_some(contents)
+}
+
+/// Gets the value of a character literal, which is its code point, or its byte when escaped such as `'\xFF'`.
+fn char_value(bytes: &[u8]) -> Option<i32> {
+    if let [b] = bytes {
+        return Some(*b as i32);
+    }
+
+    let mut chars = std::str::from_utf8(bytes).ok()?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c as i32),
+        _ => None,
+    }
+}
+
+/// Replaces the escape sequences in quoted text, such as `\n`, `\x1B` or `\u{e9}`, with the bytes they stand for.
+/// Unicode characters, whether escaped or not, are encoded as UTF-8.
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut chars = s.chars();
+    let mut bytes = Vec::new();
+    let push = |c: char, bytes: &mut Vec<u8>| bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push(c, &mut bytes);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('0') => bytes.push(0),
+            Some(c @ ('\\' | '\'' | '"' | '`')) => push(c, &mut bytes),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(b) if digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit()) => bytes.push(b),
+                    _ => return Err(format!("Invalid escape sequence `\\x{digits}`. `\\x` must be followed by two hexadecimal digits, such as `\\x1B`.")),
+                }
+            },
+            Some('u') => {
+                let rest = chars.as_str();
+                let code_point = rest.strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .filter(|(digits, _)| (1..=6).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_hexdigit()));
+
+                match code_point.and_then(|(digits, after)| Some((char::from_u32(u32::from_str_radix(digits, 16).ok()?)?, after))) {
+                    Some((c, after)) => {
+                        push(c, &mut bytes);
+                        chars = after.chars();
+                    },
+                    None => return Err(format!("Invalid escape sequence in `{s}`. `\\u` must be followed by a Unicode code point in braces, such as `\\u{{e9}}`.")),
+                }
+            },
+            Some(c) => return Err(format!("Unknown escape sequence `\\{c}`. Expected one of \\n, \\t, \\r, \\0, \\\\, \\', \\\", \\`, \\xNN or \\u{{NNNN}}.")),
+            None => return Err(format!("Incomplete escape sequence at the end of `{s}`.")),
+        }
+    }
+
+    Ok(bytes)
+}
+
+impl FromStr for Token {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Token, Self::Err> {
+        let lexemes = lex(s);
+        if lexemes.is_empty() {
+            return Token::parse_scalar(s);
+        }
+
+        Token::parse(s, &lexemes)
+    }
+}
+
+impl EffectiveAddress {
+    /// Parses the lexemes of a memory operand, such as `byte [msg + ecx]`, that were lexed from `line`.
+    /// 
+    /// # Panics
+    /// This will panic if `lexemes` is empty.
+    pub fn parse(line: &str, lexemes: &[Lexeme]) -> Result<EffectiveAddress, String> {
+        let (s, _) = text_of(line, lexemes);
+        let invalid = || format!("Could not parse `{s}` as a memory operand. Memory operands must be of the form `[base + index*scale + displacement]`.");
+
+        let open = lexemes.iter().position(|l| l.kind == LexemeKind::OpenBracket).ok_or_else(invalid)?;
+        let (size, expr) = lexemes.split_at(open);
+        let [_, expr @ .., close] = expr else { return Err(invalid()) };
+        if close.kind != LexemeKind::CloseBracket { return Err(invalid()) }
+
+        let mut ea = EffectiveAddress::default();
+
+        // Parse the optional size specifier
+        if !size.is_empty() {
+            let (size, _) = text_of(line, size);
+            ea.size = Some(DataSize::from_str(size).map_err(|_| format!("Unknown operand size `{size}`. Expected one of `byte`, `word` or `dword`."))?);
+        }
+
+        // Split the expression into signed terms, outside of parentheses
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut begin = 0;
+        let mut depth = 0;
+        for i in 0..=expr.len() {
+            let sign = expr.get(i).map(|l| l.kind);
+            match sign {
+                Some(LexemeKind::OpenParen) => depth += 1,
+                Some(LexemeKind::CloseParen) => depth -= 1,
+                _ => {},
+            }
+            if depth != 0 || !matches!(sign, None | Some(LexemeKind::Plus | LexemeKind::Minus)) { continue }
+
+            let term = &expr[begin..i];
+            if term.is_empty() {
+                // Allow a leading sign, reject empty terms such as `[eax+]`
+                if !terms.is_empty() || negative || sign != Some(LexemeKind::Minus) { return Err(invalid()) }
+            }
+            else {
+                terms.push((negative, term));
+            }
+            negative = sign == Some(LexemeKind::Minus);
+            begin = i + 1;
+        }
+
+        // Offsets are nested into a single expression, so they are limited like the nesting of expressions
+        if terms.len() > MAX_NESTING {
+            return Err(format!("This memory operand has too many terms. Memory operands can add at most {MAX_NESTING} terms."));
+        }
+
+        for (negative, term) in terms {
+            match term {
+                // Scaled index register
+                [lhs, star, rhs] if star.kind == LexemeKind::Star && [lhs, rhs].iter().any(|l| RegisterName::from_str(l.text).is_ok()) => {
+                    let (reg, scale) = if RegisterName::from_str(lhs.text).is_ok() { (lhs.text, rhs.text) } else { (rhs.text, lhs.text) };
+
+                    let reg = RegisterName::from_str(reg).map_err(|_| invalid())?;
+                    let scale = match u8::from_str(scale) {
+                        Ok(scale @ (1 | 2 | 4 | 8)) => scale,
+                        _ => return Err(format!("Invalid scale `{scale}` in `{s}`. The scale of an index register must be 1, 2, 4 or 8.")),
+                    };
+                    if negative || ea.index.is_some() { return Err(invalid()) }
+                    ea.index = Some((reg, scale));
+                },
+
+                // Base or index register
+                [word] if RegisterName::from_str(word.text).is_ok() => {
+                    let reg = RegisterName::from_str(word.text).map_err(|_| invalid())?;
+                    if negative { return Err(invalid()) }
+                    if ea.base.is_none() {
+                        ea.base = Some(reg);
+                    }
+                    else if ea.index.is_none() {
+                        ea.index = Some((reg, 1));
+                    }
+                    else {
+                        return Err(format!("Too many registers in `{s}`. A memory operand can use at most a base and an index register."));
+                    }
+                },
+
+                // Constant displacement or symbol address
+                [word] if word.kind == LexemeKind::Word => {
+                    match Token::parse_scalar(word.text)? {
+                        Token::Numeric(i) => {
+                            let i = if negative { i.wrapping_neg() } else { i };
+                            ea.displacement = ea.displacement.wrapping_add(i);
+                        },
+                        Token::Identifier(id) if !negative && ea.symbol.is_none() => ea.symbol = Some(Spanned::new(id, word.span)),
+                        Token::Identifier(_) => ea.add_offset(line, negative, term)?,
+                        _ => return Err(invalid()),
+                    }
+                },
+
+                // Constant expression, such as `LEN * 4`
+                _ => ea.add_offset(line, negative, term)?,
+            }
+        }
+
+        Ok(ea)
+    }
+}
+
+impl EffectiveAddress {
+    /// Adds a term that is not a register or literal to the offset.
+    fn add_offset(&mut self, line: &str, negative: bool, term: &[Lexeme]) -> Result<(), String> {
+        let (_, span) = text_of(line, term);
+        let term = Expression::parse(line, term)?;
+        let term = if negative { Expression::Unary(UnaryOperator::Negate, Box::new(term)) } else { term };
+
+        self.offset = Some(match self.offset.take() {
+            Some(offset) => Spanned::new(Expression::Binary(BinaryOperator::Add, Box::new(offset.node), Box::new(term)), offset.span.to(span)),
+            None => Spanned::new(term, span),
+        });
+        Ok(())
+    }
+}
+
+impl FromStr for EffectiveAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EffectiveAddress, Self::Err> {
+        let lexemes = lex(s);
+        if lexemes.is_empty() {
+            return Err(format!("Could not parse `{s}` as a memory operand. Memory operands must be of the form `[base + index*scale + displacement]`."));
+        }
+
+        EffectiveAddress::parse(s, &lexemes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,9 +356,13 @@ mod tests {
     fn token_from_str_parses_register_name() -> Result<(), String> {
         let t1 = Token::from_str("eax")?;
         let t2 = Token::from_str("ebp")?;
+        let t3 = Token::from_str("esi")?;
+        let t4 = Token::from_str("ah")?;
         
         assert_eq!(t1, Token::Register(RegisterName::Eax));
         assert_eq!(t2, Token::Register(RegisterName::Ebp));
+        assert_eq!(t3, Token::Register(RegisterName::Esi));
+        assert_eq!(t4, Token::Register(RegisterName::Ah));
 
         Ok(())
     }
@@ -86,9 +373,39 @@ mod tests {
         let t2 = Token::from_str("\"Welcome\"")?;
         let t3 = Token::from_str("\"123\"")?;
 
-        assert_eq!(t1, Token::String("Hello World".to_string()));
-        assert_eq!(t2, Token::String("Welcome".to_string()));
-        assert_eq!(t3, Token::String("123".to_string()));
+        assert_eq!(t1, Token::String(b"Hello World".to_vec()));
+        assert_eq!(t2, Token::String(b"Welcome".to_vec()));
+        assert_eq!(t3, Token::String(b"123".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_parses_quoted_string_value() -> Result<(), String> {
+        let t1 = Token::from_str("'Hello World'")?;
+        let t2 = Token::from_str("`it's`")?;
+        let t3 = Token::from_str("''")?;
+        let t4 = Token::from_str("\"héllo\"")?;
+
+        assert_eq!(t1, Token::String(b"Hello World".to_vec()));
+        assert_eq!(t2, Token::String(b"it's".to_vec()));
+        assert_eq!(t3, Token::String(b"".to_vec()));
+        assert_eq!(t4, Token::String("héllo".as_bytes().to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_parses_string_escapes() -> Result<(), String> {
+        let t1 = Token::from_str(r#""Hello\n\tWorld\0""#)?;
+        let t2 = Token::from_str(r#""say \"hi\" \\ bye""#)?;
+        let t3 = Token::from_str(r#"`\x1B[0m\xff`"#)?;
+        let t4 = Token::from_str(r#"'caf\u{e9} \u{1F600}'"#)?;
+
+        assert_eq!(t1, Token::String(b"Hello\n\tWorld\0".to_vec()));
+        assert_eq!(t2, Token::String(br#"say "hi" \ bye"#.to_vec()));
+        assert_eq!(t3, Token::String(b"\x1b[0m\xff".to_vec()));
+        assert_eq!(t4, Token::String("café 😀".as_bytes().to_vec()));
 
         Ok(())
     }
@@ -97,9 +414,93 @@ mod tests {
     fn token_from_str_err_on_invalid_string_value() {
         let t1 = Token::from_str("D\"og");
         let t2 = Token::from_str("\"Do\"g");
+        let t3 = Token::from_str(r#""C:\""#);
+        let t4 = Token::from_str(r#""\x4""#);
+        let t5 = Token::from_str(r#""\u{110000}""#);
+        let t6 = Token::from_str(r#""\q""#);
 
         assert!(t1.err() == Some("Could not parse `D\"og` as a string value.".to_string()));
         assert!(t2.err() == Some("Could not parse `\"Do\"g` as a string value.".to_string()));
+        assert!(t3.err() == Some(r#"Could not parse `"C:\"` as a string value."#.to_string()));
+        assert!(t4.err() == Some(r#"Invalid escape sequence `\x4`. `\x` must be followed by two hexadecimal digits, such as `\x1B`."#.to_string()));
+        assert!(t5.err() == Some(r#"Invalid escape sequence in `\u{110000}`. `\u` must be followed by a Unicode code point in braces, such as `\u{e9}`."#.to_string()));
+        assert!(t6.err() == Some(r#"Unknown escape sequence `\q`. Expected one of \n, \t, \r, \0, \\, \', \", \`, \xNN or \u{NNNN}."#.to_string()));
+    }
+
+    #[test]
+    fn token_from_str_parses_memory_operand() -> Result<(), String> {
+        let t1 = Token::from_str("[ebx]")?;
+        let t2 = Token::from_str("[ebp - 8]")?;
+        let t3 = Token::from_str("byte [msg+ecx]")?;
+        let t4 = Token::from_str("dword [eax + ecx*4 + 0x10]")?;
+        let t5 = Token::from_str("[4*edx+buffer-1]")?;
+
+        assert_eq!(t1, Token::Memory(EffectiveAddress { base: Some(RegisterName::Ebx), ..Default::default() }));
+        assert_eq!(t2, Token::Memory(EffectiveAddress { base: Some(RegisterName::Ebp), displacement: -8, ..Default::default() }));
+        assert_eq!(t3, Token::Memory(EffectiveAddress {
+            size: Some(DataSize::Byte),
+            base: Some(RegisterName::Ecx),
+            symbol: Some("msg".to_string().into()),
+            ..Default::default()
+        }));
+        assert_eq!(t4, Token::Memory(EffectiveAddress {
+            size: Some(DataSize::Dword),
+            base: Some(RegisterName::Eax),
+            index: Some((RegisterName::Ecx, 4)),
+            displacement: 0x10,
+            ..Default::default()
+        }));
+        assert_eq!(t5, Token::Memory(EffectiveAddress {
+            index: Some((RegisterName::Edx, 4)),
+            symbol: Some("buffer".to_string().into()),
+            displacement: -1,
+            ..Default::default()
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_parses_memory_operand_with_expressions() -> Result<(), String> {
+        let Token::Memory(ea) = Token::from_str("[ebx + LEN*4 - (1 + 1)]")? else { panic!("expected a memory operand") };
+        let constants = [("LEN".to_string(), 2)].into_iter().collect();
+
+        assert_eq!(ea.base, Some(RegisterName::Ebx));
+        assert_eq!(ea.offset.map(|offset| offset.constant(&constants)), Some(Ok(Some(6))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_parses_expressions() -> Result<(), String> {
+        let t1 = Token::from_str("2 * (3 + 4)")?;
+        let t2 = Token::from_str("-1")?;
+        let t3 = Token::from_str("LEN + 1")?;
+        let t4 = Token::from_str("$")?;
+
+        assert_eq!(t1, Token::Numeric(14));
+        assert_eq!(t2, Token::Numeric(-1));
+        assert!(matches!(t3, Token::Expression(Expression::Binary(BinaryOperator::Add, ..))));
+        assert_eq!(t4, Token::Expression(Expression::Here));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_err_on_invalid_memory_operand() {
+        let t1 = Token::from_str("[]");
+        let t2 = Token::from_str("[eax+]");
+        let t3 = Token::from_str("[eax*3]");
+        let t4 = Token::from_str("qword [eax]");
+        let t5 = Token::from_str("[eax+ebx+ecx]");
+        let t6 = Token::from_str("[-eax]");
+
+        assert!(t1.err() == Some("Could not parse `[]` as a memory operand. Memory operands must be of the form `[base + index*scale + displacement]`.".to_string()));
+        assert!(t2.err() == Some("Could not parse `[eax+]` as a memory operand. Memory operands must be of the form `[base + index*scale + displacement]`.".to_string()));
+        assert!(t3.err() == Some("Invalid scale `3` in `[eax*3]`. The scale of an index register must be 1, 2, 4 or 8.".to_string()));
+        assert!(t4.err() == Some("Unknown operand size `qword`. Expected one of `byte`, `word` or `dword`.".to_string()));
+        assert!(t5.err() == Some("Too many registers in `[eax+ebx+ecx]`. A memory operand can use at most a base and an index register.".to_string()));
+        assert!(t6.err() == Some("Could not parse `[-eax]` as a memory operand. Memory operands must be of the form `[base + index*scale + displacement]`.".to_string()));
     }
 
     #[test]
@@ -107,10 +508,16 @@ mod tests {
         let t1 = Token::from_str("0x0")?;
         let t2 = Token::from_str("0xa")?;
         let t3 = Token::from_str("0x1a35e")?;
+        let t4 = Token::from_str("0xFFFFFFFF")?;
+        let t5 = Token::from_str("0Ah")?;
+        let t6 = Token::from_str("0DEAD_BEEFh")?;
         
         assert_eq!(t1, Token::Numeric(0x0));
         assert_eq!(t2, Token::Numeric(0xa));
         assert_eq!(t3, Token::Numeric(0x1a35e));
+        assert_eq!(t4, Token::Numeric(-1));
+        assert_eq!(t5, Token::Numeric(10));
+        assert_eq!(t6, Token::Numeric(0xDEADBEEF_u32 as i32));
 
         Ok(())
     }
@@ -119,9 +526,15 @@ mod tests {
     fn token_from_str_err_on_invalid_hexadecimal_value() {
         let t1 = Token::from_str("0x");
         let t2 = Token::from_str("0xq4");
+        let t3 = Token::from_str("0Gh");
+        let t4 = Token::from_str("0x100000000");
+        let t5 = Token::from_str("0x+5");
 
         assert!(t1.err() == Some("Could not parse `0x` as a hexadecimal value.".to_string()));
         assert!(t2.err() == Some("Could not parse `0xq4` as a hexadecimal value.".to_string()));
+        assert!(t3.err() == Some("Could not parse `0Gh` as a hexadecimal value.".to_string()));
+        assert!(t4.err() == Some("`0x100000000` is out of range. Numeric values must be from -2147483648 to 4294967295.".to_string()));
+        assert!(t5.err() == Some("Could not parse `0x` as a hexadecimal value.".to_string()));
     }
 
     #[test]
@@ -133,6 +546,9 @@ mod tests {
         assert_eq!(t1, Token::Numeric(0b0));
         assert_eq!(t2, Token::Numeric(0b11010110));
         assert_eq!(t3, Token::Numeric(0b1010111010001001));
+        assert_eq!(Token::from_str("1010b")?, Token::Numeric(0b1010));
+        assert_eq!(Token::from_str("0b1111_0000")?, Token::Numeric(0b1111_0000));
+        assert_eq!(Token::from_str("11y")?, Token::Numeric(0b11));
 
         Ok(())
     }
@@ -141,9 +557,37 @@ mod tests {
     fn token_from_str_err_on_invalid_binary_value() {
         let t1 = Token::from_str("0b");
         let t2 = Token::from_str("0b21");
+        let t3 = Token::from_str("102b");
+        let t4 = Token::from_str("0b1_0000_0000_0000_0000_0000_0000_0000_0000");
 
         assert!(t1.err() == Some("Could not parse `0b` as a binary value.".to_string()));
         assert!(t2.err() == Some("Could not parse `0b21` as a binary value.".to_string()));
+        assert!(t3.err() == Some("Could not parse `102b` as a binary value.".to_string()));
+        assert!(t4.err() == Some("`0b1_0000_0000_0000_0000_0000_0000_0000_0000` is out of range. Numeric values must be from -2147483648 to 4294967295.".to_string()));
+    }
+
+    #[test]
+    fn token_from_str_parses_octal_value() -> Result<(), String> {
+        let t1 = Token::from_str("0o17")?;
+        let t2 = Token::from_str("17q")?;
+        let t3 = Token::from_str("777o")?;
+        let t4 = Token::from_str("0O37777777777")?;
+
+        assert_eq!(t1, Token::Numeric(0o17));
+        assert_eq!(t2, Token::Numeric(0o17));
+        assert_eq!(t3, Token::Numeric(0o777));
+        assert_eq!(t4, Token::Numeric(-1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_err_on_invalid_octal_value() {
+        let t1 = Token::from_str("0o8");
+        let t2 = Token::from_str("19q");
+
+        assert!(t1.err() == Some("Could not parse `0o8` as an octal value.".to_string()));
+        assert!(t2.err() == Some("Could not parse `19q` as an octal value.".to_string()));
     }
 
     #[test]
@@ -151,23 +595,75 @@ mod tests {
         let t1 = Token::from_str("0")?;
         let t2 = Token::from_str("10")?;
         let t3 = Token::from_str("219384")?;
+        let t4 = Token::from_str("1_000_000")?;
+        let t5 = Token::from_str("4294967295")?;
 
         assert_eq!(t1, Token::Numeric(0));
         assert_eq!(t2, Token::Numeric(10));
         assert_eq!(t3, Token::Numeric(219_384));
+        assert_eq!(t4, Token::Numeric(1_000_000));
+        assert_eq!(t5, Token::Numeric(-1));
 
         Ok(())
     }
 
     #[test]
     fn token_from_str_err_on_invalid_decimal_value() {
-        let t1 = Token::from_str("1_000");
+        let t1 = Token::from_str("4294967296");
         let t2 = Token::from_str("11.4");
         let t3 = Token::from_str("6r");
+        let t4 = Token::from_str("-2147483649");
 
-        assert!(t1.err() == Some("Could not parse `1_000` as a decimal value.".to_string()));
+        assert!(t1.err() == Some("`4294967296` is out of range. Numeric values must be from -2147483648 to 4294967295.".to_string()));
         assert!(t2.err() == Some("Could not parse `11.4` as a decimal value.".to_string()));
         assert!(t3.err() == Some("Could not parse `6r` as a decimal value.".to_string()));
+        assert!(t4.err() == Some("`-2147483649` is out of range. Numeric values must be from -2147483648 to 4294967295.".to_string()));
+    }
+
+    #[test]
+    fn token_from_str_parses_negative_value() -> Result<(), String> {
+        let t1 = Token::from_str("-1")?;
+        let t2 = Token::from_str("- 0x10")?;
+        let t3 = Token::from_str("-2147483648")?;
+        let t4 = Token::from_str("-1010b")?;
+
+        assert_eq!(t1, Token::Numeric(-1));
+        assert_eq!(t2, Token::Numeric(-16));
+        assert_eq!(t3, Token::Numeric(i32::MIN));
+        assert_eq!(t4, Token::Numeric(-10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_parses_char_value() -> Result<(), String> {
+        let t1 = Token::from_str("'A'")?;
+        let t2 = Token::from_str("'\\n'")?;
+        let t3 = Token::from_str("'\\''")?;
+        let t4 = Token::from_str("';'")?;
+        let t5 = Token::from_str("'é'")?;
+
+        assert_eq!(t1, Token::Numeric(65));
+        assert_eq!(t2, Token::Numeric(10));
+        assert_eq!(t3, Token::Numeric(39));
+        assert_eq!(t4, Token::Numeric(59));
+        assert_eq!(t5, Token::Numeric(0xe9));
+        assert_eq!(Token::from_str("'\\xFF'")?, Token::Numeric(0xff));
+        assert_eq!(Token::from_str("'\\u{1F600}'")?, Token::Numeric(0x1f600));
+
+        // Longer single quoted values are strings
+        assert_eq!(Token::from_str("'AB'")?, Token::String(b"AB".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_from_str_err_on_invalid_char_value() {
+        let t1 = Token::from_str("'A");
+        let t2 = Token::from_str("'\\q'");
+
+        assert!(t1.err() == Some("Could not parse `'A` as a string value.".to_string()));
+        assert!(t2.err() == Some("Unknown escape sequence `\\q`. Expected one of \\n, \\t, \\r, \\0, \\\\, \\', \\\", \\`, \\xNN or \\u{NNNN}.".to_string()));
     }
 
     #[test]
@@ -0,0 +1,970 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}};
+
+use crate::{diagnostics::{Label, Note}, interpreter::error::AsmrRuntimeError, is_valid_identifier};
+use crate::parser::{error::ParserError, expression::Expression, lexer::{lex, split_commas, text_of, Lexeme, LexemeKind}, span::Span, token::Token};
+
+/// How deeply macros and `%rep` blocks can expand within each other, which stops runaway recursion.
+const MAX_DEPTH: usize = 64;
+
+/// How many lines the preprocessed code can have, which stops `%rep` and macros from exhausting memory.
+const MAX_EXPANDED_LINES: usize = 1_000_000;
+
+/// How many `%rep` iterations and macro expansions there can be in total, which stops ones that expand to no lines from running for too long.
+const MAX_REPETITIONS: usize = 1_000_000;
+
+/// How long a line can become once defines are substituted, which stops defines that refer to each other from exhausting memory.
+const MAX_LINE_LENGTH: usize = 65_536;
+
+/// Where a line of preprocessed code came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    /// Index into the files of the preprocessed code.
+    pub file: usize,
+    /// The 0-based line, which is the outermost macro invocation for lines expanded from macros.
+    pub line: usize,
+    /// The line of the macro body it was expanded from, if any.
+    pub expansion: Option<Expansion>,
+    /// Whether the text is unchanged, so that spans in it point into the source.
+    pub exact: bool,
+}
+
+/// A line of a macro body that was expanded into preprocessed code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    /// The name of the macro.
+    pub name: String,
+    /// Index into the files of the preprocessed code.
+    pub file: usize,
+    /// The 0-based line of the macro body.
+    pub line: usize,
+}
+
+/// A file read while preprocessing, which is the main file followed by every included file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceFile {
+    /// The path the file was read from, or `None` for code that was not read from a file.
+    pub path: Option<PathBuf>,
+    /// The lines of the file as read, before preprocessing.
+    pub lines: Vec<String>,
+}
+
+impl SourceFile {
+    /// Gets the name of the file as shown in errors.
+    pub fn name(&self) -> Option<String> {
+        self.path.as_ref().map(|path| path.display().to_string())
+    }
+}
+
+/// Preprocessed code, ready for [parse_lines](crate::parse_lines), along with where each line came from.
+#[derive(Debug, Default, PartialEq)]
+pub struct Preprocessed {
+    pub lines: Vec<String>,
+    pub origins: Vec<Origin>,
+    pub files: Vec<SourceFile>,
+}
+
+impl Preprocessed {
+    /// Points an error on a line of preprocessed code back at the file and line it came from.
+    /// Errors within macros point at the invocation, with a label on the line of the macro body.
+    pub fn map_parser_error(&self, e: ParserError) -> ParserError {
+        let Some(origin) = (e.line_number as usize).checked_sub(1).and_then(|i| self.origins.get(i)) else { return e };
+        let (file, line, span, label) = locate(&self.files, origin, Some(e.span));
+
+        ParserError {
+            file,
+            line_number: line as i32 + 1,
+            span: span.unwrap_or_else(|| line_span(&self.files, origin.file, line)),
+            cause: e.cause,
+            labels: label.into_iter().chain(e.labels.into_iter().map(|l| self.map_label(l))).collect(),
+            notes: e.notes,
+        }
+    }
+
+    /// Points a runtime error on a line of preprocessed code back at the file and line it came from, like [Preprocessed::map_parser_error].
+    pub fn map_runtime_error(&self, e: AsmrRuntimeError) -> AsmrRuntimeError {
+        let Some(origin) = self.origins.get(e.line_number) else { return e };
+        let (file, line, span, label) = locate(&self.files, origin, e.span);
+
+        AsmrRuntimeError {
+            file,
+            line_number: line,
+            span,
+            cause: e.cause,
+            labels: label.into_iter().chain(e.labels.into_iter().map(|l| self.map_label(l))).collect(),
+            notes: e.notes,
+        }
+    }
+
+    fn map_label(&self, label: Label) -> Label {
+        match label.line_number.checked_sub(1).and_then(|i| self.origins.get(i)) {
+            Some(origin) => {
+                let (file, line, span, _) = locate(&self.files, origin, label.span);
+                Label::new(line + 1, span, label.message).in_file(file)
+            },
+            None => label,
+        }
+    }
+}
+
+/// Gets the file and line of `origin`, the span within it if `span` still points into it, and a label on the macro body line it was expanded from.
+fn locate(files: &[SourceFile], origin: &Origin, span: Option<Span>) -> (Option<String>, usize, Option<Span>, Option<Label>) {
+    let name = |file: usize| files.get(file).and_then(SourceFile::name);
+    let span = span.filter(|_| origin.exact);
+    match &origin.expansion {
+        Some(expansion) => {
+            let label = Label::new(expansion.line + 1, span, format!("expanded from macro `{}`", expansion.name)).in_file(name(expansion.file));
+            (name(origin.file), origin.line, None, Some(label))
+        },
+        None => (name(origin.file), origin.line, span, None),
+    }
+}
+
+/// Gets the span of a source line without surrounding whitespace and comments.
+fn line_span(files: &[SourceFile], file: usize, line: usize) -> Span {
+    text_span(files.get(file).and_then(|f| f.lines.get(line)).map_or("", |s| s.as_str()))
+}
+
+/// Gets the span of a line of text without surrounding whitespace and comments.
+fn text_span(text: &str) -> Span {
+    let lexemes = lex(text);
+    if lexemes.is_empty() { Span::default() } else { text_of(text, &lexemes).1 }
+}
+
+/// Preprocesses lines of asmr code, expanding NASM-style directives before they are parsed.
+///
+/// The supported directives are
+/// ```nasm
+/// %include "io.asmr"      ; Preprocesses a file in place, found relative to this file
+/// %define NAME value      ; Replaces the word NAME with value on later lines
+/// %undef NAME
+/// %macro name 2           ; Defines a macro taking 2 parameters, used as `name a, b`
+///     push %2             ; %1 to %N are its parameters, %0 is the number of parameters
+/// %%skip:                 ; %% labels are local to each expansion
+/// %endmacro
+/// %rep 4                  ; Repeats the lines up to %endrep 4 times
+/// %endrep
+/// %if SIZE & 1            ; Keeps the lines up to %else or %endif if the constant expression is not 0
+/// %ifdef NAME             ; Keeps the lines up to %else or %endif if NAME is defined
+/// %else
+/// %endif
+/// ```
+///
+/// # Errors
+/// Returns the first error in the directives. Use [preprocess_recovering] to get every error.
+///
+/// # Examples
+///
+/// ```
+/// # use assembl_really::{preprocess, parse_lines};
+/// let v = vec!["%define COUNT 3", "%rep COUNT", "inc eax", "%endrep"];
+/// let preprocessed = preprocess(v.iter()).unwrap();
+/// assert_eq!(preprocessed.lines, ["inc eax", "inc eax", "inc eax"]);
+/// assert_eq!(preprocessed.origins[1].line, 2);
+///
+/// let lines = parse_lines(preprocessed.lines.iter());
+/// ```
+pub fn preprocess<L, S>(lines: L) -> Result<Preprocessed, ParserError>
+where
+    L: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    let (preprocessed, errors) = preprocess_recovering(lines);
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(preprocessed),
+    }
+}
+
+/// Preprocesses lines of asmr code like [preprocess], but recovers from errors to report every error at once.
+/// Directives that fail are left out of the preprocessed code. The errors are ordered by line number.
+///
+/// As the lines are not read from a file, included files are found relative to the working directory.
+pub fn preprocess_recovering<L, S>(lines: L) -> (Preprocessed, Vec<ParserError>)
+where
+    L: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    let file = SourceFile { path: None, lines: lines.map(|l| l.as_ref().to_string()).collect() };
+    Preprocessor::default().run(file)
+}
+
+/// Reads and preprocesses an asmr file like [preprocess_recovering], including files relative to
+/// the including file or else relative to one of `include_paths`.
+/// The main file is the first of [Preprocessed::files].
+///
+/// # Errors
+/// Returns an error if the main file cannot be read. Errors in the code, including files that
+/// cannot be included, are returned alongside the preprocessed code.
+pub fn preprocess_file(path: impl AsRef<Path>, include_paths: &[PathBuf]) -> io::Result<(Preprocessed, Vec<ParserError>)> {
+    let path = path.as_ref();
+    let file = SourceFile { path: Some(path.to_path_buf()), lines: fs::read_to_string(path)?.lines().map(str::to_string).collect() };
+
+    let mut preprocessor = Preprocessor { include_paths, ..Default::default() };
+    preprocessor.includes.push(path.canonicalize()?);
+    Ok(preprocessor.run(file))
+}
+
+/// A line waiting to be preprocessed.
+#[derive(Clone, Debug)]
+struct SourceLine {
+    text: String,
+    origin: Origin,
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: usize,
+    body: Vec<SourceLine>,
+}
+
+/// Expands directives line by line, carrying the defines and macros seen so far.
+#[derive(Default)]
+struct Preprocessor<'a> {
+    /// The directories to search for included files after the directory of the including file.
+    include_paths: &'a [PathBuf],
+    /// The canonical paths of the files being included, to detect cycles.
+    includes: Vec<PathBuf>,
+    files: Vec<SourceFile>,
+    defines: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    /// Numbers each macro expansion, to give it its own local labels.
+    expansions: usize,
+    /// How many `%rep` iterations have been preprocessed, which along with `expansions` is limited by [MAX_REPETITIONS].
+    repetitions: usize,
+    /// Whether the code expanded past [MAX_EXPANDED_LINES] or [MAX_REPETITIONS], after which nothing more is expanded.
+    overflowed: bool,
+    lines: Vec<String>,
+    origins: Vec<Origin>,
+    errors: Vec<ParserError>,
+}
+
+impl<'a> Preprocessor<'a> {
+    /// Preprocesses `file` as the main file.
+    fn run(mut self, file: SourceFile) -> (Preprocessed, Vec<ParserError>) {
+        self.process_file(file, 0);
+
+        // Errors from included files are ordered after those of the main file
+        let Preprocessor { lines, origins, files, mut errors, .. } = self;
+        errors.sort_by_key(|e| (e.file != files[0].name(), e.file.clone(), e.line_number));
+        (Preprocessed { lines, origins, files }, errors)
+    }
+
+    /// Adds `file` to the files of the preprocessed code and preprocesses every line of it.
+    fn process_file(&mut self, file: SourceFile, depth: usize) {
+        let index = self.files.len();
+        let lines: Vec<_> = file.lines.iter().enumerate()
+            .map(|(i, text)| SourceLine { text: text.clone(), origin: Origin { file: index, line: i, expansion: None, exact: true } })
+            .collect();
+
+        self.files.push(file);
+        self.process(&lines, depth);
+    }
+
+    /// Creates an error on `line`, pointing at `span` within it where the line is unchanged from the source.
+    fn error(&self, line: &SourceLine, span: Span, cause: impl Into<String>) -> ParserError {
+        let (file, line_number, span, label) = locate(&self.files, &line.origin, Some(span));
+        let e = ParserError::from(line_number as i32 + 1, span.unwrap_or_else(|| line_span(&self.files, line.origin.file, line_number)), cause);
+        let e = ParserError { file, ..e };
+        match label {
+            Some(label) => e.with_label(label),
+            None => e,
+        }
+    }
+
+    /// Includes the file at `path`, found relative to the file of `line` or else one of the include paths.
+    fn include(&mut self, line: &SourceLine, path: &str, span: Span, depth: usize) {
+        let directory = self.files[line.origin.file].path.as_ref()
+            .and_then(|p| p.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let Some(resolved) = std::iter::once(&directory).chain(self.include_paths).map(|d| d.join(path)).find(|p| p.is_file()) else {
+            self.errors.push(self.error(line, span, format!("Could not find `{path}` relative to the including file or in the include paths."))
+                .with_note(Note::Help("add the directory containing it to the include paths with `-I`".to_string())));
+            return;
+        };
+
+        let canonical = resolved.canonicalize().unwrap_or_else(|_| resolved.clone());
+        if self.includes.contains(&canonical) {
+            let chain: Vec<_> = self.includes.iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+            self.errors.push(self.error(line, span, format!("Including `{path}` would create an include cycle.")).with_note(Note::Note(format!("the files include each other as {}", chain.join(" -> ")))));
+            return;
+        }
+
+        let contents = match fs::read_to_string(&resolved) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.errors.push(self.error(line, span, format!("Could not read `{}`: {e}.", resolved.display())));
+                return;
+            },
+        };
+
+        self.includes.push(canonical);
+        self.process_file(SourceFile { path: Some(resolved), lines: contents.lines().map(str::to_string).collect() }, depth);
+        self.includes.pop();
+    }
+
+    /// Preprocesses `lines`, which are `depth` macro expansions or `%rep` blocks deep, appending the result.
+    fn process(&mut self, lines: &[SourceLine], depth: usize) {
+        let mut i = 0;
+        while i < lines.len() {
+            let line = &lines[i];
+            i += 1;
+
+            let lexemes = lex(&line.text);
+            let Some((directive, args)) = directive_of(&lexemes) else {
+                self.process_code(line, depth);
+                continue;
+            };
+            let span = lexemes[0].span.to(directive.span);
+
+            match directive.text {
+                "include" => {
+                    let path = match args {
+                        [path] if path.kind == LexemeKind::String => match Token::parse(&line.text, args) {
+                            Ok(Token::String(bytes)) => String::from_utf8(bytes).ok(),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let Some(path) = path else {
+                        self.errors.push(self.error(line, span, "`%include` requires a quoted path, as in `%include \"io.asmr\"`."));
+                        continue;
+                    };
+
+                    self.include(line, &path, text_of(&line.text, args).1, depth);
+                },
+                "define" | "undef" => {
+                    let Some(name) = args.first().filter(|name| is_valid_identifier(name.text)) else {
+                        let span = args.first().map_or(span, |name| name.span);
+                        self.errors.push(self.error(line, span, format!("`%{}` requires a name, as in `%{} NAME`.", directive.text, directive.text)));
+                        continue;
+                    };
+
+                    if directive.text == "undef" {
+                        self.defines.remove(name.text);
+                    }
+                    else {
+                        let value = if args.len() > 1 { text_of(&line.text, &args[1..]).0 } else { "" };
+                        self.defines.insert(name.text.to_string(), value.to_string());
+                    }
+                },
+                "macro" => {
+                    let end = find_end(&lines[i..], &["macro"], "endmacro");
+                    let body = &lines[i..i + end.unwrap_or(lines.len() - i)];
+                    i += end.map_or(lines.len() - i, |end| end + 1);
+
+                    let definition = match args {
+                        [name, params] if is_valid_identifier(name.text) => params.text.parse::<usize>().ok().map(|params| (name.text, params)),
+                        _ => None,
+                    };
+                    let Some((name, params)) = definition else {
+                        let span = if args.is_empty() { span } else { text_of(&line.text, args).1 };
+                        self.errors.push(self.error(line, span, "Invalid macro definition syntax. Expected `%macro name N`, where N is the number of parameters."));
+                        continue;
+                    };
+
+                    if end.is_none() {
+                        self.errors.push(self.error(line, span, format!("`%macro {name}` is missing a matching `%endmacro`."))
+                            .with_note(Note::Help("add `%endmacro` after the last line of the macro".to_string())));
+                    }
+                    else if let Some(nested) = body.iter().find(|l| directive_name(&l.text).as_deref() == Some("macro")) {
+                        self.errors.push(self.error(nested, text_span(&nested.text), format!("Cannot define a macro inside `%macro {name}`.")));
+                    }
+                    else {
+                        self.macros.insert(name.to_string(), Macro { params, body: body.to_vec() });
+                    }
+                },
+                "rep" => {
+                    let Some(end) = find_end(&lines[i..], &["rep"], "endrep") else {
+                        self.errors.push(self.error(line, span, "`%rep` is missing a matching `%endrep`."));
+                        continue;
+                    };
+                    let body = &lines[i..i + end];
+                    i += end + 1;
+
+                    match self.evaluate(line, &lexemes, directive.text) {
+                        Some(count) if count < 0 => self.errors.push(self.error(line, text_of(&line.text, args).1, format!("`%rep` requires a count of at least 0 but found `{count}`."))),
+                        Some(_) if depth >= MAX_DEPTH => self.errors.push(self.error(line, span, format!("`%rep` blocks and macros cannot be nested more than {MAX_DEPTH} deep."))),
+                        Some(count) if count as usize > MAX_REPETITIONS => self.errors.push(self.error(line, text_of(&line.text, args).1, format!("`%rep` cannot repeat more than {MAX_REPETITIONS} times but found `{count}`."))),
+                        Some(count) => {
+                            // Nested blocks multiply their counts, so every iteration counts towards the limit
+                            for _ in 0..count {
+                                self.repetitions += 1;
+                                if self.overflow(line, || span) { break }
+                                self.process(body, depth + 1);
+                            }
+                        },
+                        None => {},
+                    }
+                },
+                "if" | "ifdef" => {
+                    let Some(end) = find_end(&lines[i..], &["if", "ifdef"], "endif") else {
+                        self.errors.push(self.error(line, span, format!("`%{}` is missing a matching `%endif`.", directive.text)));
+                        continue;
+                    };
+                    let block = &lines[i..i + end];
+                    i += end + 1;
+
+                    // Split the block at its `%else`, ignoring those of nested conditionals
+                    let (then, otherwise) = match find_end(block, &["if", "ifdef"], "else") {
+                        Some(split) => (&block[..split], &block[split + 1..]),
+                        None => (block, &[][..]),
+                    };
+
+                    let condition = match directive.text {
+                        "ifdef" => match args {
+                            [name] => Some(self.defines.contains_key(name.text)),
+                            _ => {
+                                self.errors.push(self.error(line, span, "`%ifdef` requires a name, as in `%ifdef NAME`."));
+                                None
+                            },
+                        },
+                        _ => self.evaluate(line, &lexemes, directive.text).map(|i| i != 0),
+                    };
+
+                    match condition {
+                        Some(true) => self.process(then, depth),
+                        Some(false) => self.process(otherwise, depth),
+                        None => {},
+                    }
+                },
+                "endmacro" | "endrep" | "endif" | "else" => {
+                    let opening = match directive.text {
+                        "endmacro" => "%macro",
+                        "endrep" => "%rep",
+                        _ => "%if",
+                    };
+                    self.errors.push(self.error(line, span, format!("`%{}` without a matching `{opening}`.", directive.text)));
+                },
+                other => self.errors.push(self.error(line, span, format!("Unknown preprocessor directive `%{other}`."))),
+            }
+        }
+    }
+
+    /// Checks whether the code has expanded past [MAX_EXPANDED_LINES] or [MAX_REPETITIONS], reporting it on `line` the first time.
+    fn overflow(&mut self, line: &SourceLine, span: impl FnOnce() -> Span) -> bool {
+        let cause = if self.lines.len() >= MAX_EXPANDED_LINES {
+            format!("Too many lines of expanded code. `%rep` blocks and macros can expand to at most {MAX_EXPANDED_LINES} lines.")
+        }
+        else if self.expansions + self.repetitions > MAX_REPETITIONS {
+            format!("Too many repetitions. `%rep` blocks and macros can repeat and expand at most {MAX_REPETITIONS} times in total.")
+        }
+        else {
+            return self.overflowed;
+        };
+
+        if !self.overflowed {
+            self.errors.push(self.error(line, span(), cause));
+            self.overflowed = true;
+        }
+        true
+    }
+
+    /// Substitutes defines into a line of code, expanding it if it invokes a macro.
+    fn process_code(&mut self, line: &SourceLine, depth: usize) {
+        if self.overflow(line, || text_span(&line.text)) { return }
+
+        let text = match self.substitute(&line.text) {
+            Ok(text) => text,
+            Err(cause) => {
+                self.errors.push(self.error(line, text_span(&line.text), cause));
+                return;
+            },
+        };
+        let line = SourceLine { origin: Origin { exact: line.origin.exact && text == line.text, ..line.origin.clone() }, text };
+
+        // Macros are invoked by name, like an instruction
+        let lexemes = lex(&line.text);
+        let Some((name, m)) = lexemes.first().and_then(|l| self.macros.get_key_value(l.text)) else {
+            self.lines.push(line.text);
+            self.origins.push(line.origin);
+            return;
+        };
+        let (name, params, body) = (name.clone(), m.params, m.body.clone());
+
+        let args: Vec<&str> = split_commas(&lexemes[1..]).into_iter()
+            .map(|arg| if arg.is_empty() { "" } else { text_of(&line.text, arg).0 })
+            .collect();
+        if args.len() != params {
+            let span = if args.is_empty() { lexemes[0].span } else { text_of(&line.text, &lexemes[1..]).1 };
+            self.errors.push(self.error(&line, span, format!("`{name}` takes {params} parameters but was given {}.", args.len())));
+            return;
+        }
+        else if depth >= MAX_DEPTH {
+            self.errors.push(self.error(&line, lexemes[0].span, format!("`%rep` blocks and macros cannot be nested more than {MAX_DEPTH} deep.")));
+            return;
+        }
+
+        self.expansions += 1;
+        if self.overflow(&line, || lexemes[0].span) { return }
+        let mut expanded = Vec::new();
+        for body_line in &body {
+            match expand_params(&body_line.text, &args, self.expansions) {
+                Ok(text) => {
+                    let expansion = Expansion { name: name.clone(), file: body_line.origin.file, line: body_line.origin.line };
+                    let exact = body_line.origin.exact && text == body_line.text;
+                    expanded.push(SourceLine { text, origin: Origin { file: line.origin.file, line: line.origin.line, expansion: Some(expansion), exact } });
+                },
+                Err(cause) => self.errors.push(self.error(&line, lexemes[0].span, cause)),
+            }
+        }
+
+        self.process(&expanded, depth + 1);
+    }
+
+    /// Replaces each defined word in `text` with its value, substituting defines within the values too.
+    fn substitute(&self, text: &str) -> Result<String, String> {
+        let mut substituted = String::new();
+        self.expand_defines(text, &mut Vec::new(), &mut 0, &mut substituted)?;
+        Ok(substituted)
+    }
+
+    /// Appends `text` to `out` with defines substituted, other than those in `expanding`.
+    /// Like NASM, a define is not substituted within its own value, so `%define A A + 1` expands `A` to `A + 1`.
+    fn expand_defines<'s>(&'s self, text: &str, expanding: &mut Vec<&'s str>, substitutions: &mut usize, out: &mut String) -> Result<(), String> {
+        let too_long = || format!("Substituting defines makes this line too long. Lines can expand to at most {MAX_LINE_LENGTH} bytes.");
+
+        let mut end = 0;
+        for l in lex(text) {
+            let Some((name, value)) = self.defines.get_key_value(l.text).filter(|(name, _)| l.kind == LexemeKind::Word && !expanding.contains(&name.as_str())) else { continue };
+            if expanding.len() >= MAX_DEPTH {
+                return Err(format!("Too many nested `%define` substitutions. Defines can refer to each other at most {MAX_DEPTH} deep."));
+            }
+
+            // Defines that expand to nothing do not lengthen the line, so the number of substitutions is limited as well
+            *substitutions += 1;
+            if *substitutions > MAX_LINE_LENGTH { return Err(too_long()) }
+
+            out.push_str(&text[end..l.span.start]);
+            expanding.push(name);
+            self.expand_defines(value, expanding, substitutions, out)?;
+            expanding.pop();
+            end = l.span.end;
+
+            if out.len() > MAX_LINE_LENGTH { return Err(too_long()) }
+        }
+
+        out.push_str(&text[end..]);
+        if out.len() > MAX_LINE_LENGTH { return Err(too_long()) }
+        Ok(())
+    }
+
+    /// Evaluates the constant expression after a directive such as `%if`, with defines substituted.
+    fn evaluate(&mut self, line: &SourceLine, lexemes: &[Lexeme], directive: &str) -> Option<i32> {
+        let span = lexemes[0].span.to(lexemes[1].span);
+        if lexemes.len() < 3 {
+            self.errors.push(self.error(line, span, format!("`%{directive}` requires a constant expression.")));
+            return None;
+        }
+
+        let (text, span) = text_of(&line.text, &lexemes[2..]);
+        let result = self.substitute(text).and_then(|text| {
+            let lexemes = lex(&text);
+            if lexemes.is_empty() {
+                return Err(format!("`%{directive}` requires a constant expression."));
+            }
+
+            Expression::parse(&text, &lexemes)?.constant(&HashMap::new())?
+                .ok_or_else(|| format!("Could not evaluate `{text}`. Expressions in `%{directive}` can only use numbers and defined values."))
+        });
+
+        result.map_err(|cause| self.errors.push(self.error(line, span, cause))).ok()
+    }
+}
+
+/// Splits a directive line such as `%define SIZE 4` into the directive name and its arguments.
+fn directive_of<'a, 'b>(lexemes: &'b [Lexeme<'a>]) -> Option<(&'b Lexeme<'a>, &'b [Lexeme<'a>])> {
+    match lexemes {
+        [percent, directive, args @ ..] if percent.kind == LexemeKind::Percent && directive.kind == LexemeKind::Word && percent.span.end == directive.span.start => Some((directive, args)),
+        _ => None,
+    }
+}
+
+fn directive_name(text: &str) -> Option<String> {
+    directive_of(&lex(text)).map(|(directive, _)| directive.text.to_string())
+}
+
+/// Finds the line that closes a block, skipping blocks opened by `open` nested within it.
+fn find_end(lines: &[SourceLine], open: &[&str], close: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate() {
+        match directive_name(&line.text) {
+            Some(name) if open.contains(&name.as_str()) => depth += 1,
+            Some(name) if name == close && depth == 0 => return Some(i),
+            Some(name) if depth > 0 && open.iter().any(|o| closes(o) == name) => depth -= 1,
+            _ => {},
+        }
+    }
+
+    None
+}
+
+/// Gets the directive that closes a block opened by `open`.
+fn closes(open: &str) -> &'static str {
+    match open {
+        "macro" => "endmacro",
+        "rep" => "endrep",
+        _ => "endif",
+    }
+}
+
+/// Replaces the parameters of a macro body line, where `%1` is the first argument, `%0` is the number of arguments
+/// and `%%name` is a label local to the expansion numbered `expansion`. Strings and comments are left as they are.
+fn expand_params(text: &str, args: &[&str], expansion: usize) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.char_indices().peekable();
+    let mut quote = None;
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                out.push(c);
+                if let Some((_, escaped)) = chars.next() { out.push(escaped) }
+                continue;
+            },
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, ';') => {
+                out.push_str(&text[i..]);
+                break;
+            },
+            (None, '%') if chars.next_if(|(_, next)| *next == '%').is_some() => {
+                out.push_str(&format!("..{expansion}."));
+                continue;
+            },
+            (None, '%') if chars.peek().is_some_and(|(_, next)| next.is_ascii_digit()) => {
+                let mut n = String::new();
+                while let Some((_, digit)) = chars.next_if(|(_, next)| next.is_ascii_digit()) {
+                    n.push(digit);
+                }
+
+                match n.parse::<usize>() {
+                    Ok(0) => out.push_str(&args.len().to_string()),
+                    Ok(n) if n <= args.len() => out.push_str(args[n - 1]),
+                    _ => return Err(format!("`%{n}` is out of range. The macro takes {} parameters.", args.len())),
+                }
+                continue;
+            },
+            _ => {},
+        }
+        out.push(c);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_lines, execute};
+
+    fn preprocess_str(source: &[&str]) -> Result<Vec<String>, ParserError> {
+        preprocess(source.iter()).map(|p| p.lines)
+    }
+
+    /// Gets the span of the bytes `start..end` of an ASCII line.
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end, column: start, column_end: end }
+    }
+
+    /// Writes `files` into a new temporary directory for `test`, returning the directory.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("asmr-{test}-{}", std::process::id()));
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn defines_substitute_whole_words() -> Result<(), ParserError> {
+        let lines = preprocess_str(&[
+            "%define SIZE 4",
+            "%define DOUBLE SIZE * 2",
+            "mov eax, DOUBLE ; SIZE",
+            "mov ebx, SIZES",
+            "%undef SIZE",
+            "push \"SIZE\", SIZE",
+        ])?;
+
+        assert_eq!(lines, ["mov eax, 4 * 2 ; SIZE", "mov ebx, SIZES", "push \"SIZE\", SIZE"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn defines_are_not_substituted_within_themselves() {
+        let mut source = vec!["%define A A A", "%define B C + 1", "%define C B * 2", "mov eax, A", "mov eax, B"];
+        let doubling: Vec<_> = (0..20).map(|i| format!("%define D{i} D{} D{}", i + 1, i + 1)).collect();
+        let nested: Vec<_> = (0..=MAX_DEPTH).map(|i| format!("%define N{i} N{}", i + 1)).collect();
+        source.extend(doubling.iter().chain(&nested).map(String::as_str));
+        source.extend(["mov eax, D0", "mov eax, N0"]);
+
+        let (preprocessed, errors) = preprocess_recovering(source.iter());
+        let line = source.len() as i32;
+
+        assert_eq!(preprocessed.lines, ["mov eax, A A", "mov eax, B * 2 + 1"]);
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [
+            (line - 1, "Substituting defines makes this line too long. Lines can expand to at most 65536 bytes."),
+            (line, "Too many nested `%define` substitutions. Defines can refer to each other at most 64 deep."),
+        ]);
+    }
+
+    #[test]
+    fn macros_expand_params_and_local_labels() -> Result<(), ParserError> {
+        let lines = preprocess_str(&[
+            "%macro print 1",
+            "    push %1",
+            "    call asmr::io::print ; %1",
+            "%endmacro",
+            "%macro countdown 2",
+            "    mov %1, %2",
+            "%%loop:",
+            "    dec %1",
+            "    jnz %%loop ; %0 params",
+            "%endmacro",
+            "print msg",
+            "countdown ecx, 3",
+            "countdown edx, 1",
+        ])?;
+
+        assert_eq!(lines, [
+            "    push msg",
+            "    call asmr::io::print ; %1",
+            "    mov ecx, 3",
+            "..2.loop:",
+            "    dec ecx",
+            "    jnz ..2.loop ; %0 params",
+            "    mov edx, 1",
+            "..3.loop:",
+            "    dec edx",
+            "    jnz ..3.loop ; %0 params",
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rep_and_conditionals_select_lines() -> Result<(), ParserError> {
+        let lines = preprocess_str(&[
+            "%define DEBUG",
+            "%define N 2",
+            "%rep N + 1",
+            "    %if N / 2",
+            "        inc eax",
+            "    %else",
+            "        dec eax",
+            "    %endif",
+            "%endrep",
+            "%ifdef DEBUG",
+            "    nop",
+            "%endif",
+            "%ifdef RELEASE",
+            "    %rep 4",
+            "    hlt",
+            "    %endrep",
+            "%else",
+            "    ret",
+            "%endif",
+        ])?;
+
+        assert_eq!(lines, ["        inc eax", "        inc eax", "        inc eax", "    nop", "    ret"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn preprocessed_lines_map_to_their_source() -> Result<(), ParserError> {
+        let preprocessed = preprocess([
+            "%macro twice 1",
+            "    %1",
+            "    %1",
+            "%endmacro",
+            "twice nop",
+            "mov eax, 1",
+        ].iter())?;
+
+        assert_eq!(preprocessed.origins, [
+            Origin { file: 0, line: 4, expansion: Some(Expansion { name: "twice".to_string(), file: 0, line: 1 }), exact: false },
+            Origin { file: 0, line: 4, expansion: Some(Expansion { name: "twice".to_string(), file: 0, line: 2 }), exact: false },
+            Origin { file: 0, line: 5, expansion: None, exact: true },
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_point_at_the_source_line() -> Result<(), ParserError> {
+        let source = [
+            "%macro load 1",
+            "    mov eax, %1",
+            "    mov ebx, [eax]",
+            "%endmacro",
+            "%define BAD #",
+            "load BAD",
+            "push BAD",
+            "load 0",
+        ];
+        let preprocessed = preprocess(source.iter())?;
+
+        // Parser errors in macros point at the invocation, with a label on the line of the macro
+        let e = parse_lines(preprocessed.lines.iter()).unwrap_err();
+        assert_eq!(preprocessed.map_parser_error(e), ParserError::from(6, span(0, 8), "Could not parse token `#`. Was this meant to be an identifier? Identifiers must be strictly [a-z, A-Z, 0-9, _, ., :] and must start with [a-z, A-Z. _, .].")
+            .with_label(Label::new(2, None, "expanded from macro `load`")));
+
+        // Runtime errors on unchanged lines keep their span
+        let preprocessed = preprocess(source.iter().filter(|l| !l.contains("BAD")))?;
+        let e = execute(parse_lines(preprocessed.lines.iter()).unwrap()).unwrap_err();
+        let e = preprocessed.map_runtime_error(e);
+        assert_eq!((e.line_number, e.span, e.labels), (4, None, vec![Label::new(3, Some(span(13, 18)), "expanded from macro `load`")]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn directives_err_on_invalid_syntax() {
+        let (preprocessed, errors) = preprocess_recovering([
+            "%macro m",
+            "%endmacro",
+            "%macro two 2",
+            "    push %1, %2",
+            "%endmacro",
+            "two 1",
+            "%rep x",
+            "%endrep",
+            "%endif",
+            "%endmacro",
+            "%bogus",
+            "nop",
+            "%if 1",
+        ].iter());
+
+        assert_eq!(preprocessed.lines, ["nop"]);
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [
+            (1, "Invalid macro definition syntax. Expected `%macro name N`, where N is the number of parameters."),
+            (6, "`two` takes 2 parameters but was given 1."),
+            (7, "Could not evaluate `x`. Expressions in `%rep` can only use numbers and defined values."),
+            (9, "`%endif` without a matching `%if`."),
+            (10, "`%endmacro` without a matching `%macro`."),
+            (11, "Unknown preprocessor directive `%bogus`."),
+            (13, "`%if` is missing a matching `%endif`."),
+        ]);
+    }
+
+    #[test]
+    fn unclosed_blocks_err_and_keep_preprocessing() {
+        let (preprocessed, errors) = preprocess_recovering([
+            "%rep 2",
+            "nop",
+            "%undef",
+            "%if 1",
+            "mov eax, 1",
+        ].iter());
+
+        assert_eq!(preprocessed.lines, ["nop", "mov eax, 1"]);
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [
+            (1, "`%rep` is missing a matching `%endrep`."),
+            (3, "`%undef` requires a name, as in `%undef NAME`."),
+            (4, "`%if` is missing a matching `%endif`."),
+        ]);
+
+        let (preprocessed, errors) = preprocess_recovering(["%rep 2", "nop", "mov eax, #1"].iter());
+        let (_, parse_errors) = crate::parse_lines_recovering(preprocessed.lines.iter());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(parse_errors.into_iter().map(|e| preprocessed.map_parser_error(e).line_number).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn expansion_errs_past_its_limits() {
+        let (preprocessed, errors) = preprocess_recovering([
+            "%rep 2000000000",
+            "nop",
+            "%endrep",
+            "%rep 1000",
+            "%rep 1000",
+            "nop",
+            "nop",
+            "%endrep",
+            "%endrep",
+        ].iter());
+
+        assert_eq!(preprocessed.lines.len(), MAX_EXPANDED_LINES);
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [
+            (1, "`%rep` cannot repeat more than 1000000 times but found `2000000000`."),
+            (4, "Too many lines of expanded code. `%rep` blocks and macros can expand to at most 1000000 lines."),
+        ]);
+
+        // Blocks that expand to no lines are limited by their number of repetitions
+        let repetitions = "Too many repetitions. `%rep` blocks and macros can repeat and expand at most 1000000 times in total.";
+        let (preprocessed, errors) = preprocess_recovering(["%rep 1000000", "%rep 1000000", "%endrep", "%endrep", "%rep 2", "%define X 1", "%endrep"].iter());
+        assert!(preprocessed.lines.is_empty());
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [(2, repetitions)]);
+
+        let (preprocessed, errors) = preprocess_recovering(["%macro m 0", "%define X 1", "%endmacro", "%rep 1000000", "m", "m", "%endrep"].iter());
+        assert!(preprocessed.lines.is_empty());
+        assert_eq!(errors.iter().map(|e| (e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [(5, repetitions)]);
+    }
+
+    #[test]
+    fn includes_resolve_relative_to_the_including_file_and_include_paths() -> io::Result<()> {
+        let dir = write_files("includes-resolve", &[
+            ("main.asmr", "%include \"lib/io.asmr\"\nprint msg\nmov eax, NEWLINE"),
+            ("lib/io.asmr", "%include 'macros.asmr'"),
+            ("lib/macros.asmr", "%macro print 1\n    push %1\n    call asmr::io::print\n%endmacro\n%include \"consts.asmr\""),
+            ("shared/consts.asmr", "%define NEWLINE 10"),
+        ]);
+        let (preprocessed, errors) = preprocess_file(dir.join("main.asmr"), &[dir.join("shared")])?;
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(errors, []);
+        assert_eq!(preprocessed.lines, ["    push msg", "    call asmr::io::print", "mov eax, 10"]);
+        assert_eq!(preprocessed.files.iter().map(|f| f.path.clone().unwrap()).collect::<Vec<_>>(), [
+            dir.join("main.asmr"),
+            dir.join("lib/io.asmr"),
+            dir.join("lib/macros.asmr"),
+            dir.join("shared/consts.asmr"),
+        ]);
+        assert_eq!(preprocessed.origins[0], Origin { file: 0, line: 1, expansion: Some(Expansion { name: "print".to_string(), file: 2, line: 1 }), exact: false });
+
+        Ok(())
+    }
+
+    #[test]
+    fn includes_err_on_cycles_and_missing_files() -> io::Result<()> {
+        let dir = write_files("includes-err", &[
+            ("a.asmr", "%include \"b.asmr\"\n%include \"missing.asmr\"\n%include b.asmr"),
+            ("b.asmr", "nop\n%include \"a.asmr\""),
+        ]);
+        let (preprocessed, errors) = preprocess_file(dir.join("a.asmr"), &[])?;
+        fs::remove_dir_all(&dir)?;
+
+        let b = Some(dir.join("b.asmr").display().to_string());
+        assert_eq!(preprocessed.lines, ["nop"]);
+        assert_eq!(errors.iter().map(|e| (e.file == b, e.line_number, e.cause.as_str())).collect::<Vec<_>>(), [
+            (false, 2, "Could not find `missing.asmr` relative to the including file or in the include paths."),
+            (false, 3, "`%include` requires a quoted path, as in `%include \"io.asmr\"`."),
+            (true, 2, "Including `a.asmr` would create an include cycle."),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_point_at_the_included_file() -> io::Result<()> {
+        let dir = write_files("includes-runtime", &[
+            ("main.asmr", "call fail\njmp end\n%include \"helper.asmr\"\nend:"),
+            ("helper.asmr", "fail:\n    mov eax, [0]\n    ret"),
+        ]);
+        let (preprocessed, _) = preprocess_file(dir.join("main.asmr"), &[])?;
+        fs::remove_dir_all(&dir)?;
+
+        let e = execute(parse_lines(preprocessed.lines.iter()).unwrap()).unwrap_err();
+        let e = preprocessed.map_runtime_error(e);
+        assert_eq!((e.file, e.line_number, e.span), (Some(dir.join("helper.asmr").display().to_string()), 1, Some(span(13, 16))));
+
+        Ok(())
+    }
+}